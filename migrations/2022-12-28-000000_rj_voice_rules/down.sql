@@ -0,0 +1,12 @@
+CREATE TEMPORARY TABLE rj_user_settings_backup(id, scripts, enable_by_default, tts_people);
+INSERT INTO rj_user_settings_backup SELECT id, scripts, enable_by_default, tts_people FROM rj_user_settings;
+DROP TABLE rj_user_settings;
+CREATE TABLE rj_user_settings (
+	id INTEGER PRIMARY KEY NOT NULL CHECK(id = 0),
+	scripts TEXT,
+	enable_by_default INTEGER,
+	tts_people TEXT,
+	UNIQUE(id) ON CONFLICT REPLACE
+);
+INSERT INTO rj_user_settings SELECT * FROM rj_user_settings_backup;
+DROP TABLE rj_user_settings_backup;
@@ -0,0 +1 @@
+ALTER TABLE rj_user_settings ADD COLUMN voice_rules TEXT NOT NULL DEFAULT '[]';
@@ -0,0 +1,12 @@
+CREATE TEMPORARY TABLE rj_admin_settings_backup(id, tts_service_url, tts_text_param_key, tts_enable_ssml);
+INSERT INTO rj_admin_settings_backup SELECT id, tts_service_url, tts_text_param_key, tts_enable_ssml FROM rj_admin_settings;
+DROP TABLE rj_admin_settings;
+CREATE TABLE rj_admin_settings (
+	id INTEGER PRIMARY KEY NOT NULL CHECK(id = 0),
+	tts_service_url TEXT,
+	tts_text_param_key TEXT,
+	tts_enable_ssml INTEGER,
+	UNIQUE(id) ON CONFLICT REPLACE
+);
+INSERT INTO rj_admin_settings SELECT * FROM rj_admin_settings_backup;
+DROP TABLE rj_admin_settings_backup;
@@ -0,0 +1 @@
+ALTER TABLE rj_admin_settings ADD COLUMN tts_headers TEXT NOT NULL DEFAULT '{}';
@@ -0,0 +1,2 @@
+ALTER TABLE rj_admin_settings ADD COLUMN allowed_voice_models TEXT NOT NULL DEFAULT '[]';
+ALTER TABLE rj_admin_settings ADD COLUMN allowed_languages TEXT NOT NULL DEFAULT '[]';
@@ -0,0 +1 @@
+ALTER TABLE rj_user_settings ADD COLUMN default_host TEXT;
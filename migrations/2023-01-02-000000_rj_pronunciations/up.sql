@@ -0,0 +1 @@
+ALTER TABLE rj_admin_settings ADD COLUMN pronunciations TEXT NOT NULL DEFAULT '{}';
@@ -0,0 +1 @@
+ALTER TABLE users ADD COLUMN rj_enabled INTEGER;
@@ -0,0 +1 @@
+ALTER TABLE misc_settings ADD COLUMN index_insert_buffer_size INTEGER;
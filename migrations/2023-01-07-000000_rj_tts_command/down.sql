@@ -0,0 +1,19 @@
+CREATE TEMPORARY TABLE rj_admin_settings_backup(id, tts_service_url, tts_text_param_key, tts_enable_ssml, tts_headers, tts_fallback_path, allowed_voice_models, allowed_languages, pronunciations, natural_pause, tts_content_type);
+INSERT INTO rj_admin_settings_backup SELECT id, tts_service_url, tts_text_param_key, tts_enable_ssml, tts_headers, tts_fallback_path, allowed_voice_models, allowed_languages, pronunciations, natural_pause, tts_content_type FROM rj_admin_settings;
+DROP TABLE rj_admin_settings;
+CREATE TABLE rj_admin_settings (
+	id INTEGER PRIMARY KEY NOT NULL CHECK(id = 0),
+	tts_service_url TEXT,
+	tts_text_param_key TEXT,
+	tts_enable_ssml INTEGER,
+	tts_headers TEXT NOT NULL DEFAULT '{}',
+	tts_fallback_path TEXT,
+	allowed_voice_models TEXT NOT NULL DEFAULT '[]',
+	allowed_languages TEXT NOT NULL DEFAULT '[]',
+	pronunciations TEXT NOT NULL DEFAULT '{}',
+	natural_pause TEXT,
+	tts_content_type TEXT,
+	UNIQUE(id) ON CONFLICT REPLACE
+);
+INSERT INTO rj_admin_settings SELECT * FROM rj_admin_settings_backup;
+DROP TABLE rj_admin_settings_backup;
@@ -0,0 +1,3 @@
+ALTER TABLE rj_admin_settings ADD COLUMN tts_backend TEXT NOT NULL DEFAULT '"http"';
+ALTER TABLE rj_admin_settings ADD COLUMN tts_command TEXT;
+ALTER TABLE rj_admin_settings ADD COLUMN tts_command_args TEXT NOT NULL DEFAULT '[]';
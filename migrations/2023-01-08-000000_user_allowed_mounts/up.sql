@@ -0,0 +1 @@
+ALTER TABLE users ADD COLUMN allowed_mounts TEXT NOT NULL DEFAULT '[]';
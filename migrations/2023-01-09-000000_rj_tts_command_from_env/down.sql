@@ -0,0 +1,2 @@
+ALTER TABLE rj_admin_settings ADD COLUMN tts_command TEXT;
+ALTER TABLE rj_admin_settings ADD COLUMN tts_command_args TEXT NOT NULL DEFAULT '[]';
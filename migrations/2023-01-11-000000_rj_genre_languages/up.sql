@@ -0,0 +1 @@
+ALTER TABLE rj_admin_settings ADD COLUMN genre_languages TEXT NOT NULL DEFAULT '{}';
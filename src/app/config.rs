@@ -121,6 +121,7 @@ mod test {
 			settings: Some(settings::NewSettings {
 				album_art_pattern: Some("🖼️\\.jpg".into()),
 				reindex_every_n_seconds: Some(100),
+				index_insert_buffer_size: Some(100),
 			}),
 			..Default::default()
 		};
@@ -136,6 +137,10 @@ mod test {
 			settings.index_sleep_duration_seconds,
 			new_settings.reindex_every_n_seconds.unwrap()
 		);
+		assert_eq!(
+			settings.index_insert_buffer_size,
+			new_settings.index_insert_buffer_size
+		);
 	}
 
 	#[test]
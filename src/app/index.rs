@@ -1,4 +1,5 @@
 use log::error;
+use std::sync::atomic::Ordering;
 use std::sync::{Arc, Condvar, Mutex, RwLock};
 use std::time::Duration;
 
@@ -22,6 +23,8 @@ pub struct Index {
 	vfs_manager: vfs::Manager,
 	settings_manager: settings::Manager,
 	pending_reindex: Arc<(Mutex<bool>, Condvar)>,
+	update_progress: Arc<Progress>,
+	update_cancellation: Arc<Cancellation>,
 	pub rj_manager: Arc<RwLock<rj::Manager>>,
 }
 
@@ -42,6 +45,8 @@ impl Index {
 				Mutex::new(false),
 				Condvar::new(),
 			)),
+			update_progress: Arc::new(Progress::default()),
+			update_cancellation: Arc::new(Cancellation::default()),
 			rj_manager: Arc::new(RwLock::new(rj_manager)),
 		};
 
@@ -60,6 +65,17 @@ impl Index {
 		cvar.notify_one();
 	}
 
+	pub fn get_update_progress(&self) -> IndexProgress {
+		IndexProgress {
+			directories: self.update_progress.directories.load(Ordering::Relaxed),
+			songs: self.update_progress.songs.load(Ordering::Relaxed),
+		}
+	}
+
+	pub fn cancel_update(&self, discard_pending: bool) {
+		self.update_cancellation.cancel(discard_pending);
+	}
+
 	pub fn begin_periodic_updates(&self) {
 		let auto_index = self.clone();
 		std::thread::spawn(move || {
@@ -77,8 +93,9 @@ impl Index {
 				}
 				*pending = false;
 			}
-			if let Err(e) = self.update() {
-				error!("Error while updating index: {}", e);
+			match self.update() {
+				Ok(()) | Err(Error::Cancelled) => (),
+				Err(e) => error!("Error while updating index: {}", e),
 			}
 		}
 	}
@@ -204,6 +204,7 @@ fn read_ape(path: &Path) -> Result<SongTags, Error> {
 	let composer = tag.item("COMPOSER").and_then(read_ape_string);
 	let genre = tag.item("GENRE").and_then(read_ape_string);
 	let label = tag.item("PUBLISHER").and_then(read_ape_string);
+	let has_artwork = tag.item("Cover Art (Front)").is_some();
 	Ok(SongTags {
 		artist,
 		album_artist,
@@ -213,7 +214,7 @@ fn read_ape(path: &Path) -> Result<SongTags, Error> {
 		disc_number,
 		track_number,
 		year,
-		has_artwork: false,
+		has_artwork,
 		lyricist,
 		composer,
 		genre,
@@ -255,6 +256,7 @@ fn read_vorbis(path: &Path) -> Result<SongTags, Error> {
 				"COMPOSER" => tags.composer = Some(value),
 				"GENRE" => tags.genre = Some(value),
 				"PUBLISHER" => tags.label = Some(value),
+				"METADATA_BLOCK_PICTURE" => tags.has_artwork = true,
 				_ => (),
 			}
 		}
@@ -296,6 +298,7 @@ fn read_opus(path: &Path) -> Result<SongTags, Error> {
 				"COMPOSER" => tags.composer = Some(value),
 				"GENRE" => tags.genre = Some(value),
 				"PUBLISHER" => tags.label = Some(value),
+				"METADATA_BLOCK_PICTURE" => tags.has_artwork = true,
 				_ => (),
 			}
 		}
@@ -428,6 +431,11 @@ fn reads_embedded_artwork() {
 			.unwrap()
 			.has_artwork
 	);
+	assert!(
+		read(Path::new("test-data/artwork/sample.ape"))
+			.unwrap()
+			.has_artwork
+	);
 	assert!(
 		read(Path::new("test-data/artwork/sample.mp3"))
 			.unwrap()
@@ -448,4 +456,14 @@ fn reads_embedded_artwork() {
 			.unwrap()
 			.has_artwork
 	);
+	assert!(
+		read(Path::new("test-data/artwork/sample.ogg"))
+			.unwrap()
+			.has_artwork
+	);
+	assert!(
+		read(Path::new("test-data/artwork/sample.opus"))
+			.unwrap()
+			.has_artwork
+	);
 }
@@ -1,23 +1,75 @@
-use diesel::dsl::sql;
+use diesel::dsl::{not, sql};
 use diesel::prelude::*;
 use diesel::sql_types;
+use diesel::sqlite::Sqlite;
+use lazy_static::lazy_static;
 use regex::Regex;
 use std::ops::Range;
 use std::path::{Path, PathBuf};
 
 use super::*;
 use crate::db::{self, directories, songs};
+use crate::utils::{fold_accents, fuzzy_score};
+
+lazy_static! {
+	// Quoted token values. Quotes elsewhere, like in "don't", are left alone.
+	static ref QUOTED_VALUE: Regex = Regex::new(r#":("[^"]*"|'[^']*')"#).unwrap();
+}
+
+fn quoted_values(query: &str) -> Vec<Range<usize>> {
+	QUOTED_VALUE
+		.captures_iter(query)
+		.map(|c| c.get(1).unwrap().range())
+		.collect()
+}
+
+// Returns the byte offsets of `substr` in `query` that are not within quoted values.
+fn find_unquoted(query: &str, substr: &str) -> Vec<usize> {
+	let quoted = quoted_values(query);
+	query
+		.match_indices(substr)
+		.map(|(i, _)| i)
+		.filter(|i| !quoted.iter().any(|q| q.contains(i)))
+		.collect()
+}
+
+// Collapses runs of whitespace into a single space, except within quoted values.
+fn collapse_whitespace(query: &str) -> String {
+	let re = Regex::new(r"\s+").unwrap();
+	let mut collapsed = String::new();
+	let mut end = 0;
+	for quoted in quoted_values(query) {
+		collapsed.push_str(&re.replace_all(&query[end..quoted.start], " "));
+		collapsed.push_str(&query[quoted.clone()]);
+		end = quoted.end;
+	}
+	collapsed.push_str(&re.replace_all(&query[end..], " "));
+	collapsed.trim().to_string()
+}
+
+// Drops the quotes around a token value. Quotes within the value are kept.
+fn unquote(value: &str) -> &str {
+	for quote in ['"', '\''] {
+		if let Some(inner) = value
+			.strip_prefix(quote)
+			.and_then(|v| v.strip_suffix(quote))
+		{
+			return inner;
+		}
+	}
+	value
+}
 
 // A token is one of the field of song structure followed by ':' and a word or words within a
-// single or double quotes.
-// query should contain only one occurrence of token.
+// single or double quotes. Quoted words are kept as they are, colons included.
+// query should contain only one occurrence of token outside of quotes.
 // Ex. composer:"Some Composer" and lyricist:lyricist_name
 fn parse_token(query: &str, token: &str) -> (Option<String>, String) {
 	let mut substr = token.to_string();
 	substr.push(':');
-	let count = query.matches(&substr).count();
+	let positions = find_unquoted(query, &substr);
 
-	if count == 0 || count > 1 {
+	if positions.len() != 1 {
 		return (None, query.to_string());
 	}
 
@@ -25,24 +77,18 @@ fn parse_token(query: &str, token: &str) -> (Option<String>, String) {
 	// 1 'artist:artist_name generic_query'
 	// 2 'generic_query artist:artist_name'
 	// 3 'generic_query artist:artist_name generic_query'
-	// In case of 2 and 3 we will have more than one string after split.
-	let mut splits: Vec<&str> = query.split(&substr).collect();
-	let mut query: String = "".to_string();
-	if splits.len() > 1 {
-		query.push_str(splits.remove(0).trim());
-	}
-	let re = Regex::new(r#""([^"]+)"|'([^']+)'|^([\w\-]+)"#).unwrap();
-	let t = match re.find(splits[0]) {
+	// In case of 2 and 3 there is a generic query before the token.
+	let after = &query[positions[0] + substr.len()..];
+	let mut query: String = query[..positions[0]].trim().to_string();
+	let re = Regex::new(r#""([^"]+)"|'([^']+)'|^([\w\-<>=]+)"#).unwrap();
+	let t = match re.find(after) {
 		Some(x) => x,
 		None => {
 			return (None, query);
 		}
 	};
-	let artist = "%".to_string()
-		+ splits[0][t.start()..t.end()]
-			.replace(['\'', '"'], "")
-			.trim() + "%";
-	let rest = splits[0][t.end()..].trim();
+	let artist = "%".to_string() + unquote(t.as_str()).trim() + "%";
+	let rest = after[t.end()..].trim();
 
 	if !rest.is_empty() {
 		if query.is_empty() {
@@ -60,25 +106,62 @@ fn parse_token(query: &str, token: &str) -> (Option<String>, String) {
 	(None, query)
 }
 
-fn parse_year(query: &str, token: &str) -> (Option<Range<i32>>, String) {
-	let (raw_years, ret) = parse_token(query, token);
-
-	println!("{:?}", raw_years);
-
-	let raw_years = match raw_years {
-		Some(x) => x.replace('%', ""),
+// Like parse_token but also accepts several occurrences of the token joined by 'or'.
+// All the values of the group are returned, any one of which may match.
+// Ex. artist:miles or artist:"john coltrane"
+fn parse_token_any(query: &str, token: &str) -> (Vec<String>, String) {
+	let value = r#""[^"]+"|'[^']+'|[\w\-<>=]+"#;
+	let escaped = regex::escape(token);
+	let group = Regex::new(&format!(
+		r"(?:^|\s)({escaped}:(?:{value})(?:\s+or\s+{escaped}:(?:{value}))+)(?:\s|$)"
+	))
+	.unwrap();
+	let group = match group.captures(query).and_then(|c| c.get(1)) {
+		Some(x) => x,
 		None => {
-			return (None, ret);
+			let (value, query) = parse_token(query, token);
+			return (value.into_iter().collect(), query);
 		}
 	};
+
+	// Every occurrence of the token has to be part of the group
+	let substr = format!("{token}:");
+	if find_unquoted(group.as_str(), &substr).len() != find_unquoted(query, &substr).len() {
+		return (Vec::new(), query.to_string());
+	}
+
+	let values = Regex::new(&format!("{escaped}:({value})"))
+		.unwrap()
+		.captures_iter(group.as_str())
+		.map(|c| "%".to_string() + unquote(&c[1]).trim() + "%")
+		.collect();
+	let rest = [query[..group.start()].trim(), query[group.end()..].trim()]
+		.iter()
+		.filter(|s| !s.is_empty())
+		.copied()
+		.collect::<Vec<_>>()
+		.join(" ");
+	(values, rest)
+}
+
+// Parses a token with a single number or an inclusive range of numbers.
+// Ex. year:1998 or duration:180-240
+fn parse_range(query: &str, token: &str) -> (Option<Range<i32>>, String) {
+	let (raw_years, ret) = parse_token(query, token);
+	match raw_years {
+		Some(x) => (range_from_str(&x.replace('%', "")), ret),
+		None => (None, ret),
+	}
+}
+
+fn range_from_str(raw_years: &str) -> Option<Range<i32>> {
 	let hyphen_count = raw_years.matches('-').count();
 
 	if hyphen_count > 1 {
-		return (None, ret);
+		return None;
 	}
 
 	let string_years: Vec<&str> = raw_years.split('-').collect();
-	println!("{:?}", string_years);
 	let start = string_years[0].parse::<i32>();
 	let mut end = Ok(0);
 	if hyphen_count == 0 {
@@ -89,14 +172,50 @@ fn parse_year(query: &str, token: &str) -> (Option<Range<i32>>, String) {
 		end = string_years[1].parse::<i32>();
 	}
 	if start.is_err() || end.is_err() {
-		return (None, ret);
+		return None;
 	}
 
-	(Some(start.unwrap()..end.unwrap() + 1_i32), ret)
+	Some(start.unwrap()..end.unwrap() + 1_i32)
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum YearFilter {
+	Range(Range<i32>),
+	Greater(i32),
+	GreaterOrEqual(i32),
+	Less(i32),
+	LessOrEqual(i32),
+}
+
+// Parses a year token with a single year, a range of years or a comparison.
+// Ex. year:1998, year:1998-2004 or year:>=2010
+fn parse_year(query: &str, token: &str) -> (Option<YearFilter>, String) {
+	let (raw_years, ret) = parse_token(query, token);
+	let raw_years = match raw_years {
+		Some(x) => x.replace('%', ""),
+		None => {
+			return (None, ret);
+		}
+	};
+
+	let comparisons: [(&str, fn(i32) -> YearFilter); 4] = [
+		(">=", YearFilter::GreaterOrEqual),
+		("<=", YearFilter::LessOrEqual),
+		(">", YearFilter::Greater),
+		("<", YearFilter::Less),
+	];
+	for (operator, filter) in comparisons {
+		if let Some(year) = raw_years.strip_prefix(operator) {
+			return (year.parse::<i32>().ok().map(filter), ret);
+		}
+	}
+	(range_from_str(&raw_years).map(YearFilter::Range), ret)
+}
+
+// Fields of songs to leave out of results. Set with a leading '-' on a token.
+// Ex. -genre:metal
 #[derive(Default, Debug, PartialEq)]
-pub struct QueryFields {
+pub struct ExcludedFields {
 	pub title: Option<String>,
 	pub artist: Option<String>,
 	pub album_artist: Option<String>,
@@ -104,23 +223,62 @@ pub struct QueryFields {
 	pub lyricist: Option<String>,
 	pub composer: Option<String>,
 	pub genre: Option<String>,
+}
+
+#[derive(Default, Debug, PartialEq)]
+pub struct QueryFields {
+	// Each of the text fields holds the values any one of which should match
+	pub title: Vec<String>,
+	pub artist: Vec<String>,
+	pub album_artist: Vec<String>,
+	pub album: Vec<String>,
+	pub lyricist: Vec<String>,
+	pub composer: Vec<String>,
+	pub genre: Vec<String>,
 	pub general_query: Option<String>,
-	pub years: Option<Range<i32>>,
+	pub years: Option<YearFilter>,
+	pub durations: Option<Range<i32>>,
+	pub track_numbers: Option<Range<i32>>,
+	pub excluded: ExcludedFields,
+}
+
+impl QueryFields {
+	// True when the query has no field tokens at all.
+	fn is_general(&self) -> bool {
+		let general = QueryFields {
+			general_query: self.general_query.clone(),
+			..Default::default()
+		};
+		*self == general
+	}
+
+	// Only these fields are known to directories.
+	fn has_directory_fields(&self) -> bool {
+		!self.artist.is_empty() || !self.album.is_empty() || self.years.is_some()
+	}
 }
 
 pub fn parse_query(query: &str) -> QueryFields {
 	// Replace multiple spaces and trim leading and trailing spaces.
-	let re = Regex::new(r"\s+").unwrap();
-	let query = re.replace_all(&query.to_ascii_lowercase(), " ").to_string();
-	let query = query.trim().to_string();
-	let (title, query) = parse_token(&query, "title");
-	let (album_artist, query) = parse_token(&query, "album_artist");
-	let (artist, query) = parse_token(&query, "artist");
-	let (album, query) = parse_token(&query, "album");
-	let (lyricist, query) = parse_token(&query, "lyricist");
-	let (composer, query) = parse_token(&query, "composer");
-	let (genre, query) = parse_token(&query, "genre");
+	let query = collapse_whitespace(&query.to_ascii_lowercase());
+	// Excluded tokens go first so that "-artist:" is not taken for "artist:".
+	let (excluded_title, query) = parse_token(&query, "-title");
+	let (excluded_album_artist, query) = parse_token(&query, "-album_artist");
+	let (excluded_artist, query) = parse_token(&query, "-artist");
+	let (excluded_album, query) = parse_token(&query, "-album");
+	let (excluded_lyricist, query) = parse_token(&query, "-lyricist");
+	let (excluded_composer, query) = parse_token(&query, "-composer");
+	let (excluded_genre, query) = parse_token(&query, "-genre");
+	let (title, query) = parse_token_any(&query, "title");
+	let (album_artist, query) = parse_token_any(&query, "album_artist");
+	let (artist, query) = parse_token_any(&query, "artist");
+	let (album, query) = parse_token_any(&query, "album");
+	let (lyricist, query) = parse_token_any(&query, "lyricist");
+	let (composer, query) = parse_token_any(&query, "composer");
+	let (genre, query) = parse_token_any(&query, "genre");
 	let (years, query) = parse_year(&query, "year");
+	let (durations, query) = parse_range(&query, "duration");
+	let (track_numbers, query) = parse_range(&query, "track_number");
 	QueryFields {
 		title,
 		artist,
@@ -131,6 +289,17 @@ pub fn parse_query(query: &str) -> QueryFields {
 		genre,
 		general_query: Some(query),
 		years,
+		durations,
+		track_numbers,
+		excluded: ExcludedFields {
+			title: excluded_title,
+			artist: excluded_artist,
+			album_artist: excluded_album_artist,
+			album: excluded_album,
+			lyricist: excluded_lyricist,
+			composer: excluded_composer,
+			genre: excluded_genre,
+		},
 	}
 }
 
@@ -151,19 +320,596 @@ sql_function!(
 	fn random() -> Integer;
 );
 
+sql_function!(
+	fn substr(
+		text: sql_types::Text,
+		start: sql_types::Integer,
+		length: sql_types::Integer,
+	) -> sql_types::Text;
+);
+
+// Search results are capped to this many files unless the caller asks otherwise.
+// As with SQLite, a negative cap means no cap.
+pub const DEFAULT_SEARCH_MAX_RESULTS: i64 = 1000;
+
+// Directories come first so they are kept over songs when there are too many results.
+fn truncate_results<T>(output: &mut Vec<T>, max_results: Option<i64>) {
+	if let Some(max_results) = max_results.and_then(|m| usize::try_from(m).ok()) {
+		output.truncate(max_results);
+	}
+}
+
+// Entries that compare equal on the ordering column stay in path order.
+fn directory_order(order: BrowseOrder) -> &'static str {
+	match order {
+		BrowseOrder::Path => "path COLLATE NOCASE ASC",
+		BrowseOrder::Album => "album COLLATE NOCASE ASC, path COLLATE NOCASE ASC",
+		BrowseOrder::Year => "year IS NULL, year ASC, path COLLATE NOCASE ASC",
+		BrowseOrder::DateAdded => "date_added DESC, path COLLATE NOCASE ASC",
+	}
+}
+
+fn song_order(order: BrowseOrder) -> &'static str {
+	match order {
+		BrowseOrder::Path | BrowseOrder::DateAdded => "path COLLATE NOCASE ASC",
+		BrowseOrder::Album => "album COLLATE NOCASE ASC, path COLLATE NOCASE ASC",
+		BrowseOrder::Year => "year IS NULL, year ASC, path COLLATE NOCASE ASC",
+	}
+}
+
+// Lowest score of a fuzzy search result, low enough to let a typo or a plural through.
+const FUZZY_SEARCH_THRESHOLD: f64 = 0.75;
+
+// Length of the query word prefixes fuzzy search candidates must contain.
+const FUZZY_SEARCH_PREFIX_LENGTH: usize = 2;
+
+// Cheap LIKE patterns checked before scoring fuzzy search candidates: near matches almost
+// always start one of the query words the same way. Empty when the query has no words.
+fn fuzzy_prefix_patterns(query: &str) -> Vec<String> {
+	query
+		.split(|c: char| !c.is_alphanumeric())
+		.filter(|w| !w.is_empty())
+		.map(|w| {
+			let prefix: String = w.chars().take(FUZZY_SEARCH_PREFIX_LENGTH).collect();
+			format!("%{}%", prefix)
+		})
+		.collect()
+}
+
+// Rust side equivalent of the fuzzy_score SQL function.
+fn fuzzy_value_score(value: Option<&str>, query: &str, fold: bool) -> f64 {
+	match value {
+		Some(value) if fold => fuzzy_score(&fold_accents(value), &fold_accents(query)),
+		Some(value) => fuzzy_score(value, query),
+		None => 0.0,
+	}
+}
+
+// Best score of a generic search result across the columns it is matched on.
+fn fuzzy_file_score(file: &CollectionFile, query: &str, fold: bool) -> f64 {
+	generic_columns(file)
+		.into_iter()
+		.map(|(_, value)| fuzzy_value_score(value, query, fold))
+		.fold(0.0, f64::max)
+}
+
+// Rust side equivalent of a LIKE comparison: '%' matches any run of characters, '_' matches
+// any single character and, as in SQLite, ASCII letters match regardless of case.
+fn like_ignore_case(value: Option<&str>, pattern: &str, fold: bool) -> bool {
+	let (value, pattern) = match value {
+		Some(value) if fold => (fold_accents(value), fold_accents(pattern)),
+		Some(value) => (value.to_owned(), pattern.to_owned()),
+		None => return false,
+	};
+	let pattern: Vec<char> = pattern.to_ascii_lowercase().chars().collect();
+
+	// matches[i] tells whether the value read so far matches the first i pattern characters
+	let mut matches = vec![true];
+	for p in &pattern {
+		matches.push(*matches.last().unwrap() && *p == '%');
+	}
+	for c in value.to_ascii_lowercase().chars() {
+		let mut next = vec![false];
+		for (i, p) in pattern.iter().enumerate() {
+			let matched = match *p {
+				'%' => next[i] || matches[i + 1],
+				'_' => matches[i],
+				p => matches[i] && p == c,
+			};
+			next.push(matched);
+		}
+		matches = next;
+	}
+	matches[pattern.len()]
+}
+
+// Columns of a file that generic searches look at.
+fn generic_columns(file: &CollectionFile) -> Vec<(SearchField, Option<&str>)> {
+	match file {
+		CollectionFile::Directory(d) => vec![(SearchField::Path, Some(d.path.as_str()))],
+		CollectionFile::Song(s) => vec![
+			(SearchField::Path, Some(s.path.as_str())),
+			(SearchField::Title, s.title.as_deref()),
+			(SearchField::Artist, s.artist.as_deref()),
+			(SearchField::AlbumArtist, s.album_artist.as_deref()),
+			(SearchField::Album, s.album.as_deref()),
+			(SearchField::Lyricist, s.lyricist.as_deref()),
+			(SearchField::Composer, s.composer.as_deref()),
+			(SearchField::Genre, s.genre.as_deref()),
+		],
+	}
+}
+
+// Generic searches match directories on their path and songs on any text column.
+// Like the SQL filters, `file` is matched on its real path, before it is virtualized.
+fn generic_matches(
+	file: &CollectionFile,
+	query: &str,
+	options: &SearchOptions,
+) -> Vec<SearchField> {
+	let fold = options.fold_accents;
+	generic_columns(file)
+		.into_iter()
+		.filter(|(_, value)| {
+			like_ignore_case(*value, &format!("%{}%", query), fold)
+				|| (options.fuzzy
+					&& fuzzy_value_score(*value, query, fold) >= FUZZY_SEARCH_THRESHOLD)
+		})
+		.map(|(field, _)| field)
+		.collect()
+}
+
+// Field search results match every field of the query they have.
+fn field_matches(file: &CollectionFile, fields: &QueryFields) -> Vec<SearchField> {
+	let candidates = [
+		(SearchField::Title, !fields.title.is_empty(), false),
+		(SearchField::Artist, !fields.artist.is_empty(), true),
+		(
+			SearchField::AlbumArtist,
+			!fields.album_artist.is_empty(),
+			false,
+		),
+		(SearchField::Album, !fields.album.is_empty(), true),
+		(SearchField::Lyricist, !fields.lyricist.is_empty(), false),
+		(SearchField::Composer, !fields.composer.is_empty(), false),
+		(SearchField::Genre, !fields.genre.is_empty(), false),
+		(SearchField::Year, fields.years.is_some(), true),
+		(SearchField::Duration, fields.durations.is_some(), false),
+		(
+			SearchField::TrackNumber,
+			fields.track_numbers.is_some(),
+			false,
+		),
+	];
+	let is_directory = matches!(file, CollectionFile::Directory(_));
+	candidates
+		.into_iter()
+		.filter(|(_, queried, on_directories)| *queried && (*on_directories || !is_directory))
+		.map(|(field, _, _)| field)
+		.collect()
+}
+
+// Matches a nullable text column against a LIKE pattern.
+// With accent folding, diacritics are stripped from both sides before comparing.
+macro_rules! text_like {
+	($table:ty, $column:expr, $pattern:expr, $fold:expr) => {{
+		let condition: Box<
+			dyn BoxableExpression<$table, Sqlite, SqlType = sql_types::Nullable<sql_types::Bool>>,
+		> = if $fold {
+			Box::new(
+				db::fold_accents($column)
+					.like(fold_accents($pattern))
+					.nullable(),
+			)
+		} else {
+			Box::new($column.like($pattern.to_owned()).nullable())
+		};
+		condition
+	}};
+}
+
+// Tells whether a nullable text column is close enough to the query, see `utils::fuzzy_score`.
+macro_rules! text_fuzzy {
+	($table:ty, $column:expr, $query:expr, $fold:expr) => {{
+		let condition: Box<
+			dyn BoxableExpression<$table, Sqlite, SqlType = sql_types::Nullable<sql_types::Bool>>,
+		> = if $fold {
+			Box::new(
+				db::fuzzy_score(db::fold_accents($column), fold_accents($query))
+					.ge(FUZZY_SEARCH_THRESHOLD)
+					.nullable(),
+			)
+		} else {
+			Box::new(
+				db::fuzzy_score($column, $query.to_owned())
+					.ge(FUZZY_SEARCH_THRESHOLD)
+					.nullable(),
+			)
+		};
+		condition
+	}};
+}
+
+// Matches a nullable text column against any one of the LIKE patterns.
+macro_rules! any_like {
+	($table:ty, $column:expr, $patterns:expr, $fold:expr) => {{
+		let mut patterns = $patterns.iter();
+		let mut condition = text_like!($table, $column, patterns.next().unwrap(), $fold);
+		for pattern in patterns {
+			condition = Box::new(condition.or(text_like!($table, $column, pattern, $fold)));
+		}
+		condition
+	}};
+}
+
+// Keeps the paths that are within one of the mount points, which are the ones
+// `VFS::real_to_virtual` can map. Paths are compared as is, like `Path::strip_prefix` does.
+macro_rules! within_mounts {
+	($table:ty, $column:expr, $vfs:expr) => {{
+		let mut condition: Box<dyn BoxableExpression<$table, Sqlite, SqlType = sql_types::Bool>> =
+			Box::new(sql::<sql_types::Bool>("0"));
+		for mount in $vfs.mounts() {
+			let source = mount.source.to_string_lossy();
+			let source = source.trim_end_matches(std::path::is_separator);
+			let prefix = format!("{}{}", source, std::path::MAIN_SEPARATOR);
+			let prefix_length = prefix.chars().count() as i32;
+			condition = Box::new(
+				condition
+					.or($column.eq(source.to_owned()))
+					.or(substr($column, 1, prefix_length).eq(prefix)),
+			);
+		}
+		condition
+	}};
+}
+
+// Filters are built apart from the queries loading results, so that counting matches
+// applies exactly the same filters.
+type DirectoryFilter = directories::BoxedQuery<'static, Sqlite>;
+type SongFilter = songs::BoxedQuery<'static, Sqlite>;
+
+// Dirs with matching path and parent not matching
+fn generic_directory_filter(query: &str, options: &SearchOptions) -> DirectoryFilter {
+	use self::directories::dsl::*;
+	let fold = options.fold_accents;
+	if options.fuzzy {
+		let mut filter = directories.into_boxed();
+		let patterns = fuzzy_prefix_patterns(query);
+		if !patterns.is_empty() {
+			filter = filter.filter(any_like!(
+				db::directories::table,
+				path.nullable(),
+				patterns,
+				fold
+			));
+		}
+		return filter
+			.filter(text_fuzzy!(
+				db::directories::table,
+				path.nullable(),
+				query,
+				fold
+			))
+			.filter(not(text_fuzzy!(
+				db::directories::table,
+				parent,
+				query,
+				fold
+			)));
+	}
+	let like_test = format!("%{}%", query);
+	directories
+		.into_boxed()
+		.filter(text_like!(
+			db::directories::table,
+			path.nullable(),
+			&like_test,
+			fold
+		))
+		.filter(not(text_like!(
+			db::directories::table,
+			parent,
+			&like_test,
+			fold
+		)))
+}
+
+// Songs with matching path or tags and non-matching parent
+fn generic_song_filter(query: &str, options: &SearchOptions) -> SongFilter {
+	use self::songs::dsl::*;
+	let fold = options.fold_accents;
+	if options.fuzzy {
+		let mut filter = songs.into_boxed();
+		let patterns = fuzzy_prefix_patterns(query);
+		if !patterns.is_empty() {
+			filter = filter.filter(
+				any_like!(db::songs::table, path.nullable(), patterns, fold)
+					.or(any_like!(db::songs::table, title, patterns, fold))
+					.or(any_like!(db::songs::table, album, patterns, fold))
+					.or(any_like!(db::songs::table, artist, patterns, fold))
+					.or(any_like!(db::songs::table, album_artist, patterns, fold))
+					.or(any_like!(db::songs::table, composer, patterns, fold))
+					.or(any_like!(db::songs::table, lyricist, patterns, fold))
+					.or(any_like!(db::songs::table, genre, patterns, fold)),
+			);
+		}
+		return filter
+			.filter(
+				text_fuzzy!(db::songs::table, path.nullable(), query, fold)
+					.or(text_fuzzy!(db::songs::table, title, query, fold))
+					.or(text_fuzzy!(db::songs::table, album, query, fold))
+					.or(text_fuzzy!(db::songs::table, artist, query, fold))
+					.or(text_fuzzy!(db::songs::table, album_artist, query, fold))
+					.or(text_fuzzy!(db::songs::table, composer, query, fold))
+					.or(text_fuzzy!(db::songs::table, lyricist, query, fold))
+					.or(text_fuzzy!(db::songs::table, genre, query, fold)),
+			)
+			.filter(not(text_fuzzy!(
+				db::songs::table,
+				parent.nullable(),
+				query,
+				fold
+			)));
+	}
+	let like_test = format!("%{}%", query);
+	songs
+		.into_boxed()
+		.filter(
+			text_like!(db::songs::table, path.nullable(), &like_test, fold)
+				.or(text_like!(db::songs::table, title, &like_test, fold))
+				.or(text_like!(db::songs::table, album, &like_test, fold))
+				.or(text_like!(db::songs::table, artist, &like_test, fold))
+				.or(text_like!(db::songs::table, album_artist, &like_test, fold))
+				.or(text_like!(db::songs::table, composer, &like_test, fold))
+				.or(text_like!(db::songs::table, lyricist, &like_test, fold))
+				.or(text_like!(db::songs::table, genre, &like_test, fold)),
+		)
+		.filter(not(text_like!(
+			db::songs::table,
+			parent.nullable(),
+			&like_test,
+			fold
+		)))
+}
+
+// Words outside of tokens narrow results down like a generic search would.
+fn general_like_pattern(fields: &QueryFields) -> Option<String> {
+	fields
+		.general_query
+		.as_ref()
+		.filter(|q| !q.is_empty())
+		.map(|q| format!("%{}%", q))
+}
+
+// Dirs matching the fields they have, other fields do not constrain them
+fn field_directory_filter(fields: &QueryFields, fold: bool) -> DirectoryFilter {
+	use self::directories::dsl::*;
+	let general_like = general_like_pattern(fields);
+	let mut filter = directories.into_boxed();
+	if !fields.artist.is_empty() {
+		filter = filter.filter(any_like!(
+			db::directories::table,
+			artist,
+			fields.artist,
+			fold
+		))
+	}
+
+	if !fields.album.is_empty() {
+		filter = filter.filter(any_like!(db::directories::table, album, fields.album, fold))
+	}
+
+	let excluded = &fields.excluded;
+	if let Some(artist_name) = excluded.artist.as_ref() {
+		filter = filter.filter(artist.is_null().or(not(text_like!(
+			db::directories::table,
+			artist,
+			artist_name,
+			fold
+		))))
+	}
+
+	if let Some(album_name) = excluded.album.as_ref() {
+		filter = filter.filter(album.is_null().or(not(text_like!(
+			db::directories::table,
+			album,
+			album_name,
+			fold
+		))))
+	}
+
+	if let Some(years) = fields.years.as_ref() {
+		filter = match years {
+			YearFilter::Range(years) => filter
+				.filter(year.ge(years.start))
+				.filter(year.lt(years.end)),
+			YearFilter::Greater(y) => filter.filter(year.gt(*y)),
+			YearFilter::GreaterOrEqual(y) => filter.filter(year.ge(*y)),
+			YearFilter::Less(y) => filter.filter(year.lt(*y)),
+			YearFilter::LessOrEqual(y) => filter.filter(year.le(*y)),
+		}
+	}
+
+	if let Some(like_test) = general_like.as_ref() {
+		filter = filter.filter(text_like!(
+			db::directories::table,
+			path.nullable(),
+			like_test,
+			fold
+		))
+	}
+
+	filter
+}
+
+fn field_song_filter(fields: &QueryFields, fold: bool) -> SongFilter {
+	use self::songs::dsl::*;
+	let general_like = general_like_pattern(fields);
+	let mut filter = songs.into_boxed();
+	if !fields.title.is_empty() {
+		filter = filter.filter(any_like!(db::songs::table, title, fields.title, fold))
+	}
+
+	if !fields.artist.is_empty() {
+		filter = filter.filter(any_like!(db::songs::table, artist, fields.artist, fold))
+	}
+
+	if !fields.album_artist.is_empty() {
+		filter = filter.filter(any_like!(
+			db::songs::table,
+			album_artist,
+			fields.album_artist,
+			fold
+		))
+	}
+
+	if !fields.album.is_empty() {
+		filter = filter.filter(any_like!(db::songs::table, album, fields.album, fold))
+	}
+
+	if !fields.lyricist.is_empty() {
+		filter = filter.filter(any_like!(db::songs::table, lyricist, fields.lyricist, fold))
+	}
+
+	if !fields.composer.is_empty() {
+		filter = filter.filter(any_like!(db::songs::table, composer, fields.composer, fold))
+	}
+
+	if !fields.genre.is_empty() {
+		filter = filter.filter(any_like!(db::songs::table, genre, fields.genre, fold))
+	}
+
+	let excluded = &fields.excluded;
+	if let Some(title_name) = excluded.title.as_ref() {
+		filter = filter.filter(title.is_null().or(not(text_like!(
+			db::songs::table,
+			title,
+			title_name,
+			fold
+		))))
+	}
+
+	if let Some(artist_name) = excluded.artist.as_ref() {
+		filter = filter.filter(artist.is_null().or(not(text_like!(
+			db::songs::table,
+			artist,
+			artist_name,
+			fold
+		))))
+	}
+
+	if let Some(album_artist_name) = excluded.album_artist.as_ref() {
+		filter = filter.filter(album_artist.is_null().or(not(text_like!(
+			db::songs::table,
+			album_artist,
+			album_artist_name,
+			fold
+		))))
+	}
+
+	if let Some(album_name) = excluded.album.as_ref() {
+		filter = filter.filter(album.is_null().or(not(text_like!(
+			db::songs::table,
+			album,
+			album_name,
+			fold
+		))))
+	}
+
+	if let Some(lyricist_name) = excluded.lyricist.as_ref() {
+		filter = filter.filter(lyricist.is_null().or(not(text_like!(
+			db::songs::table,
+			lyricist,
+			lyricist_name,
+			fold
+		))))
+	}
+
+	if let Some(composer_name) = excluded.composer.as_ref() {
+		filter = filter.filter(composer.is_null().or(not(text_like!(
+			db::songs::table,
+			composer,
+			composer_name,
+			fold
+		))))
+	}
+
+	if let Some(genre_name) = excluded.genre.as_ref() {
+		filter = filter.filter(genre.is_null().or(not(text_like!(
+			db::songs::table,
+			genre,
+			genre_name,
+			fold
+		))))
+	}
+
+	if let Some(years) = fields.years.as_ref() {
+		filter = match years {
+			YearFilter::Range(years) => filter
+				.filter(year.ge(years.start))
+				.filter(year.lt(years.end)),
+			YearFilter::Greater(y) => filter.filter(year.gt(*y)),
+			YearFilter::GreaterOrEqual(y) => filter.filter(year.ge(*y)),
+			YearFilter::Less(y) => filter.filter(year.lt(*y)),
+			YearFilter::LessOrEqual(y) => filter.filter(year.le(*y)),
+		}
+	}
+
+	if let Some(durations) = fields.durations.as_ref() {
+		filter = filter
+			.filter(duration.ge(durations.start))
+			.filter(duration.lt(durations.end))
+	}
+
+	if let Some(track_numbers) = fields.track_numbers.as_ref() {
+		filter = filter
+			.filter(track_number.ge(track_numbers.start))
+			.filter(track_number.lt(track_numbers.end))
+	}
+
+	if let Some(like_test) = general_like.as_ref() {
+		filter = filter.filter(
+			text_like!(db::songs::table, path.nullable(), like_test, fold)
+				.or(text_like!(db::songs::table, title, like_test, fold))
+				.or(text_like!(db::songs::table, album, like_test, fold))
+				.or(text_like!(db::songs::table, artist, like_test, fold))
+				.or(text_like!(db::songs::table, album_artist, like_test, fold))
+				.or(text_like!(db::songs::table, composer, like_test, fold))
+				.or(text_like!(db::songs::table, lyricist, like_test, fold))
+				.or(text_like!(db::songs::table, genre, like_test, fold)),
+		)
+	}
+
+	filter
+}
+
 impl Index {
-	pub fn browse<P>(&self, virtual_path: P) -> Result<Vec<CollectionFile>, QueryError>
+	/// Browses a directory, skipping its first `offset` entries and returning at most
+	/// `limit` entries. Directories are listed before songs, each in the given order.
+	pub fn browse_page<P>(
+		&self,
+		virtual_path: P,
+		offset: u32,
+		limit: Option<u32>,
+		order: BrowseOrder,
+	) -> Result<Vec<CollectionFile>, QueryError>
 	where
 		P: AsRef<Path>,
 	{
 		let mut output = Vec::new();
 		let vfs = self.vfs_manager.get_vfs()?;
 		let mut connection = self.db.connect()?;
+		let offset = i64::from(offset);
+		// SQLite treats a negative limit as no limit.
+		let sql_limit = limit.map_or(-1, i64::from);
 
 		if virtual_path.as_ref().components().count() == 0 {
 			// Browse top-level
 			let real_directories: Vec<Directory> = directories::table
 				.filter(directories::parent.is_null())
+				.order(sql::<sql_types::Bool>(directory_order(order)))
+				.limit(sql_limit)
+				.offset(offset)
 				.load(&mut connection)?;
 			let virtual_directories = real_directories
 				.into_iter()
@@ -174,49 +920,79 @@ impl Index {
 			let real_path = vfs.virtual_to_real(virtual_path)?;
 			let real_path_string = real_path.as_path().to_string_lossy().into_owned();
 
+			let directory_count: i64 = directories::table
+				.filter(directories::parent.eq(&real_path_string))
+				.count()
+				.get_result(&mut connection)?;
 			let real_directories: Vec<Directory> = directories::table
 				.filter(directories::parent.eq(&real_path_string))
-				.order(sql::<sql_types::Bool>("path COLLATE NOCASE ASC"))
+				.order(sql::<sql_types::Bool>(directory_order(order)))
+				.limit(sql_limit)
+				.offset(offset)
 				.load(&mut connection)?;
+			let song_limit = limit.map(|l| i64::from(l) - real_directories.len() as i64);
 			let virtual_directories = real_directories
 				.into_iter()
 				.filter_map(|d| d.virtualize(&vfs));
 			output.extend(virtual_directories.map(CollectionFile::Directory));
 
-			println!("Browse: {}", real_path_string);
-			let real_songs: Vec<Song> = songs::table
-				.filter(songs::parent.eq(&real_path_string))
-				.order(sql::<sql_types::Bool>("path COLLATE NOCASE ASC"))
-				.load(&mut connection)?;
-			let virtual_songs = real_songs.into_iter().filter_map(|s| s.virtualize(&vfs));
-			output.extend(virtual_songs.map(CollectionFile::Song));
+			if song_limit.map_or(true, |l| l > 0) {
+				log::debug!("Browse: {}", real_path_string);
+				let real_songs: Vec<Song> = songs::table
+					.filter(songs::parent.eq(&real_path_string))
+					.order(sql::<sql_types::Bool>(song_order(order)))
+					.limit(song_limit.unwrap_or(-1))
+					.offset((offset - directory_count).max(0))
+					.load(&mut connection)?;
+				let virtual_songs = real_songs.into_iter().filter_map(|s| s.virtualize(&vfs));
+				output.extend(virtual_songs.map(CollectionFile::Song));
+			}
 		}
 
 		Ok(output)
 	}
 
-	pub fn flatten<P>(&self, virtual_path: P) -> Result<Vec<Song>, QueryError>
+	/// Lists songs under a directory in the given order, skipping the first `offset`
+	/// songs and returning at most `limit` songs.
+	pub fn flatten_page<P>(
+		&self,
+		virtual_path: P,
+		offset: u32,
+		limit: Option<u32>,
+		order: SongOrder,
+	) -> Result<Vec<Song>, QueryError>
 	where
 		P: AsRef<Path>,
 	{
-		use self::songs::dsl::*;
 		let vfs = self.vfs_manager.get_vfs()?;
 		let mut connection = self.db.connect()?;
+		let offset = i64::from(offset);
+		// SQLite treats a negative limit as no limit.
+		let sql_limit = limit.map_or(-1, i64::from);
 
-		let real_songs: Vec<Song> = if virtual_path.as_ref().parent().is_some() {
+		// Songs have no date of their own, they are as recent as their directory.
+		let mut query = songs::table
+			.left_join(directories::table.on(songs::parent.eq(directories::path)))
+			.select(songs::all_columns)
+			.into_boxed();
+		if virtual_path.as_ref().parent().is_some() {
 			let real_path = vfs.virtual_to_real(virtual_path)?;
 			let song_path_filter = {
 				let mut path_buf = real_path;
 				path_buf.push("%");
 				path_buf.as_path().to_string_lossy().into_owned()
 			};
-			songs
-				.filter(path.like(&song_path_filter))
-				.order(path)
-				.load(&mut connection)?
-		} else {
-			songs.order(path).load(&mut connection)?
+			query = query.filter(songs::path.like(song_path_filter));
+		}
+		query = match order {
+			SongOrder::Path => query.order(songs::path),
+			SongOrder::Random => query.order(random()),
+			SongOrder::RecentlyAdded => query.order((directories::date_added.desc(), songs::path)),
 		};
+		let real_songs: Vec<Song> = query
+			.limit(sql_limit)
+			.offset(offset)
+			.load(&mut connection)?;
 
 		let virtual_songs = real_songs.into_iter().filter_map(|s| s.virtualize(&vfs));
 		Ok(virtual_songs.collect::<Vec<_>>())
@@ -252,113 +1028,211 @@ impl Index {
 		Ok(virtual_directories.collect::<Vec<_>>())
 	}
 
-	pub fn generic_search(&self, query: &str) -> Result<Vec<CollectionFile>, QueryError> {
+	pub fn get_random_songs(&self, count: i64) -> Result<Vec<Song>, QueryError> {
+		use self::songs::dsl::*;
 		let vfs = self.vfs_manager.get_vfs()?;
 		let mut connection = self.db.connect()?;
-		let like_test = format!("%{}%", query);
-		let mut output = Vec::new();
+		let real_songs: Vec<Song> = songs.limit(count).order(random()).load(&mut connection)?;
+		let virtual_songs = real_songs.into_iter().filter_map(|s| s.virtualize(&vfs));
+		Ok(virtual_songs.collect::<Vec<_>>())
+	}
 
-		// Find dirs with matching path and parent not matching
-		{
-			use self::directories::dsl::*;
-			let real_directories: Vec<Directory> = directories
-				.filter(path.like(&like_test))
-				.filter(parent.not_like(&like_test))
-				.load(&mut connection)?;
+	// Songs have no date of their own, they are as recent as their directory.
+	pub fn get_recent_songs(&self, count: i64) -> Result<Vec<Song>, QueryError> {
+		let vfs = self.vfs_manager.get_vfs()?;
+		let mut connection = self.db.connect()?;
+		let real_songs: Vec<Song> = songs::table
+			.inner_join(directories::table.on(songs::parent.eq(directories::path)))
+			.select(songs::all_columns)
+			.order((directories::date_added.desc(), songs::path))
+			.limit(count)
+			.load(&mut connection)?;
+		let virtual_songs = real_songs.into_iter().filter_map(|s| s.virtualize(&vfs));
+		Ok(virtual_songs.collect::<Vec<_>>())
+	}
 
-			let virtual_directories = real_directories
-				.into_iter()
-				.filter_map(|d| d.virtualize(&vfs));
+	pub fn generic_search(
+		&self,
+		query: &str,
+		options: &SearchOptions,
+	) -> Result<Vec<CollectionFile>, QueryError> {
+		let results = self.generic_search_with_matches(query, options)?;
+		Ok(results.into_iter().map(|r| r.file).collect())
+	}
 
-			output.extend(virtual_directories.map(CollectionFile::Directory));
-		}
+	// Files are scored and matched on their indexed values, as the SQL filters see them, and
+	// virtualized afterwards. Fuzzy searches also return near matches, best first: only the
+	// candidates sharing a word prefix with the query are scored, and the results are capped
+	// once sorted rather than in SQL.
+	fn generic_search_with_matches(
+		&self,
+		query: &str,
+		options: &SearchOptions,
+	) -> Result<Vec<SearchResult>, QueryError> {
+		let vfs = self.vfs_manager.get_vfs()?;
+		let mut connection = self.db.connect()?;
+		let fold = options.fold_accents;
+		// SQLite treats a negative limit as no limit.
+		let sql_limit = if options.fuzzy {
+			-1
+		} else {
+			options.max_results.unwrap_or(-1)
+		};
+		let mut real_files = Vec::new();
 
-		// Find songs with matching title/album/artist and non-matching parent
-		{
-			use self::songs::dsl::*;
-			let real_songs: Vec<Song> = songs
-				.filter(
-					path.like(&like_test)
-						.or(title.like(&like_test))
-						.or(album.like(&like_test))
-						.or(artist.like(&like_test))
-						.or(album_artist.like(&like_test))
-						.or(composer.like(&like_test))
-						.or(lyricist.like(&like_test))
-						.or(genre.like(&like_test)),
-				)
-				.filter(parent.not_like(&like_test))
+		if options.kind.includes_directories() {
+			let real_directories: Vec<Directory> = generic_directory_filter(query, options)
+				.order(directories::path)
+				.limit(sql_limit)
 				.load(&mut connection)?;
+			real_files.extend(real_directories.into_iter().map(CollectionFile::Directory));
+		}
 
-			let virtual_songs = real_songs.into_iter().filter_map(|d| d.virtualize(&vfs));
-
-			output.extend(virtual_songs.map(CollectionFile::Song));
+		if options.kind.includes_songs() {
+			let real_songs: Vec<Song> = generic_song_filter(query, options)
+				.order(songs::path)
+				.limit(sql_limit)
+				.load(&mut connection)?;
+			real_files.extend(real_songs.into_iter().map(CollectionFile::Song));
 		}
 
+		let mut scored: Vec<(f64, SearchResult)> = real_files
+			.into_iter()
+			.filter_map(|file| {
+				let score = if options.fuzzy {
+					fuzzy_file_score(&file, query, fold)
+				} else {
+					0.0
+				};
+				let matched_fields = generic_matches(&file, query, options);
+				let file = match file {
+					CollectionFile::Directory(d) => CollectionFile::Directory(d.virtualize(&vfs)?),
+					CollectionFile::Song(s) => CollectionFile::Song(s.virtualize(&vfs)?),
+				};
+				Some((
+					score,
+					SearchResult {
+						file,
+						matched_fields,
+					},
+				))
+			})
+			.collect();
+		// The sort is stable so that equally good matches stay ordered by path.
+		scored.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+		let mut output: Vec<SearchResult> = scored.into_iter().map(|(_, r)| r).collect();
+		truncate_results(&mut output, options.max_results);
 		Ok(output)
 	}
 
-	fn field_search(&self, fields: &QueryFields) -> Result<Vec<CollectionFile>, QueryError> {
+	fn field_search(
+		&self,
+		fields: &QueryFields,
+		options: &SearchOptions,
+	) -> Result<Vec<CollectionFile>, QueryError> {
 		let vfs = self.vfs_manager.get_vfs()?;
 		let mut connection = self.db.connect()?;
+		let fold = options.fold_accents;
+		let sql_limit = options.max_results.unwrap_or(-1);
 		let mut output = Vec::new();
 
-		// Find songs with matching title/album/artist and non-matching parent
-		{
-			use self::songs::dsl::*;
-			let mut filter = songs.into_boxed();
-			if let Some(title_name) = fields.title.as_ref() {
-				filter = filter.filter(title.like(title_name))
-			}
-
-			if let Some(artist_name) = fields.artist.as_ref() {
-				filter = filter.filter(artist.like(artist_name))
-			}
-
-			if let Some(album_artist_name) = fields.album_artist.as_ref() {
-				filter = filter.filter(album_artist.like(album_artist_name))
-			}
-
-			if let Some(album_name) = fields.album.as_ref() {
-				filter = filter.filter(album.like(album_name))
-			}
-
-			if let Some(lyricist_name) = fields.lyricist.as_ref() {
-				filter = filter.filter(lyricist.like(lyricist_name))
-			}
-
-			if let Some(composer_name) = fields.composer.as_ref() {
-				filter = filter.filter(composer.like(composer_name))
-			}
-
-			if let Some(genre_name) = fields.genre.as_ref() {
-				filter = filter.filter(genre.like(genre_name))
-			}
+		if options.kind.includes_directories() && fields.has_directory_fields() {
+			let real_directories: Vec<Directory> = field_directory_filter(fields, fold)
+				.order(directories::path)
+				.limit(sql_limit)
+				.load(&mut connection)?;
+			let virtual_directories = real_directories
+				.into_iter()
+				.filter_map(|d| d.virtualize(&vfs));
 
-			if let Some(years) = fields.years.as_ref() {
-				filter = filter
-					.filter(year.ge(years.start))
-					.filter(year.lt(years.end))
-			}
+			output.extend(virtual_directories.map(CollectionFile::Directory));
+		}
 
-			let real_songs: Vec<Song> = filter.load(&mut connection)?;
+		if options.kind.includes_songs() {
+			let real_songs: Vec<Song> = field_song_filter(fields, fold)
+				.order(songs::path)
+				.limit(sql_limit)
+				.load(&mut connection)?;
 			let virtual_songs = real_songs.into_iter().filter_map(|d| d.virtualize(&vfs));
 
 			output.extend(virtual_songs.map(CollectionFile::Song));
 		}
+		truncate_results(&mut output, options.max_results);
 		Ok(output)
 	}
 
-	pub fn search(&self, query: &str) -> Result<Vec<CollectionFile>, QueryError> {
+	pub fn search(
+		&self,
+		query: &str,
+		options: &SearchOptions,
+	) -> Result<Vec<CollectionFile>, QueryError> {
 		let parsed_query = parse_query(query);
-		let tmp = QueryFields {
-			general_query: Some(parsed_query.general_query.as_ref().unwrap().to_string()),
-			..Default::default()
+		if parsed_query.is_general() {
+			return self.generic_search(parsed_query.general_query.as_ref().unwrap(), options);
+		}
+		self.field_search(&parsed_query, options)
+	}
+
+	// Same as search but tells which fields each result matched on.
+	pub fn search_with_matches(
+		&self,
+		query: &str,
+		options: &SearchOptions,
+	) -> Result<Vec<SearchResult>, QueryError> {
+		let parsed_query = parse_query(query);
+		let results = if parsed_query.is_general() {
+			let general_query = parsed_query.general_query.as_ref().unwrap();
+			self.generic_search_with_matches(general_query, options)?
+		} else {
+			self.field_search(&parsed_query, options)?
+				.into_iter()
+				.map(|file| SearchResult {
+					matched_fields: field_matches(&file, &parsed_query),
+					file,
+				})
+				.collect()
 		};
-		if parsed_query == tmp {
-			return self.generic_search(parsed_query.general_query.as_ref().unwrap());
+		Ok(results)
+	}
+
+	// Number of files a search matches when it is not capped by `max_results`.
+	// Files are counted with the same filters as `search` and, like its results, only those
+	// that are visible in the vfs are counted.
+	pub fn search_count(&self, query: &str, options: &SearchOptions) -> Result<i64, QueryError> {
+		let parsed_query = parse_query(query);
+		let vfs = self.vfs_manager.get_vfs()?;
+		let mut connection = self.db.connect()?;
+		let fold = options.fold_accents;
+		let include_directories = options.kind.includes_directories()
+			&& (parsed_query.is_general() || parsed_query.has_directory_fields());
+		let (directory_filter, song_filter) = match parsed_query.general_query.as_ref() {
+			Some(general_query) if parsed_query.is_general() => (
+				generic_directory_filter(general_query, options),
+				generic_song_filter(general_query, options),
+			),
+			_ => (
+				field_directory_filter(&parsed_query, fold),
+				field_song_filter(&parsed_query, fold),
+			),
+		};
+		let mut count = 0;
+		if include_directories {
+			count += directory_filter
+				.filter(within_mounts!(
+					db::directories::table,
+					directories::path,
+					vfs
+				))
+				.count()
+				.get_result::<i64>(&mut connection)?;
+		}
+		if options.kind.includes_songs() {
+			count += song_filter
+				.filter(within_mounts!(db::songs::table, songs::path, vfs))
+				.count()
+				.get_result::<i64>(&mut connection)?;
 		}
-		self.field_search(&parsed_query)
+		Ok(count)
 	}
 
 	pub fn get_song(&self, virtual_path: &Path) -> Result<Song, QueryError> {
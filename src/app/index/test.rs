@@ -3,7 +3,7 @@ use std::default::Default;
 use std::path::{Path, PathBuf};
 
 use super::*;
-use crate::app::test;
+use crate::app::{test, vfs};
 use crate::db::{directories, songs};
 use crate::test_name;
 
@@ -25,6 +25,68 @@ fn update_adds_new_content() {
 	let all_songs: Vec<Song> = songs::table.load(&mut connection).unwrap();
 	assert_eq!(all_directories.len(), TEST_DIRECTORIES_COUNT);
 	assert_eq!(all_songs.len(), TEST_ALL_SONGS_COUNT);
+
+	let progress = ctx.index.get_update_progress();
+	assert_eq!(progress.directories, TEST_DIRECTORIES_COUNT as u64);
+	assert_eq!(progress.songs, TEST_ALL_SONGS_COUNT as u64);
+}
+
+#[test]
+fn update_can_be_cancelled() {
+	let ctx = test::ContextBuilder::new(test_name!())
+		.mount(TEST_MOUNT_NAME, "test-data/small-collection")
+		.build();
+
+	// Cancel until the update notices, whichever step it is at.
+	let index = ctx.index.clone();
+	let update = std::thread::spawn(move || index.update());
+	while !update.is_finished() {
+		ctx.index.cancel_update(false);
+		std::thread::yield_now();
+	}
+	assert!(matches!(update.join().unwrap(), Err(Error::Cancelled)));
+
+	// Whatever was written is valid, and the next update completes the index.
+	let mut connection = ctx.db.connect().unwrap();
+	let all_songs: Vec<Song> = songs::table.load(&mut connection).unwrap();
+	assert!(all_songs.len() <= TEST_ALL_SONGS_COUNT);
+	assert!(all_songs.iter().all(|s| Path::new(&s.path).exists()));
+
+	ctx.index.update().unwrap();
+	let all_directories: Vec<Directory> = directories::table.load(&mut connection).unwrap();
+	let all_songs: Vec<Song> = songs::table.load(&mut connection).unwrap();
+	assert_eq!(all_directories.len(), TEST_DIRECTORIES_COUNT);
+	assert_eq!(all_songs.len(), TEST_ALL_SONGS_COUNT);
+}
+
+#[test]
+fn update_preserves_existing_rows() {
+	let ctx = test::ContextBuilder::new(test_name!())
+		.mount(TEST_MOUNT_NAME, "test-data/small-collection")
+		.build();
+
+	ctx.index.update().unwrap();
+
+	let mut connection = ctx.db.connect().unwrap();
+	diesel::update(directories::table)
+		.set(directories::date_added.eq(0))
+		.execute(&mut connection)
+		.unwrap();
+	let before: Vec<(i32, String, i32)> = directories::table
+		.select((directories::id, directories::path, directories::date_added))
+		.order(directories::path)
+		.load(&mut connection)
+		.unwrap();
+
+	ctx.index.update().unwrap();
+
+	let after: Vec<(i32, String, i32)> = directories::table
+		.select((directories::id, directories::path, directories::date_added))
+		.order(directories::path)
+		.load(&mut connection)
+		.unwrap();
+	assert_eq!(before, after);
+	assert!(after.iter().all(|(_, _, date_added)| *date_added == 0));
 }
 
 #[test]
@@ -76,7 +138,10 @@ fn can_browse_top_level() {
 	ctx.index.update().unwrap();
 
 	let root_path = Path::new(TEST_MOUNT_NAME);
-	let files = ctx.index.browse(Path::new("")).unwrap();
+	let files = ctx
+		.index
+		.browse_page(Path::new(""), 0, None, BrowseOrder::Path)
+		.unwrap();
 	assert_eq!(files.len(), 1);
 	match files[0] {
 		CollectionFile::Directory(ref d) => assert_eq!(d.path, root_path.to_str().unwrap()),
@@ -94,7 +159,10 @@ fn can_browse_directory() {
 		.build();
 	ctx.index.update().unwrap();
 
-	let files = ctx.index.browse(Path::new(TEST_MOUNT_NAME)).unwrap();
+	let files = ctx
+		.index
+		.browse_page(Path::new(TEST_MOUNT_NAME), 0, None, BrowseOrder::Path)
+		.unwrap();
 
 	assert_eq!(files.len(), 4);
 	if let (CollectionFile::Directory(ref d1), CollectionFile::Directory(ref d2)) =
@@ -108,13 +176,157 @@ fn can_browse_directory() {
 	}
 }
 
+#[test]
+fn can_browse_in_order() {
+	let ctx = test::ContextBuilder::new(test_name!())
+		.mount(TEST_MOUNT_NAME, "test-data/small-collection")
+		.build();
+	ctx.index.update().unwrap();
+
+	let path: PathBuf = [TEST_MOUNT_NAME, "Tobokegao"].iter().collect();
+	let files = ctx
+		.index
+		.browse_page(&path, 0, None, BrowseOrder::Path)
+		.unwrap();
+	let by_year = ctx
+		.index
+		.browse_page(&path, 0, None, BrowseOrder::Year)
+		.unwrap();
+	assert_eq!(by_year.len(), files.len());
+	let years: Vec<Option<i32>> = by_year
+		.iter()
+		.filter_map(|f| match f {
+			CollectionFile::Directory(d) => Some(d.year),
+			_ => None,
+		})
+		.collect();
+	let missing_last = |y: &Option<i32>| (y.is_none(), *y);
+	assert!(years
+		.windows(2)
+		.all(|w| missing_last(&w[0]) <= missing_last(&w[1])));
+
+	let by_date = ctx
+		.index
+		.browse_page(&path, 0, None, BrowseOrder::DateAdded)
+		.unwrap();
+	assert_eq!(by_date.len(), files.len());
+	let dates: Vec<i32> = by_date
+		.iter()
+		.filter_map(|f| match f {
+			CollectionFile::Directory(d) => Some(d.date_added),
+			_ => None,
+		})
+		.collect();
+	assert!(dates.windows(2).all(|w| w[0] >= w[1]));
+
+	// Directories still come before songs
+	let by_album = ctx
+		.index
+		.browse_page(Path::new(TEST_MOUNT_NAME), 0, None, BrowseOrder::Album)
+		.unwrap();
+	let first_song = by_album
+		.iter()
+		.position(|f| matches!(f, CollectionFile::Song(_)))
+		.unwrap();
+	assert!(by_album[first_song..]
+		.iter()
+		.all(|f| matches!(f, CollectionFile::Song(_))));
+}
+
+#[test]
+fn can_browse_and_flatten_pages() {
+	let ctx = test::ContextBuilder::new(test_name!())
+		.mount(TEST_MOUNT_NAME, "test-data/small-collection")
+		.build();
+	ctx.index.update().unwrap();
+
+	let files = ctx
+		.index
+		.browse_page(Path::new(TEST_MOUNT_NAME), 0, None, BrowseOrder::Path)
+		.unwrap();
+	let mut pages = ctx
+		.index
+		.browse_page(Path::new(TEST_MOUNT_NAME), 0, Some(3), BrowseOrder::Path)
+		.unwrap();
+	assert_eq!(pages.len(), 3);
+	pages.extend(
+		ctx.index
+			.browse_page(Path::new(TEST_MOUNT_NAME), 3, Some(3), BrowseOrder::Path)
+			.unwrap(),
+	);
+	assert_eq!(pages, files);
+
+	let songs = ctx
+		.index
+		.flatten_page(Path::new(TEST_MOUNT_NAME), 0, None, SongOrder::Path)
+		.unwrap();
+	let page = ctx
+		.index
+		.flatten_page(Path::new(TEST_MOUNT_NAME), 5, Some(4), SongOrder::Path)
+		.unwrap();
+	assert_eq!(page, songs[5..9]);
+	let tail = ctx
+		.index
+		.flatten_page(Path::new(TEST_MOUNT_NAME), 10, None, SongOrder::Path)
+		.unwrap();
+	assert_eq!(tail, songs[10..]);
+}
+
+#[test]
+fn can_flatten_in_any_order() {
+	let ctx = test::ContextBuilder::new(test_name!())
+		.mount(TEST_MOUNT_NAME, "test-data/small-collection")
+		.build();
+	ctx.index.update().unwrap();
+
+	let mut connection = ctx.db.connect().unwrap();
+	diesel::update(directories::table)
+		.set(directories::date_added.eq(0))
+		.execute(&mut connection)
+		.unwrap();
+	diesel::update(directories::table)
+		.filter(directories::path.like("%Tobokegao%"))
+		.set(directories::date_added.eq(1))
+		.execute(&mut connection)
+		.unwrap();
+
+	let songs = ctx
+		.index
+		.flatten_page(Path::new(TEST_MOUNT_NAME), 0, None, SongOrder::Path)
+		.unwrap();
+	let shuffled = ctx
+		.index
+		.flatten_page(Path::new(TEST_MOUNT_NAME), 0, Some(5), SongOrder::Random)
+		.unwrap();
+	assert_eq!(shuffled.len(), 5);
+	assert!(shuffled.iter().all(|s| songs.contains(s)));
+
+	let recent = ctx
+		.index
+		.flatten_page(
+			Path::new(TEST_MOUNT_NAME),
+			0,
+			None,
+			SongOrder::RecentlyAdded,
+		)
+		.unwrap();
+	assert_eq!(recent.len(), TEST_ALL_SONGS_COUNT);
+	let tobokegao_virtual: PathBuf = [TEST_MOUNT_NAME, "Tobokegao"].iter().collect();
+	assert!(recent[..8]
+		.iter()
+		.all(|s| Path::new(&s.path).starts_with(&tobokegao_virtual)));
+}
+
 #[test]
 fn can_flatten_root() {
 	let ctx = test::ContextBuilder::new(test_name!())
 		.mount(TEST_MOUNT_NAME, "test-data/small-collection")
 		.build();
 	ctx.index.update().unwrap();
-	let songs = ctx.index.flatten(Path::new(TEST_MOUNT_NAME)).unwrap();
+	let songs = ctx
+		.index
+		.flatten_page(Path::new(TEST_MOUNT_NAME), 0, None, SongOrder::Path)
+		.unwrap();
 	assert_eq!(songs.len(), TEST_ALL_SONGS_COUNT);
 	assert_eq!(songs[0].title, Some("Above The Water".to_owned()));
 }
@@ -126,7 +338,10 @@ fn can_flatten_directory() {
 		.build();
 	ctx.index.update().unwrap();
 	let path: PathBuf = [TEST_MOUNT_NAME, "Tobokegao"].iter().collect();
-	let songs = ctx.index.flatten(path).unwrap();
+	let songs = ctx
+		.index
+		.flatten_page(path, 0, None, SongOrder::Path)
+		.unwrap();
 	assert_eq!(songs.len(), 8);
 }
 
@@ -137,7 +352,10 @@ fn can_flatten_directory_with_shared_prefix() {
 		.build();
 	ctx.index.update().unwrap();
 	let path: PathBuf = [TEST_MOUNT_NAME, "Tobokegao", "Picnic"].iter().collect(); // Prefix of '(Picnic Remixes)'
-	let songs = ctx.index.flatten(path).unwrap();
+	let songs = ctx
+		.index
+		.flatten_page(path, 0, None, SongOrder::Path)
+		.unwrap();
 	assert_eq!(songs.len(), 7);
 }
 
@@ -162,6 +380,28 @@ fn can_get_recent_albums() {
 	assert!(albums[0].date_added >= albums[1].date_added);
 }
 
+#[test]
+fn can_get_random_songs() {
+	let ctx = test::ContextBuilder::new(test_name!())
+		.mount(TEST_MOUNT_NAME, "test-data/small-collection")
+		.build();
+	ctx.index.update().unwrap();
+	let songs = ctx.index.get_random_songs(3).unwrap();
+	assert_eq!(songs.len(), 3);
+}
+
+#[test]
+fn can_get_recent_songs() {
+	let ctx = test::ContextBuilder::new(test_name!())
+		.mount(TEST_MOUNT_NAME, "test-data/small-collection")
+		.build();
+	ctx.index.update().unwrap();
+	let songs = ctx.index.get_recent_songs(2).unwrap();
+	assert_eq!(songs.len(), 2);
+	let songs = ctx.index.get_recent_songs(100).unwrap();
+	assert_eq!(songs.len(), TEST_ALL_SONGS_COUNT);
+}
+
 #[test]
 fn can_get_a_song() {
 	let ctx = test::ContextBuilder::new(test_name!())
@@ -226,7 +466,10 @@ fn album_art_pattern_is_case_insensitive() {
 
 		let hunted_virtual_dir: PathBuf = [TEST_MOUNT_NAME, "Khemmis", "Hunted"].iter().collect();
 		let artwork_virtual_path = hunted_virtual_dir.join("Folder.jpg");
-		let song = &ctx.index.flatten(&hunted_virtual_dir).unwrap()[0];
+		let song = &ctx
+			.index
+			.flatten_page(&hunted_virtual_dir, 0, None, SongOrder::Path)
+			.unwrap()[0];
 		assert_eq!(
 			song.artwork,
 			Some(artwork_virtual_path.to_string_lossy().into_owned())
@@ -264,7 +507,7 @@ fn query_string_tokern_empty_string() {
 fn query_string_token_at_start() {
 	let query = QueryFields {
 		general_query: Some("generic query".to_string()),
-		composer: Some("%test_composer%".to_string()),
+		composer: vec!["%test_composer%".to_string()],
 		..Default::default()
 	};
 	assert_eq!(query, parse_query("composer:TEST_COMPOSER generic query"));
@@ -274,7 +517,7 @@ fn query_string_token_at_start() {
 fn query_string_token_at_end() {
 	let query = QueryFields {
 		general_query: Some("generic query".to_string()),
-		composer: Some("%est composer%".to_string()),
+		composer: vec!["%est composer%".to_string()],
 		..Default::default()
 	};
 	assert_eq!(
@@ -287,7 +530,7 @@ fn query_string_token_at_end() {
 fn query_string_token_in_the_middle() {
 	let query = QueryFields {
 		general_query: Some("generic query generic2 query2".to_string()),
-		composer: Some("%test composer%".to_string()),
+		composer: vec!["%test composer%".to_string()],
 		..Default::default()
 	};
 	assert_eq!(
@@ -301,7 +544,7 @@ fn query_string_token_in_the_middle() {
 fn query_string_repeated_token_should_not_be_parsed() {
 	let query = QueryFields {
 		general_query: Some(
-			"artist:\" singer1 \" generic query artist:'singer2 ' generic2 query2".to_string(),
+			"artist:\"  singer1 \" generic query artist:'singer2 ' generic2 query2".to_string(),
 		),
 		..Default::default()
 	};
@@ -317,8 +560,8 @@ fn query_string_repeated_token_should_not_be_parsed() {
 fn query_string_multiple_space_trim() {
 	let query = QueryFields {
 		general_query: Some("generic query generic2 query2".to_string()),
-		composer: Some("%first1 last1%".to_string()),
-		artist: Some("%first2 last2%".to_string()),
+		composer: vec!["%first1 last1%".to_string()],
+		artist: vec!["%first2  last2%".to_string()],
 		..Default::default()
 	};
 	assert_eq!(
@@ -330,10 +573,34 @@ fn query_string_multiple_space_trim() {
 	);
 }
 
+#[test]
+fn query_string_quoted_values_are_kept() {
+	let query = QueryFields {
+		general_query: Some("generic query".to_string()),
+		title: vec!["%don't stop%".to_string()],
+		album: vec!["%title: live%".to_string()],
+		genre: vec!["%drum  and   bass%".to_string()],
+		..Default::default()
+	};
+	assert_eq!(
+		query,
+		parse_query(
+			"generic  album:\"Title: Live\" title:\"Don't stop\"   genre:'Drum  and   Bass' query"
+		)
+	);
+
+	let query = QueryFields {
+		general_query: Some("".to_string()),
+		album: vec!["%greatest: hits%".to_string()],
+		..Default::default()
+	};
+	assert_eq!(query, parse_query("album:\"greatest: hits\""));
+}
+
 #[test]
 fn query_string_with_one_year() {
 	let query = QueryFields {
-		years: Some(1998..1999),
+		years: Some(YearFilter::Range(1998..1999)),
 		general_query: Some("".to_string()),
 		..Default::default()
 	};
@@ -343,25 +610,127 @@ fn query_string_with_one_year() {
 #[test]
 fn query_string_with_multiple_years() {
 	let query = QueryFields {
-		years: Some(1998..2005),
+		years: Some(YearFilter::Range(1998..2005)),
 		general_query: Some("".to_string()),
 		..Default::default()
 	};
 	assert_eq!(query, parse_query("year:1998-2004"));
 }
 
+#[test]
+fn query_string_with_year_comparisons() {
+	let year_filter = |query| parse_query(query).years;
+	assert_eq!(year_filter("year:>2010"), Some(YearFilter::Greater(2010)));
+	assert_eq!(
+		year_filter("year:>=2010"),
+		Some(YearFilter::GreaterOrEqual(2010))
+	);
+	assert_eq!(year_filter("year:<1999"), Some(YearFilter::Less(1999)));
+	assert_eq!(
+		year_filter("year:<=1999"),
+		Some(YearFilter::LessOrEqual(1999))
+	);
+	assert_eq!(year_filter("year:>=abc"), None);
+}
+
+#[test]
+fn search_by_year_comparison() {
+	let ctx = test::ContextBuilder::new(test_name!())
+		.mount(TEST_MOUNT_NAME, "test-data/small-collection")
+		.build();
+	ctx.index.update().unwrap();
+
+	// Expected results come from the years actually indexed rather than from assumptions
+	// about the test data.
+	let songs = ctx
+		.index
+		.flatten_page(Path::new(TEST_MOUNT_NAME), 0, None, SongOrder::Path)
+		.unwrap();
+	let expected = |keep: fn(i32) -> bool| -> Vec<String> {
+		let mut paths: Vec<String> = songs
+			.iter()
+			.filter(|s| s.year.map_or(false, keep))
+			.map(|s| s.path.clone())
+			.collect();
+		paths.sort();
+		paths
+	};
+	let found = |query: &str| -> Vec<String> {
+		let files = ctx.index.search(query, &SearchOptions::default()).unwrap();
+		let mut paths: Vec<String> = files
+			.into_iter()
+			.filter_map(|f| match f {
+				CollectionFile::Song(s) => Some(s.path),
+				_ => None,
+			})
+			.collect();
+		paths.sort();
+		paths
+	};
+
+	assert!(!expected(|y| y == 2016).is_empty());
+	assert_eq!(found("year:>2016"), expected(|y| y > 2016));
+	assert_eq!(found("year:>=2016"), expected(|y| y >= 2016));
+	assert_eq!(found("year:<2016"), expected(|y| y < 2016));
+	assert_eq!(found("year:<=2016"), expected(|y| y <= 2016));
+}
+
+#[test]
+fn query_string_with_one_duration() {
+	let query = QueryFields {
+		durations: Some(180..181),
+		general_query: Some("".to_string()),
+		..Default::default()
+	};
+	assert_eq!(query, parse_query("duration:180"));
+}
+
+#[test]
+fn query_string_with_duration_range() {
+	let query = QueryFields {
+		durations: Some(180..241),
+		general_query: Some("short".to_string()),
+		..Default::default()
+	};
+	assert_eq!(query, parse_query("short duration:180-240"));
+}
+
+#[test]
+fn query_string_with_one_track_number() {
+	let query = QueryFields {
+		track_numbers: Some(3..4),
+		general_query: Some("".to_string()),
+		..Default::default()
+	};
+	assert_eq!(query, parse_query("track_number:3"));
+}
+
+#[test]
+fn query_string_with_track_number_range() {
+	let query = QueryFields {
+		track_numbers: Some(1..4),
+		years: Some(YearFilter::Range(2016..2017)),
+		general_query: Some("".to_string()),
+		..Default::default()
+	};
+	assert_eq!(query, parse_query("year:2016 track_number:1-3"));
+}
+
 #[test]
 fn query_string_all_fields() {
 	let query = QueryFields {
 		general_query: Some("filmfare".to_string()),
-		composer: Some("%r. d. burman%".to_string()),
-		artist: Some("%asha%".to_string()),
-		lyricist: Some("%gulzar%".to_string()),
-		album: Some("%ijaazat%".to_string()),
-		album_artist: Some("%burman%".to_string()),
-		title: Some("%choti si%".to_string()),
-		genre: Some("%filmi%".to_string()),
-		years: Some(0..2000),
+		composer: vec!["%r. d. burman%".to_string()],
+		artist: vec!["%asha%".to_string()],
+		lyricist: vec!["%gulzar%".to_string()],
+		album: vec!["%ijaazat%".to_string()],
+		album_artist: vec!["%burman%".to_string()],
+		title: vec!["%choti si%".to_string()],
+		genre: vec!["%filmi%".to_string()],
+		years: Some(YearFilter::Range(0..2000)),
+		durations: None,
+		track_numbers: None,
+		excluded: ExcludedFields::default(),
 	};
 	assert_eq!(
 		query,
@@ -371,3 +740,490 @@ fn query_string_all_fields() {
 		)
 	);
 }
+
+#[test]
+fn query_string_with_excluded_fields() {
+	let query = QueryFields {
+		general_query: Some("live".to_string()),
+		artist: vec!["%stratovarius%".to_string()],
+		album_artist: vec!["%kotipelto%".to_string()],
+		excluded: ExcludedFields {
+			artist: Some("%tolkki%".to_string()),
+			album_artist: Some("%various%".to_string()),
+			genre: Some("%metal%".to_string()),
+			..Default::default()
+		},
+		..Default::default()
+	};
+	assert_eq!(
+		query,
+		parse_query(
+			"artist:stratovarius -genre:Metal live -album_artist:various \
+		   album_artist:kotipelto -artist:'Tolkki'"
+		)
+	);
+}
+
+#[test]
+fn query_string_with_or_tokens() {
+	let query = QueryFields {
+		general_query: Some("generic query".to_string()),
+		artist: vec!["%miles%".to_string(), "%john coltrane%".to_string()],
+		genre: vec!["%jazz%".to_string()],
+		..Default::default()
+	};
+	assert_eq!(
+		query,
+		parse_query("generic artist:Miles OR artist:\"John Coltrane\" genre:jazz query")
+	);
+}
+
+#[test]
+fn query_string_or_tokens_repeated_outside_the_group_should_not_be_parsed() {
+	let query = QueryFields {
+		general_query: Some("artist:miles or artist:coltrane artist:davis".to_string()),
+		title: vec!["%so what%".to_string()],
+		..Default::default()
+	};
+	assert_eq!(
+		query,
+		parse_query("artist:miles OR artist:coltrane title:'so what' artist:davis")
+	);
+}
+
+#[test]
+fn search_with_or_tokens() {
+	let ctx = test::ContextBuilder::new(test_name!())
+		.mount(TEST_MOUNT_NAME, "test-data/small-collection")
+		.build();
+	ctx.index.update().unwrap();
+
+	let files = ctx
+		.index
+		.search(
+			"title:candlelight OR title:'three gates'",
+			&SearchOptions::default(),
+		)
+		.unwrap();
+	assert_eq!(files.len(), 2);
+
+	// Other fields still narrow down the results
+	let files = ctx
+		.index
+		.search(
+			"title:candlelight OR title:'three gates' -title:candlelight",
+			&SearchOptions::default(),
+		)
+		.unwrap();
+	assert_eq!(files.len(), 1);
+}
+
+#[test]
+fn search_excludes_fields() {
+	let ctx = test::ContextBuilder::new(test_name!())
+		.mount(TEST_MOUNT_NAME, "test-data/small-collection")
+		.build();
+	ctx.index.update().unwrap();
+
+	// Songs without an artist are not excluded.
+	let files = ctx
+		.index
+		.search("-artist:khemmis", &SearchOptions::default())
+		.unwrap();
+	assert_eq!(files.len(), TEST_ALL_SONGS_COUNT - 5);
+
+	let files = ctx
+		.index
+		.search(
+			"album:hunted -title:hunted",
+			&SearchOptions {
+				kind: SearchKind::Songs,
+				..Default::default()
+			},
+		)
+		.unwrap();
+	assert_eq!(files.len(), 4);
+	for file in files {
+		match file {
+			CollectionFile::Song(s) => assert_ne!(s.title, Some("Hunted".to_owned())),
+			_ => panic!("Expected song"),
+		}
+	}
+}
+
+#[test]
+fn search_by_field_includes_directories() {
+	let ctx = test::ContextBuilder::new(test_name!())
+		.mount(TEST_MOUNT_NAME, "test-data/small-collection")
+		.build();
+	ctx.index.update().unwrap();
+
+	let hunted_path: PathBuf = [TEST_MOUNT_NAME, "Khemmis", "Hunted"].iter().collect();
+	let files = ctx
+		.index
+		.search(
+			"artist:khemmis",
+			&SearchOptions {
+				kind: SearchKind::Directories,
+				..Default::default()
+			},
+		)
+		.unwrap();
+	assert_eq!(files.len(), 1);
+	match &files[0] {
+		CollectionFile::Directory(d) => assert_eq!(d.path, hunted_path.to_str().unwrap()),
+		_ => panic!("Expected directory"),
+	}
+
+	let files = ctx
+		.index
+		.search("artist:khemmis", &SearchOptions::default())
+		.unwrap();
+	assert_eq!(files.len(), 6);
+
+	// Directories have no title
+	let files = ctx
+		.index
+		.search("album:hunted title:candlelight", &SearchOptions::default())
+		.unwrap();
+	assert_eq!(files.len(), 2);
+
+	// Fields directories do not have are not searched for directories
+	let files = ctx
+		.index
+		.search(
+			"title:candlelight",
+			&SearchOptions {
+				kind: SearchKind::Directories,
+				..Default::default()
+			},
+		)
+		.unwrap();
+	assert!(files.is_empty());
+}
+
+#[test]
+fn search_combines_fields_and_general_query() {
+	let ctx = test::ContextBuilder::new(test_name!())
+		.mount(TEST_MOUNT_NAME, "test-data/small-collection")
+		.build();
+	ctx.index.update().unwrap();
+
+	let files = ctx
+		.index
+		.search("artist:khemmis candlelight", &SearchOptions::default())
+		.unwrap();
+	assert_eq!(files.len(), 1);
+	match &files[0] {
+		CollectionFile::Song(s) => assert!(s.path.ends_with("02 - Candlelight.mp3")),
+		_ => panic!("Expected song"),
+	}
+
+	let files = ctx
+		.index
+		.search("artist:tobokegao candlelight", &SearchOptions::default())
+		.unwrap();
+	assert!(files.is_empty());
+}
+
+#[test]
+fn search_results_are_capped() {
+	let ctx = test::ContextBuilder::new(test_name!())
+		.mount(TEST_MOUNT_NAME, "test-data/small-collection")
+		.build();
+	ctx.index.update().unwrap();
+
+	let all_files = ctx
+		.index
+		.search(
+			"",
+			&SearchOptions {
+				kind: SearchKind::Songs,
+				..Default::default()
+			},
+		)
+		.unwrap();
+	assert_eq!(all_files.len(), TEST_ALL_SONGS_COUNT);
+
+	// Truncated results are the first ones in path order
+	let files = ctx
+		.index
+		.search(
+			"",
+			&SearchOptions {
+				kind: SearchKind::Songs,
+				max_results: Some(3),
+				..Default::default()
+			},
+		)
+		.unwrap();
+	assert_eq!(files, all_files[..3]);
+
+	let files = ctx
+		.index
+		.search(
+			"year:2016",
+			&SearchOptions {
+				kind: SearchKind::All,
+				max_results: Some(2),
+				..Default::default()
+			},
+		)
+		.unwrap();
+	assert_eq!(files.len(), 2);
+}
+
+#[test]
+fn search_count_ignores_cap() {
+	let ctx = test::ContextBuilder::new(test_name!())
+		.mount(TEST_MOUNT_NAME, "test-data/small-collection")
+		.build();
+	ctx.index.update().unwrap();
+
+	for query in ["", "door", "year:2016", "artist:tobokegao -title:door"] {
+		let options = SearchOptions::default();
+		let count = ctx.index.search_count(query, &options).unwrap();
+		assert_eq!(
+			count,
+			ctx.index.search(query, &options).unwrap().len() as i64
+		);
+
+		let options = SearchOptions {
+			kind: SearchKind::Songs,
+			max_results: Some(1),
+			..Default::default()
+		};
+		let count = ctx.index.search_count(query, &options).unwrap();
+		let all_songs = SearchOptions {
+			max_results: None,
+			..options
+		};
+		assert_eq!(
+			count,
+			ctx.index.search(query, &all_songs).unwrap().len() as i64
+		);
+	}
+
+	let options = SearchOptions {
+		kind: SearchKind::Songs,
+		max_results: Some(3),
+		..Default::default()
+	};
+	assert_eq!(ctx.index.search("", &options).unwrap().len(), 3);
+	assert_eq!(
+		ctx.index.search_count("", &options).unwrap(),
+		TEST_ALL_SONGS_COUNT as i64
+	);
+}
+
+#[test]
+fn search_count_skips_unmounted_files() {
+	let ctx = test::ContextBuilder::new(test_name!())
+		.mount(TEST_MOUNT_NAME, "test-data/small-collection")
+		.build();
+	ctx.index.update().unwrap();
+
+	// Indexed files outside of the remaining mount are no longer returned by search.
+	ctx.vfs_manager
+		.set_mount_dirs(&[vfs::MountDir {
+			name: TEST_MOUNT_NAME.to_owned(),
+			source: "test-data/small-collection/Khemmis".to_owned(),
+		}])
+		.unwrap();
+
+	for query in ["", "hunted", "artist:khemmis"] {
+		let options = SearchOptions::default();
+		let results = ctx.index.search(query, &options).unwrap();
+		assert!(results.len() < TEST_ALL_SONGS_COUNT + TEST_DIRECTORIES_COUNT);
+		assert_eq!(
+			ctx.index.search_count(query, &options).unwrap(),
+			results.len() as i64
+		);
+	}
+
+	// Mount points only cover whole directories, not paths that merely share a prefix.
+	ctx.vfs_manager
+		.set_mount_dirs(&[vfs::MountDir {
+			name: TEST_MOUNT_NAME.to_owned(),
+			source: "test-data/small-collection/Khem".to_owned(),
+		}])
+		.unwrap();
+	let options = SearchOptions::default();
+	assert!(ctx.index.search("hunted", &options).unwrap().is_empty());
+	assert_eq!(ctx.index.search_count("hunted", &options).unwrap(), 0);
+}
+
+#[test]
+fn search_can_fold_accents() {
+	let ctx = test::ContextBuilder::new(test_name!())
+		.mount(TEST_MOUNT_NAME, "test-data/small-collection")
+		.build();
+	ctx.index.update().unwrap();
+
+	let options = SearchOptions::default();
+	assert!(ctx.index.search("pïcnïc", &options).unwrap().is_empty());
+	assert!(ctx
+		.index
+		.search("title:pïcnïc", &options)
+		.unwrap()
+		.is_empty());
+
+	let options = SearchOptions {
+		fold_accents: true,
+		..Default::default()
+	};
+	assert!(!ctx.index.search("pïcnïc", &options).unwrap().is_empty());
+	let files = ctx.index.search("title:PÏCNÏC", &options).unwrap();
+	assert_eq!(files.len(), 2);
+}
+
+#[test]
+fn search_can_be_fuzzy() {
+	let ctx = test::ContextBuilder::new(test_name!())
+		.mount(TEST_MOUNT_NAME, "test-data/small-collection")
+		.build();
+	ctx.index.update().unwrap();
+
+	let options = SearchOptions::default();
+	assert!(ctx.index.search("candlelite", &options).unwrap().is_empty());
+
+	let options = SearchOptions {
+		fuzzy: true,
+		..Default::default()
+	};
+	let results = ctx
+		.index
+		.search_with_matches("candlelite", &options)
+		.unwrap();
+	assert_eq!(results.len(), 1);
+	match &results[0].file {
+		CollectionFile::Song(s) => assert_eq!(s.title, Some("Candlelight".to_owned())),
+		_ => panic!("Expected song"),
+	}
+	assert!(results[0].matched_fields.contains(&SearchField::Title));
+
+	let files = ctx.index.search("khemis", &options).unwrap();
+	assert_eq!(files.len(), 1);
+	assert!(matches!(files[0], CollectionFile::Directory(_)));
+
+	// Capped results are the best matches
+	let all_files = ctx.index.search("picnik", &options).unwrap();
+	assert!(all_files.len() > 1);
+	let capped_options = SearchOptions {
+		max_results: Some(1),
+		..options
+	};
+	let files = ctx.index.search("picnik", &capped_options).unwrap();
+	assert_eq!(files, all_files[..1]);
+}
+
+#[test]
+fn search_tells_matched_fields() {
+	let ctx = test::ContextBuilder::new(test_name!())
+		.mount(TEST_MOUNT_NAME, "test-data/small-collection")
+		.build();
+	ctx.index.update().unwrap();
+
+	let results = ctx
+		.index
+		.search_with_matches("candlelight", &SearchOptions::default())
+		.unwrap();
+	assert_eq!(results.len(), 1);
+	assert_eq!(
+		results[0].matched_fields,
+		vec![SearchField::Path, SearchField::Title]
+	);
+
+	// Wildcards match the same way as in the search itself
+	let results = ctx
+		.index
+		.search_with_matches("candle_ight", &SearchOptions::default())
+		.unwrap();
+	assert_eq!(results.len(), 1);
+	assert_eq!(
+		results[0].matched_fields,
+		vec![SearchField::Path, SearchField::Title]
+	);
+	let results = ctx
+		.index
+		.search_with_matches("hunted%door", &SearchOptions::default())
+		.unwrap();
+	assert!(!results.is_empty());
+	assert!(results.iter().all(|r| !r.matched_fields.is_empty()));
+
+	let results = ctx
+		.index
+		.search_with_matches("artist:khemmis year:2016", &SearchOptions::default())
+		.unwrap();
+	assert_eq!(results.len(), 6);
+	for result in results {
+		assert_eq!(
+			result.matched_fields,
+			vec![SearchField::Artist, SearchField::Year]
+		);
+	}
+
+	let results = ctx
+		.index
+		.search_with_matches("album:hunted title:candlelight", &SearchOptions::default())
+		.unwrap();
+	for result in results {
+		match result.file {
+			CollectionFile::Directory(_) => {
+				assert_eq!(result.matched_fields, vec![SearchField::Album])
+			}
+			CollectionFile::Song(_) => assert_eq!(
+				result.matched_fields,
+				vec![SearchField::Title, SearchField::Album]
+			),
+		}
+	}
+}
+
+#[test]
+fn song_error_is_only_serialized_when_set() {
+	let song = Song::test_only_from_path("root/song.mp3");
+	let json = serde_json::to_value(&song).unwrap();
+	assert!(json.get("error").is_none());
+	let song: Song = serde_json::from_value(json).unwrap();
+	assert!(!song.error);
+
+	let song = Song::error_song("root/missing.mp3");
+	let json = serde_json::to_value(&song).unwrap();
+	assert_eq!(json.get("error"), Some(&serde_json::Value::Bool(true)));
+}
+
+#[test]
+fn song_splits_multi_valued_tags() {
+	let mut song = Song::test_only_from_path("root/song.mp3");
+	assert!(song.artists(DEFAULT_TAG_DELIMITERS).is_empty());
+
+	song.artist = Some("Daft Punk; Pharrell Williams".to_owned());
+	song.genre = Some("Funk;Disco;".to_owned());
+	assert_eq!(
+		song.artists(DEFAULT_TAG_DELIMITERS),
+		vec!["Daft Punk", "Pharrell Williams"]
+	);
+	assert_eq!(song.genres(DEFAULT_TAG_DELIMITERS), vec!["Funk", "Disco"]);
+
+	song.artist = Some("AC/DC".to_owned());
+	assert_eq!(song.artists(DEFAULT_TAG_DELIMITERS), vec!["AC/DC"]);
+
+	song.genre = Some("Funk/Disco".to_owned());
+	assert_eq!(song.genres(&[';', '/']), vec!["Funk", "Disco"]);
+}
+
+#[test]
+fn song_builder_sets_fields() {
+	let song = Song::builder("root/artist/album/song.mp3")
+		.title("Song")
+		.track_number(3)
+		.genre("Rock")
+		.build();
+	assert_eq!(song.path, "root/artist/album/song.mp3");
+	assert_eq!(song.parent, "root/artist/album");
+	assert_eq!(song.title.as_deref(), Some("Song"));
+	assert_eq!(song.track_number, Some(3));
+	assert_eq!(song.genre.as_deref(), Some("Rock"));
+	assert_eq!(song.artist, None);
+}
@@ -1,3 +1,5 @@
+use diesel::deserialize::{self, Queryable};
+use diesel::sqlite::Sqlite;
 use serde::{Deserialize, Serialize};
 use std::path::Path;
 
@@ -10,8 +12,11 @@ pub enum CollectionFile {
 	Song(Song),
 }
 
-#[derive(Debug, PartialEq, Eq, Queryable, QueryableByName, Serialize, Deserialize, Clone)]
-#[diesel(table_name = songs)]
+/// Separators commonly used to store several values in a single tag, as in "Rock; Pop".
+/// Slashes are left out since they show up in names, as in "AC/DC".
+pub const DEFAULT_TAG_DELIMITERS: &[char] = &[';'];
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
 pub struct Song {
 	#[serde(skip_serializing, skip_deserializing)]
 	id: i32,
@@ -31,6 +36,73 @@ pub struct Song {
 	pub composer: Option<String>,
 	pub genre: Option<String>,
 	pub label: Option<String>,
+	/// Set on songs standing in for playlist entries whose file is missing.
+	#[serde(default, skip_serializing_if = "std::ops::Not::not")]
+	pub error: bool,
+}
+
+type SongRow = (
+	i32,
+	String,
+	String,
+	Option<i32>,
+	Option<i32>,
+	Option<String>,
+	Option<String>,
+	Option<String>,
+	Option<i32>,
+	Option<String>,
+	Option<String>,
+	Option<i32>,
+	Option<String>,
+	Option<String>,
+	Option<String>,
+	Option<String>,
+);
+
+// Implemented by hand because `error` is not stored in the songs table.
+impl Queryable<songs::SqlType, Sqlite> for Song {
+	type Row = SongRow;
+
+	fn build(row: Self::Row) -> deserialize::Result<Self> {
+		let (
+			id,
+			path,
+			parent,
+			track_number,
+			disc_number,
+			title,
+			artist,
+			album_artist,
+			year,
+			album,
+			artwork,
+			duration,
+			lyricist,
+			composer,
+			genre,
+			label,
+		) = row;
+		Ok(Song {
+			id,
+			path,
+			parent,
+			track_number,
+			disc_number,
+			title,
+			artist,
+			album_artist,
+			year,
+			album,
+			artwork,
+			duration,
+			lyricist,
+			composer,
+			genre,
+			label,
+			error: false,
+		})
+	}
 }
 
 impl Song {
@@ -48,6 +120,25 @@ impl Song {
 		Some(self)
 	}
 
+	pub fn id(&self) -> i32 {
+		self.id
+	}
+
+	/// Individual artists of the song, split on any of `delimiters`.
+	pub fn artists(&self, delimiters: &[char]) -> Vec<&str> {
+		split_tag_values(self.artist.as_deref(), delimiters)
+	}
+
+	/// Individual genres of the song, split on any of `delimiters`.
+	pub fn genres(&self, delimiters: &[char]) -> Vec<&str> {
+		split_tag_values(self.genre.as_deref(), delimiters)
+	}
+
+	#[cfg(test)]
+	pub fn builder(path: &str) -> SongBuilder {
+		SongBuilder::new(path)
+	}
+
 	pub fn error_song(path: &str) -> Self {
 		Song {
 			id: 0,
@@ -66,30 +157,108 @@ impl Song {
 			composer: None,
 			genre: None,
 			label: None,
+			error: true,
 		}
 	}
 
 	#[cfg(test)]
 	pub fn test_only_from_path(path: &str) -> Self {
-		Song {
-			id: 0,
-			path: path.to_string(),
-			parent: "".to_string(),
-			track_number: None,
-			disc_number: None,
-			title: None,
-			artist: None,
-			album_artist: None,
-			year: None,
-			album: None,
-			artwork: None,
-			duration: None,
-			lyricist: None,
-			composer: None,
-			genre: None,
-			label: None,
+		Song::builder(path).parent("").build()
+	}
+}
+
+fn split_tag_values<'a>(value: Option<&'a str>, delimiters: &[char]) -> Vec<&'a str> {
+	value
+		.map(|v| {
+			v.split(delimiters)
+				.map(str::trim)
+				.filter(|v| !v.is_empty())
+				.collect()
+		})
+		.unwrap_or_default()
+}
+
+/// Builds a `Song` with only the fields of interest set.
+/// The parent defaults to the directory containing `path`.
+#[cfg(test)]
+#[derive(Clone, Debug)]
+pub struct SongBuilder {
+	song: Song,
+}
+
+#[cfg(test)]
+macro_rules! song_builder_setters {
+	(text: $($field:ident),*) => {
+		$(
+			pub fn $field(mut self, $field: &str) -> Self {
+				self.song.$field = Some($field.to_string());
+				self
+			}
+		)*
+	};
+	(number: $($field:ident),*) => {
+		$(
+			pub fn $field(mut self, $field: i32) -> Self {
+				self.song.$field = Some($field);
+				self
+			}
+		)*
+	};
+}
+
+// There is a setter for every field, even those no test sets yet.
+#[cfg(test)]
+#[allow(dead_code)]
+impl SongBuilder {
+	pub fn new(path: &str) -> Self {
+		let parent = Path::new(path)
+			.parent()
+			.map(|p| p.to_string_lossy().into_owned())
+			.unwrap_or_default();
+		Self {
+			song: Song {
+				id: 0,
+				path: path.to_string(),
+				parent,
+				track_number: None,
+				disc_number: None,
+				title: None,
+				artist: None,
+				album_artist: None,
+				year: None,
+				album: None,
+				artwork: None,
+				duration: None,
+				lyricist: None,
+				composer: None,
+				genre: None,
+				label: None,
+				error: false,
+			},
 		}
 	}
+
+	pub fn parent(mut self, parent: &str) -> Self {
+		self.song.parent = parent.to_string();
+		self
+	}
+
+	song_builder_setters!(number: track_number, disc_number, year, duration);
+	song_builder_setters!(
+		text: title,
+		artist,
+		album_artist,
+		album,
+		artwork,
+		lyricist,
+		composer,
+		genre,
+		label
+	);
+
+	pub fn build(self) -> Song {
+		self.song
+	}
 }
 
 #[derive(Debug, PartialEq, Eq, Queryable, Serialize, Deserialize)]
@@ -127,4 +296,112 @@ pub struct RjRequest {
 	pub prev: Option<String>,
 	pub next: Option<String>,
 	pub next_next: Option<String>,
+	/// Set to false to skip the announcement for this transition.
+	/// None follows the user preference, or enable_by_default without one.
+	pub announce: Option<bool>,
+}
+
+/// Restricts which kind of collection files a search returns.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchKind {
+	All,
+	Songs,
+	Directories,
+}
+
+impl Default for SearchKind {
+	fn default() -> Self {
+		Self::All
+	}
+}
+
+impl SearchKind {
+	pub fn includes_songs(self) -> bool {
+		self != Self::Directories
+	}
+
+	pub fn includes_directories(self) -> bool {
+		self != Self::Songs
+	}
+}
+
+/// Fields of a collection file a search can match on.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchField {
+	Path,
+	Title,
+	Artist,
+	AlbumArtist,
+	Album,
+	Lyricist,
+	Composer,
+	Genre,
+	Year,
+	Duration,
+	TrackNumber,
+}
+
+/// Order in which songs under a directory are listed.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SongOrder {
+	Path,
+	Random,
+	/// Songs from the most recently added directories first.
+	RecentlyAdded,
+}
+
+impl Default for SongOrder {
+	fn default() -> Self {
+		Self::Path
+	}
+}
+
+/// Order in which the content of a directory is listed.
+/// Songs have no date of their own, so they stay in path order when ordered by date added.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BrowseOrder {
+	Path,
+	Album,
+	/// Oldest first, entries without a year last.
+	Year,
+	/// Most recently added first.
+	DateAdded,
+}
+
+impl Default for BrowseOrder {
+	fn default() -> Self {
+		Self::Path
+	}
+}
+
+/// Number of entries written to the index by the current or last update.
+/// Counts only move when a batch of entries is flushed to the database.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexProgress {
+	pub directories: u64,
+	pub songs: u64,
+}
+
+/// A search result along with the fields that made it match.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchResult {
+	pub file: CollectionFile,
+	pub matched_fields: Vec<SearchField>,
+}
+
+/// Parameters of a search.
+/// Folding accents makes "bjork" find "Björk" and so can return more matches than the default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SearchOptions {
+	pub kind: SearchKind,
+	/// None returns every match.
+	pub max_results: Option<i64>,
+	pub fold_accents: bool,
+	/// Generic searches also return near matches, like "guitars" for "Guitar", best first.
+	/// This is slower than the default substring match.
+	pub fuzzy: bool,
 }
@@ -1,4 +1,6 @@
 use log::{error, info};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time;
 
 mod cleaner;
@@ -13,6 +15,7 @@ use crate::db;
 use cleaner::Cleaner;
 use collector::Collector;
 use inserter::Inserter;
+pub use inserter::Progress;
 use traverser::Traverser;
 
 #[derive(thiserror::Error, Debug)]
@@ -21,10 +24,43 @@ pub enum Error {
 	IndexClean(#[from] cleaner::Error),
 	#[error(transparent)]
 	Database(#[from] diesel::result::Error),
+	#[error("Could not insert {0} batches of entries in the index")]
+	Insertion(usize),
 	#[error(transparent)]
 	DatabaseConnection(#[from] db::Error),
 	#[error(transparent)]
 	Vfs(#[from] vfs::Error),
+	#[error("Index update was cancelled")]
+	Cancelled,
+}
+
+/// Lets an in-progress index update be aborted.
+/// Entries that were received but not yet written are discarded or written, as requested.
+#[derive(Debug, Default)]
+pub struct Cancellation {
+	requested: AtomicBool,
+	discard_pending: AtomicBool,
+}
+
+impl Cancellation {
+	pub fn cancel(&self, discard_pending: bool) {
+		self.discard_pending
+			.store(discard_pending, Ordering::SeqCst);
+		self.requested.store(true, Ordering::SeqCst);
+	}
+
+	pub fn is_cancelled(&self) -> bool {
+		self.requested.load(Ordering::SeqCst)
+	}
+
+	fn discards_pending(&self) -> bool {
+		self.discard_pending.load(Ordering::SeqCst)
+	}
+
+	fn reset(&self) {
+		self.requested.store(false, Ordering::SeqCst);
+		self.discard_pending.store(false, Ordering::SeqCst);
+	}
 }
 
 impl Index {
@@ -32,28 +68,61 @@ impl Index {
 		let start = time::Instant::now();
 		info!("Beginning library index update");
 
+		// Cancelling from here on, even while cleaning, stops this update.
+		self.update_cancellation.reset();
+
 		let album_art_pattern = self.settings_manager.get_index_album_art_pattern().ok();
 
 		let cleaner = Cleaner::new(self.db.clone(), self.vfs_manager.clone());
 		cleaner.clean()?;
+		if self.update_cancellation.is_cancelled() {
+			info!("Library index update was cancelled");
+			return Err(Error::Cancelled);
+		}
+
+		self.update_progress.directories.store(0, Ordering::Relaxed);
+		self.update_progress.songs.store(0, Ordering::Relaxed);
 
 		let (insert_sender, insert_receiver) = crossbeam_channel::unbounded();
-		let inserter_db = self.db.clone();
+		let mut inserter = Inserter::new(self.db.clone(), insert_receiver)
+			.with_progress(self.update_progress.clone())
+			.with_cancellation(self.update_cancellation.clone());
+		let key = "POLARIS_INDEX_INSERT_BUFFER_SIZE";
+		let buffer_size = std::env::var_os(key)
+			.map(|v| v.to_string_lossy().to_string())
+			.and_then(|v| usize::from_str(&v).ok())
+			.or_else(|| {
+				self.settings_manager
+					.get_index_insert_buffer_size()
+					.ok()
+					.flatten()
+			});
+		if let Some(buffer_size) = buffer_size {
+			info!("Inserting index entries in batches of {}", buffer_size);
+			inserter = inserter.with_buffer_size(buffer_size);
+		}
+		let failed_batches = inserter.failed_batches();
 		let insertion_thread = std::thread::spawn(move || {
-			let mut inserter = Inserter::new(inserter_db, insert_receiver);
 			inserter.insert();
 		});
 
 		let (collect_sender, collect_receiver) = crossbeam_channel::unbounded();
+		let collector_cancellation = self.update_cancellation.clone();
 		let collector_thread = std::thread::spawn(move || {
-			let collector = Collector::new(collect_receiver, insert_sender, album_art_pattern);
+			let collector = Collector::new(
+				collect_receiver,
+				insert_sender,
+				album_art_pattern,
+				collector_cancellation,
+			);
 			collector.collect();
 		});
 
 		let vfs = self.vfs_manager.get_vfs()?;
+		let traverser_cancellation = self.update_cancellation.clone();
 		let traverser_thread = std::thread::spawn(move || {
 			let mounts = vfs.mounts();
-			let traverser = Traverser::new(collect_sender);
+			let traverser = Traverser::new(collect_sender, traverser_cancellation);
 			traverser.traverse(mounts.iter().map(|p| p.source.clone()).collect());
 		});
 
@@ -74,6 +143,14 @@ impl Index {
 			start.elapsed().as_millis() as f32 / 1000.0
 		);
 
-		Ok(())
+		if self.update_cancellation.is_cancelled() {
+			info!("Library index update was cancelled");
+			return Err(Error::Cancelled);
+		}
+
+		match failed_batches.load(Ordering::SeqCst) {
+			0 => Ok(()),
+			n => Err(Error::Insertion(n)),
+		}
 	}
 }
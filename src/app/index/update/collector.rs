@@ -1,6 +1,7 @@
 use crossbeam_channel::{Receiver, Sender};
 use log::error;
 use regex::Regex;
+use std::sync::Arc;
 
 use super::*;
 
@@ -8,6 +9,7 @@ pub struct Collector {
 	receiver: Receiver<traverser::Directory>,
 	sender: Sender<inserter::Item>,
 	album_art_pattern: Option<Regex>,
+	cancellation: Arc<Cancellation>,
 }
 
 impl Collector {
@@ -15,16 +17,21 @@ impl Collector {
 		receiver: Receiver<traverser::Directory>,
 		sender: Sender<inserter::Item>,
 		album_art_pattern: Option<Regex>,
+		cancellation: Arc<Cancellation>,
 	) -> Self {
 		Self {
 			receiver,
 			sender,
 			album_art_pattern,
+			cancellation,
 		}
 	}
 
 	pub fn collect(&self) {
 		while let Ok(directory) = self.receiver.recv() {
+			if self.cancellation.is_cancelled() {
+				return;
+			}
 			self.collect_directory(directory);
 		}
 	}
@@ -1,10 +1,17 @@
-use crossbeam_channel::Receiver;
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
 use diesel::prelude::*;
+use diesel::upsert::excluded;
 use log::error;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
 
+use super::Cancellation;
 use crate::db::{directories, songs, DB};
 
-const INDEX_BUILDING_INSERT_BUFFER_SIZE: usize = 1000; // Insertions in each transaction
+const DEFAULT_INSERT_BUFFER_SIZE: usize = 1000; // Insertions in each transaction
+const NUM_FLUSH_THREADS: usize = 2;
 
 #[derive(Debug, Insertable)]
 #[diesel(table_name = songs)]
@@ -43,28 +50,88 @@ pub enum Item {
 	Song(Song),
 }
 
+#[derive(Debug, Default)]
+pub struct Progress {
+	pub directories: AtomicU64,
+	pub songs: AtomicU64,
+}
+
+enum Batch {
+	Directories(Vec<Directory>),
+	Songs(Vec<Song>),
+}
+
+#[derive(Clone)]
+struct Flusher {
+	db: DB,
+	failed_batches: Arc<AtomicUsize>,
+	progress: Arc<Progress>,
+}
+
 pub struct Inserter {
 	receiver: Receiver<Item>,
 	new_directories: Vec<Directory>,
 	new_songs: Vec<Song>,
-	db: DB,
+	buffer_size: usize,
+	flusher: Flusher,
+	batch_sender: Option<Sender<Batch>>,
+	flush_threads: Vec<JoinHandle<()>>,
+	cancellation: Arc<Cancellation>,
 }
 
 impl Inserter {
 	pub fn new(db: DB, receiver: Receiver<Item>) -> Self {
-		let new_directories = Vec::with_capacity(INDEX_BUILDING_INSERT_BUFFER_SIZE);
-		let new_songs = Vec::with_capacity(INDEX_BUILDING_INSERT_BUFFER_SIZE);
+		let new_directories = Vec::with_capacity(DEFAULT_INSERT_BUFFER_SIZE);
+		let new_songs = Vec::with_capacity(DEFAULT_INSERT_BUFFER_SIZE);
 		Self {
 			receiver,
 			new_directories,
 			new_songs,
-			db,
+			buffer_size: DEFAULT_INSERT_BUFFER_SIZE,
+			flusher: Flusher {
+				db,
+				failed_batches: Arc::new(AtomicUsize::new(0)),
+				progress: Arc::new(Progress::default()),
+			},
+			batch_sender: None,
+			flush_threads: Vec::new(),
+			cancellation: Arc::new(Cancellation::default()),
 		}
 	}
 
+	// Number of batches that could not be written, readable after the inserter is dropped
+	pub fn failed_batches(&self) -> Arc<AtomicUsize> {
+		self.flusher.failed_batches.clone()
+	}
+
+	pub fn with_buffer_size(mut self, buffer_size: usize) -> Self {
+		self.buffer_size = buffer_size.max(1);
+		self.new_directories = Vec::with_capacity(self.buffer_size);
+		self.new_songs = Vec::with_capacity(self.buffer_size);
+		self
+	}
+
+	pub fn with_progress(mut self, progress: Arc<Progress>) -> Self {
+		self.flusher.progress = progress;
+		self
+	}
+
+	pub fn with_cancellation(mut self, cancellation: Arc<Cancellation>) -> Self {
+		self.cancellation = cancellation;
+		self
+	}
+
 	pub fn insert(&mut self) {
-		while let Ok(item) = self.receiver.recv() {
-			self.insert_item(item);
+		while !self.cancellation.is_cancelled() {
+			match self.receiver.recv_timeout(Duration::from_millis(100)) {
+				Ok(item) => self.insert_item(item),
+				Err(RecvTimeoutError::Timeout) => continue,
+				Err(RecvTimeoutError::Disconnected) => return,
+			}
+		}
+		if self.cancellation.discards_pending() {
+			self.new_directories.clear();
+			self.new_songs.clear();
 		}
 	}
 
@@ -72,13 +139,13 @@ impl Inserter {
 		match insert {
 			Item::Directory(d) => {
 				self.new_directories.push(d);
-				if self.new_directories.len() >= INDEX_BUILDING_INSERT_BUFFER_SIZE {
+				if self.new_directories.len() >= self.buffer_size {
 					self.flush_directories();
 				}
 			}
 			Item::Song(s) => {
 				self.new_songs.push(s);
-				if self.new_songs.len() >= INDEX_BUILDING_INSERT_BUFFER_SIZE {
+				if self.new_songs.len() >= self.buffer_size {
 					self.flush_songs();
 				}
 			}
@@ -86,29 +153,129 @@ impl Inserter {
 	}
 
 	fn flush_directories(&mut self) {
+		let directories = std::mem::replace(
+			&mut self.new_directories,
+			Vec::with_capacity(self.buffer_size),
+		);
+		self.queue_flush(Batch::Directories(directories));
+	}
+
+	fn flush_songs(&mut self) {
+		let songs = std::mem::replace(&mut self.new_songs, Vec::with_capacity(self.buffer_size));
+		self.queue_flush(Batch::Songs(songs));
+	}
+
+	// Batches are written by a small pool of threads so that database writes overlap with
+	// receiving more items. The bounded queue keeps memory in check when writes fall behind.
+	fn queue_flush(&mut self, batch: Batch) {
+		let flush_threads = &mut self.flush_threads;
+		let flusher = &self.flusher;
+		let sender = self.batch_sender.get_or_insert_with(|| {
+			let (sender, receiver) = crossbeam_channel::bounded(NUM_FLUSH_THREADS);
+			for _ in 0..NUM_FLUSH_THREADS {
+				let flusher = flusher.clone();
+				let receiver = receiver.clone();
+				flush_threads.push(std::thread::spawn(move || {
+					while let Ok(batch) = receiver.recv() {
+						flusher.flush(batch);
+					}
+				}));
+			}
+			sender
+		});
+		if sender.send(batch).is_err() {
+			error!("Could not queue index entries for insertion");
+			self.flusher.failed_batches.fetch_add(1, Ordering::SeqCst);
+		}
+	}
+}
+
+impl Flusher {
+	fn flush(&self, batch: Batch) {
+		match batch {
+			Batch::Directories(d) => self.flush_directories(&d),
+			Batch::Songs(s) => self.flush_songs(&s),
+		}
+	}
+
+	// Existing rows are updated in place, which keeps their ids and directory creation dates
+	fn flush_directories(&self, new_directories: &[Directory]) {
 		let res = self.db.connect().ok().and_then(|mut connection| {
-			diesel::insert_into(directories::table)
-				.values(&self.new_directories)
-				.execute(&mut *connection) // TODO https://github.com/diesel-rs/diesel/issues/1822
+			connection
+				.transaction::<_, diesel::result::Error, _>(|connection| {
+					for directory in new_directories {
+						diesel::insert_into(directories::table)
+							.values(directory)
+							.on_conflict(directories::path)
+							.do_update()
+							.set((
+								directories::parent.eq(excluded(directories::parent)),
+								directories::artist.eq(excluded(directories::artist)),
+								directories::year.eq(excluded(directories::year)),
+								directories::album.eq(excluded(directories::album)),
+								directories::artwork.eq(excluded(directories::artwork)),
+							))
+							.execute(connection)?;
+					}
+					Ok(())
+				})
 				.ok()
 		});
-		if res.is_none() {
-			error!("Could not insert new directories in database");
+		match res {
+			Some(()) => {
+				let count = new_directories.len() as u64;
+				self.progress
+					.directories
+					.fetch_add(count, Ordering::Relaxed);
+			}
+			None => {
+				error!("Could not insert new directories in database");
+				self.failed_batches.fetch_add(1, Ordering::SeqCst);
+			}
 		}
-		self.new_directories.clear();
 	}
 
-	fn flush_songs(&mut self) {
+	fn flush_songs(&self, new_songs: &[Song]) {
 		let res = self.db.connect().ok().and_then(|mut connection| {
-			diesel::insert_into(songs::table)
-				.values(&self.new_songs)
-				.execute(&mut *connection) // TODO https://github.com/diesel-rs/diesel/issues/1822
+			connection
+				.transaction::<_, diesel::result::Error, _>(|connection| {
+					for song in new_songs {
+						diesel::insert_into(songs::table)
+							.values(song)
+							.on_conflict(songs::path)
+							.do_update()
+							.set((
+								songs::parent.eq(excluded(songs::parent)),
+								songs::track_number.eq(excluded(songs::track_number)),
+								songs::disc_number.eq(excluded(songs::disc_number)),
+								songs::title.eq(excluded(songs::title)),
+								songs::artist.eq(excluded(songs::artist)),
+								songs::album_artist.eq(excluded(songs::album_artist)),
+								songs::year.eq(excluded(songs::year)),
+								songs::album.eq(excluded(songs::album)),
+								songs::artwork.eq(excluded(songs::artwork)),
+								songs::duration.eq(excluded(songs::duration)),
+								songs::lyricist.eq(excluded(songs::lyricist)),
+								songs::composer.eq(excluded(songs::composer)),
+								songs::genre.eq(excluded(songs::genre)),
+								songs::label.eq(excluded(songs::label)),
+							))
+							.execute(connection)?;
+					}
+					Ok(())
+				})
 				.ok()
 		});
-		if res.is_none() {
-			error!("Could not insert new songs in database");
+		match res {
+			Some(()) => {
+				let count = new_songs.len() as u64;
+				self.progress.songs.fetch_add(count, Ordering::Relaxed);
+			}
+			None => {
+				error!("Could not insert new songs in database");
+				self.failed_batches.fetch_add(1, Ordering::SeqCst);
+			}
 		}
-		self.new_songs.clear();
 	}
 }
 
@@ -120,5 +287,12 @@ impl Drop for Inserter {
 		if !self.new_songs.is_empty() {
 			self.flush_songs();
 		}
+		// Closing the queue lets flush threads exit once every pending batch is written
+		self.batch_sender = None;
+		for thread in self.flush_threads.drain(..) {
+			if let Err(e) = thread.join() {
+				error!("Error joining on flush thread: {:?}", e);
+			}
+		}
 	}
 }
@@ -9,6 +9,7 @@ use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
+use super::Cancellation;
 use crate::app::index::metadata::{self, SongTags};
 
 #[derive(Debug)]
@@ -28,6 +29,7 @@ pub struct Directory {
 
 pub struct Traverser {
 	directory_sender: Sender<Directory>,
+	cancellation: Arc<Cancellation>,
 }
 
 #[derive(Debug)]
@@ -37,8 +39,11 @@ struct WorkItem {
 }
 
 impl Traverser {
-	pub fn new(directory_sender: Sender<Directory>) -> Self {
-		Self { directory_sender }
+	pub fn new(directory_sender: Sender<Directory>, cancellation: Arc<Cancellation>) -> Self {
+		Self {
+			directory_sender,
+			cancellation,
+		}
 	}
 
 	pub fn traverse(&self, roots: Vec<PathBuf>) {
@@ -58,12 +63,14 @@ impl Traverser {
 			let work_item_receiver = work_item_receiver.clone();
 			let directory_sender = self.directory_sender.clone();
 			let num_pending_work_items = num_pending_work_items.clone();
+			let cancellation = self.cancellation.clone();
 			threads.push(thread::spawn(move || {
 				let worker = Worker {
 					work_item_sender,
 					work_item_receiver,
 					directory_sender,
 					num_pending_work_items,
+					cancellation,
 				};
 				worker.run();
 			}));
@@ -92,6 +99,7 @@ struct Worker {
 	work_item_receiver: Receiver<WorkItem>,
 	directory_sender: Sender<Directory>,
 	num_pending_work_items: Arc<AtomicUsize>,
+	cancellation: Arc<Cancellation>,
 }
 
 impl Worker {
@@ -104,7 +112,7 @@ impl Worker {
 
 	fn find_work_item(&self) -> Option<WorkItem> {
 		loop {
-			if self.is_all_work_done() {
+			if self.is_all_work_done() || self.cancellation.is_cancelled() {
 				return None;
 			}
 			if let Ok(w) = self
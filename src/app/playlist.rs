@@ -2,16 +2,18 @@ use core::clone::Clone;
 use diesel::prelude::*;
 use diesel::BelongingToDsl;
 use serde::{Deserialize, Serialize};
-use std::ffi::{OsStr, OsString};
+use std::collections::HashSet;
 use std::fmt::Write;
-use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+use std::path::Path;
 
 use crate::app::index::Song;
 use crate::app::vfs;
 use crate::db::{self, playlist_songs, playlists, songs, users, DB};
 
+mod jspf;
 mod m3u;
 
+pub use jspf::*;
 pub use m3u::*;
 
 #[derive(thiserror::Error, Debug)]
@@ -24,14 +26,19 @@ pub enum Error {
 	UserNotFound,
 	#[error("Playlist not found: {0}")]
 	PlaylistNotFound(String),
+	#[error("Playlist already exists: {0}")]
+	PlaylistAlreadyExists(String),
 	#[error(transparent)]
 	Vfs(#[from] vfs::Error),
+	#[error("Invalid playlist: {0}")]
+	InvalidPlaylist(String),
 }
 
 #[allow(non_camel_case_types)]
-#[derive(Debug, PartialEq, Deserialize, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
 pub enum PlaylistType {
 	m3u,
+	jspf,
 }
 
 impl Default for PlaylistType {
@@ -40,10 +47,32 @@ impl Default for PlaylistType {
 	}
 }
 
+impl PlaylistType {
+	pub fn extension(&self) -> &'static str {
+		match self {
+			Self::m3u => "m3u",
+			Self::jspf => "jspf",
+		}
+	}
+}
+
+// Duplicate songs to drop when saving a playlist.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Dedupe {
+	// Only songs repeated back to back.
+	Consecutive,
+	// Every repetition of a song after its first occurrence.
+	All,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct PlaylistExport {
 	pub name: String,
 	pub kind: Option<PlaylistType>,
+
+	// If true, songs are listed with their virtual paths instead of their real paths.
+	pub virtual_paths: Option<bool>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -61,6 +90,15 @@ pub struct PlaylistImport {
 	pub fuzzy_match: Option<bool>,
 }
 
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PlaylistStats {
+	// Number of entries, including songs missing from the collection.
+	pub song_count: i64,
+
+	// Total duration in seconds. Songs without a known duration count as zero.
+	pub duration: i64,
+}
+
 #[derive(Clone)]
 pub struct Manager {
 	db: DB,
@@ -94,11 +132,88 @@ impl Manager {
 		}
 	}
 
+	/// Names of the playlists of `owner` whose name contains `query`.
+	/// Like SQLite's LIKE, only ASCII letters are matched regardless of case.
+	pub fn search_playlists(&self, owner: &str, query: &str) -> Result<Vec<String>, Error> {
+		let mut connection = self.db.connect()?;
+		// Wildcards in the query are matched literally.
+		let escaped_query = query
+			.replace('\\', "\\\\")
+			.replace('%', "\\%")
+			.replace('_', "\\_");
+
+		let user: User = {
+			use self::users::dsl::*;
+			users
+				.filter(name.eq(owner))
+				.select((id,))
+				.first(&mut connection)
+				.optional()?
+				.ok_or(Error::UserNotFound)?
+		};
+
+		{
+			use self::playlists::dsl::*;
+			let found_playlists: Vec<String> = Playlist::belonging_to(&user)
+				.filter(name.like(format!("%{}%", escaped_query)).escape('\\'))
+				.select(name)
+				.order(name)
+				.load(&mut connection)?;
+			Ok(found_playlists)
+		}
+	}
+
+	/// Names of the playlists of `owner` that include the song at `virtual_path`.
+	pub fn playlists_containing(
+		&self,
+		owner: &str,
+		virtual_path: &Path,
+	) -> Result<Vec<String>, Error> {
+		let vfs = self.vfs_manager.get_vfs()?;
+		let real_path = vfs.virtual_to_real(virtual_path)?;
+		let real_path = real_path.to_string_lossy();
+		let mut connection = self.db.connect()?;
+
+		let user: User = {
+			use self::users::dsl::*;
+			users
+				.filter(name.eq(owner))
+				.select((id,))
+				.first(&mut connection)
+				.optional()?
+				.ok_or(Error::UserNotFound)?
+		};
+
+		{
+			use self::playlists::dsl::*;
+			let found_playlists: Vec<String> = playlists
+				.inner_join(playlist_songs::table)
+				.filter(owner.eq(user.id))
+				.filter(playlist_songs::path.eq(real_path.as_ref()))
+				.select(name)
+				.distinct()
+				.order(name)
+				.load(&mut connection)?;
+			Ok(found_playlists)
+		}
+	}
+
 	pub fn save_playlist(
 		&self,
 		playlist_name: &str,
 		owner: &str,
 		content: &[String],
+	) -> Result<(), Error> {
+		self.save_playlist_with_dedupe(playlist_name, owner, content, None)
+	}
+
+	/// Saves a playlist, optionally dropping songs that are listed more than once.
+	pub fn save_playlist_with_dedupe(
+		&self,
+		playlist_name: &str,
+		owner: &str,
+		content: &[String],
+		dedupe: Option<Dedupe>,
 	) -> Result<(), Error> {
 		let new_playlist: NewPlaylist;
 		let playlist: Playlist;
@@ -139,6 +254,7 @@ impl Manager {
 
 		let mut new_songs: Vec<NewPlaylistSong> = Vec::new();
 		new_songs.reserve(content.len());
+		let mut seen_paths = HashSet::new();
 
 		for (i, path) in content.iter().enumerate() {
 			let virtual_path = Path::new(&path);
@@ -147,6 +263,16 @@ impl Manager {
 				.ok()
 				.and_then(|p| p.to_str().map(|s| s.to_owned()))
 			{
+				let duplicate = match dedupe {
+					Some(Dedupe::Consecutive) => {
+						new_songs.last().map_or(false, |s| s.path == real_path)
+					}
+					Some(Dedupe::All) => !seen_paths.insert(real_path.clone()),
+					None => false,
+				};
+				if duplicate {
+					continue;
+				}
 				new_songs.push(NewPlaylistSong {
 					playlist: playlist.id,
 					path: real_path,
@@ -173,9 +299,84 @@ impl Manager {
 		Ok(())
 	}
 
+	pub fn append_to_playlist(
+		&self,
+		playlist_name: &str,
+		owner: &str,
+		content: &[String],
+	) -> Result<(), Error> {
+		let vfs = self.vfs_manager.get_vfs()?;
+		let real_paths: Vec<String> = content
+			.iter()
+			.filter_map(|path| {
+				vfs.virtual_to_real(Path::new(path))
+					.ok()
+					.and_then(|p| p.to_str().map(|s| s.to_owned()))
+			})
+			.collect();
+
+		let mut connection = self.db.connect()?;
+		let pid = find_playlist(&mut connection, playlist_name, owner)?.id;
+		connection.transaction::<_, diesel::result::Error, _>(|connection| {
+			use self::playlist_songs::dsl::*;
+			let last: Option<i32> = playlist_songs
+				.filter(playlist.eq(pid))
+				.select(diesel::dsl::max(ordering))
+				.get_result(connection)?;
+			let first = last.map_or(0, |last| last + 1);
+			let new_songs: Vec<NewPlaylistSong> = real_paths
+				.into_iter()
+				.enumerate()
+				.map(|(i, real_path)| NewPlaylistSong {
+					playlist: pid,
+					path: real_path,
+					ordering: first + i as i32,
+				})
+				.collect();
+			diesel::insert_into(playlist_songs)
+				.values(&new_songs)
+				.execute(&mut *connection)?; // TODO https://github.com/diesel-rs/diesel/issues/1822
+			Ok(())
+		})?;
+		Ok(())
+	}
+
+	/// Removes songs at the given positions and renumbers the songs left behind.
+	/// Positions past the end of the playlist are ignored.
+	pub fn remove_from_playlist(
+		&self,
+		playlist_name: &str,
+		owner: &str,
+		indices: &[usize],
+	) -> Result<(), Error> {
+		let mut connection = self.db.connect()?;
+		let pid = find_playlist(&mut connection, playlist_name, owner)?.id;
+		connection.transaction::<_, diesel::result::Error, _>(|connection| {
+			use self::playlist_songs::dsl::*;
+			let song_ids: Vec<i32> = playlist_songs
+				.filter(playlist.eq(pid))
+				.select(id)
+				.order_by(ordering)
+				.get_results(connection)?;
+			let (removed, kept): (Vec<_>, Vec<_>) = song_ids
+				.into_iter()
+				.enumerate()
+				.partition(|(i, _)| indices.contains(i));
+			let removed: Vec<i32> = removed.into_iter().map(|(_, song_id)| song_id).collect();
+			diesel::delete(playlist_songs.filter(id.eq_any(&removed))).execute(connection)?;
+			for (i, (_, song_id)) in kept.into_iter().enumerate() {
+				diesel::update(playlist_songs.find(song_id))
+					.set(ordering.eq(i as i32))
+					.execute(connection)?;
+			}
+			Ok(())
+		})?;
+		Ok(())
+	}
+
 	pub fn read_playlist_real(&self, playlist_name: &str, owner: &str) -> Result<Vec<Song>, Error> {
-		let songs: Vec<Song>;
-		let song_paths: Vec<String>;
+		let songs: Vec<(i32, Song)>;
+		let entries: Vec<(i32, String)>;
 
 		{
 			let mut connection = self.db.connect()?;
@@ -204,37 +405,45 @@ impl Manager {
 			};
 			let pid = playlist.id;
 
-			song_paths = {
+			entries = {
 				use self::playlist_songs::dsl::*;
 				playlist_songs
 					.filter(playlist.eq(pid))
-					.select(path)
+					.select((id, path))
 					.order_by(ordering)
 					.get_results(&mut connection)?
 			};
 
+			// Songs are keyed by playlist entry rather than by path, so that a song listed
+			// more than once keeps every one of its positions.
 			songs = {
-				use self::playlist_songs::dsl::{path as playlist_path, *};
-				use self::songs::dsl::{id, path, *};
+				use self::playlist_songs::dsl::{
+					id as entry_id, path as playlist_path, playlist, playlist_songs,
+				};
+				use self::songs::dsl::*;
 				playlist_songs
 					.inner_join(songs.on(path.eq(playlist_path)))
+					.filter(playlist.eq(pid))
 					.select((
-						id,
-						path,
-						parent,
-						track_number,
-						disc_number,
-						title,
-						artist,
-						album_artist,
-						year,
-						album,
-						artwork,
-						duration,
-						lyricist,
-						composer,
-						genre,
-						label,
+						entry_id,
+						(
+							id,
+							path,
+							parent,
+							track_number,
+							disc_number,
+							title,
+							artist,
+							album_artist,
+							year,
+							album,
+							artwork,
+							duration,
+							lyricist,
+							composer,
+							genre,
+							label,
+						),
 					))
 					.get_results(&mut connection)?
 			};
@@ -253,24 +462,39 @@ impl Manager {
 			// 	songs = query.get_results(&connection).map_err(anyhow::Error::new)?;
 		}
 
-		let mut map = std::collections::HashMap::new();
-		for (index, song) in songs.iter().enumerate() {
-			map.insert(&song.path, index);
-		}
-		let mut missing_songs = Vec::new();
-		for path in &song_paths {
-			missing_songs.push(match map.get(path) {
-				Some(index) => songs[*index].clone(),
-				None => Song::error_song(path),
-			});
-		}
+		log::trace!("songs {:?}", songs);
+		log::trace!("entries {:?}", entries);
+		let mut map: std::collections::HashMap<i32, Song> = songs.into_iter().collect();
+		let missing_songs: Vec<Song> = entries
+			.into_iter()
+			.map(|(entry, path)| {
+				map.remove(&entry)
+					.unwrap_or_else(|| Song::error_song(&path))
+			})
+			.collect();
 
-		log::error!("missing_songs {:?}", missing_songs);
-		log::error!("songs {:?}", songs);
-		log::error!("paths{:?}", song_paths);
+		log::trace!("missing_songs {:?}", missing_songs);
 		Ok(missing_songs)
 	}
 
+	pub fn playlist_stats(&self, playlist_name: &str, owner: &str) -> Result<PlaylistStats, Error> {
+		let mut connection = self.db.connect()?;
+		let pid = find_playlist(&mut connection, playlist_name, owner)?.id;
+
+		use self::playlist_songs::dsl::{path as playlist_path, *};
+		use self::songs::dsl::{duration, path, songs};
+		let (song_count, total_duration): (i64, Option<i64>) = playlist_songs
+			.left_join(songs.on(path.eq(playlist_path)))
+			.filter(playlist.eq(pid))
+			.select((diesel::dsl::count(id), diesel::dsl::sum(duration)))
+			.get_result(&mut connection)?;
+
+		Ok(PlaylistStats {
+			song_count,
+			duration: total_duration.unwrap_or(0),
+		})
+	}
+
 	pub fn read_playlist(&self, playlist_name: &str, owner: &str) -> Result<Vec<Song>, Error> {
 		let vfs = self.vfs_manager.get_vfs()?;
 		let songs = self.read_playlist_real(playlist_name, owner)?;
@@ -307,9 +531,115 @@ impl Manager {
 		}
 	}
 
+	pub fn rename_playlist(
+		&self,
+		old_name: &str,
+		new_name: &str,
+		owner: &str,
+	) -> Result<(), Error> {
+		let mut connection = self.db.connect()?;
+		let playlist = find_playlist(&mut connection, old_name, owner)?;
+		if old_name == new_name {
+			return Ok(());
+		}
+		match find_playlist(&mut connection, new_name, owner) {
+			Ok(_) => return Err(Error::PlaylistAlreadyExists(new_name.to_string())),
+			Err(Error::PlaylistNotFound(_)) => (),
+			Err(e) => return Err(e),
+		}
+
+		use self::playlists::dsl::*;
+		diesel::update(playlists.find(playlist.id))
+			.set(name.eq(new_name))
+			.execute(&mut connection)?;
+		Ok(())
+	}
+
 	pub fn export_playlist(&self, username: &str, export: PlaylistExport) -> Result<String, Error> {
-		let songs = self.read_playlist_real(&export.name, username)?;
-		create_m3u_playlist(&songs)
+		let songs = match export.virtual_paths.unwrap_or(false) {
+			true => self.read_playlist(&export.name, username)?,
+			false => self.read_playlist_real(&export.name, username)?,
+		};
+		serialize_playlist(&songs, export.kind.unwrap_or_default())
+	}
+
+	/// Saves songs listed in a playlist, exported either by polaris or by other players.
+	/// Listed paths may be real or virtual. Unless a partial import is requested, the
+	/// playlist is not saved when any listed song cannot be found.
+	pub fn import_playlist(
+		&self,
+		username: &str,
+		import: PlaylistImport,
+		content: &str,
+	) -> Result<(), Error> {
+		let vfs = self.vfs_manager.get_vfs()?;
+		let partial = import.partial.unwrap_or(false);
+		let mut virtual_paths = Vec::new();
+		for path in parse_playlist(content, import.kind.unwrap_or_default())? {
+			match resolve_playlist_entry(&vfs, &path) {
+				Some(virtual_path) => virtual_paths.push(virtual_path),
+				None if partial => log::warn!("Skipping missing playlist entry {}", path),
+				None => return Err(vfs::Error::CouldNotMapToVirtualPath(path.into()).into()),
+			}
+		}
+		self.save_playlist(&import.name, username, &virtual_paths)
+	}
+}
+
+// Returns the virtual path of a playlist entry if the song exists.
+fn resolve_playlist_entry(vfs: &vfs::VFS, path: &str) -> Option<String> {
+	if Path::new(path).is_file() {
+		if let Ok(virtual_path) = vfs.real_to_virtual(path) {
+			return virtual_path.to_str().map(|s| s.to_owned());
+		}
+	}
+	vfs.virtual_to_real(path)
+		.ok()
+		.filter(|real_path| real_path.is_file())
+		.map(|_| path.to_owned())
+}
+
+/// Serializes songs into a playlist file of the given type. Songs may come from
+/// anywhere, like a saved playlist or a temporary queue, and their paths are
+/// written as they are.
+pub fn serialize_playlist(songs: &[Song], kind: PlaylistType) -> Result<String, Error> {
+	match kind {
+		PlaylistType::m3u => create_m3u_playlist(songs),
+		PlaylistType::jspf => create_jspf_playlist(songs),
+	}
+}
+
+// Returns the paths listed in a playlist file of the given type.
+fn parse_playlist(content: &str, kind: PlaylistType) -> Result<Vec<String>, Error> {
+	match kind {
+		PlaylistType::m3u => Ok(parse_m3u_playlist(content)),
+		PlaylistType::jspf => parse_jspf_playlist(content),
+	}
+}
+
+fn find_playlist(
+	connection: &mut SqliteConnection,
+	playlist_name: &str,
+	owner: &str,
+) -> Result<Playlist, Error> {
+	let user: User = {
+		use self::users::dsl::*;
+		users
+			.filter(name.eq(owner))
+			.select((id,))
+			.first(connection)
+			.optional()?
+			.ok_or(Error::UserNotFound)?
+	};
+
+	{
+		use self::playlists::dsl::*;
+		playlists
+			.select((id, owner))
+			.filter(name.eq(playlist_name).and(owner.eq(user.id)))
+			.get_result(connection)
+			.optional()?
+			.ok_or_else(|| Error::PlaylistNotFound(playlist_name.to_string()))
 	}
 }
 
@@ -347,38 +677,33 @@ struct User {
 	id: i32,
 }
 
-fn get_common_path(songs: &[Song]) -> Option<OsString> {
-	if songs.len() < 2 {
-		return None;
-	}
-	let mut common_path = PathBuf::from(&songs.get(0).unwrap().path);
+// Paths may come from another system, so both kinds of separators are accepted.
+fn is_separator(c: char) -> bool {
+	c == '/' || c == '\\'
+}
+
+// Returns the longest directory, separator included, that every song path starts with.
+// The prefix is compared as text so that it can always be stripped from the paths as is.
+fn get_common_path(songs: &[Song]) -> Option<String> {
+	let first = &songs.first()?.path;
+	let mut common_len = first.len();
 	for song in &songs[1..] {
-		let next_path = Path::new(&song.path);
-		let iter = common_path.iter().zip(next_path.iter());
-		let mut temp = PathBuf::new();
-		for (c, n) in iter {
-			if c == n {
-				temp.push(c);
-			} else {
-				break;
-			}
-		}
-		common_path = temp;
-		if common_path.as_os_str().is_empty() {
-			return None;
-		}
+		let matching_len = first
+			.char_indices()
+			.zip(song.path.chars())
+			.take_while(|((_, a), b)| a == b)
+			.last()
+			.map_or(0, |((i, c), _)| i + c.len_utf8());
+		common_len = common_len.min(matching_len);
 	}
-	let mut path = common_path.into_os_string();
-	path.push(OsStr::new(&MAIN_SEPARATOR.to_string()));
-	Some(path)
+	let end = first[..common_len].rfind(is_separator)? + 1;
+	Some(first[..end].to_owned())
 }
 
 // Returns (common_path, buffer with with list of files).
 pub(crate) fn strip_base_path(songs: &[Song]) -> (String, String) {
-	let base_path = get_common_path(songs)
-		.unwrap_or_else(|| OsString::from(""))
-		.to_string_lossy()
-		.to_string();
+	// Every song path starts with the common path, as is, so stripping it cannot fail.
+	let base_path = get_common_path(songs).unwrap_or_default();
 	let mut buffer = String::new();
 
 	for song in songs {
@@ -396,6 +721,21 @@ pub(crate) fn strip_base_path(songs: &[Song]) -> (String, String) {
 mod tests {
 	use super::*;
 
+	#[test]
+	fn serialize_songs_without_saving() {
+		let songs = [
+			Song::test_only_from_path("root/a/b.mp3"),
+			Song::test_only_from_path("root/a/c.mp3"),
+		];
+		assert_eq!(
+			serialize_playlist(&songs, PlaylistType::m3u).unwrap(),
+			format!(
+				"{}\n{} {}=root/a/\nb.mp3\nc.mp3\n",
+				M3U_HEADER, M3U_RMIM_FIELDS, M3U_COMMON_PATH
+			)
+		);
+	}
+
 	#[test]
 	fn test_no_songs() {
 		assert_eq!(strip_base_path(&[]), ("".to_string(), "".to_string()));
@@ -405,7 +745,47 @@ mod tests {
 	fn test_single_song() {
 		assert_eq!(
 			strip_base_path(&[Song::test_only_from_path("abc/def")]),
-			("".to_string(), "abc/def\n".to_string())
+			("abc/".to_string(), "def\n".to_string())
+		);
+		assert_eq!(
+			strip_base_path(&[Song::test_only_from_path("def")]),
+			("".to_string(), "def\n".to_string())
+		);
+	}
+
+	#[test]
+	fn test_paths_differing_in_case() {
+		assert_eq!(
+			strip_base_path(&[
+				Song::test_only_from_path("abc/x/def"),
+				Song::test_only_from_path("ABC/x/ghi"),
+				Song::test_only_from_path("abc/x/jkl")
+			]),
+			(
+				"".to_string(),
+				"abc/x/def\nABC/x/ghi\nabc/x/jkl\n".to_string()
+			)
+		);
+	}
+
+	#[test]
+	fn test_mixed_separators() {
+		assert_eq!(
+			strip_base_path(&[
+				Song::test_only_from_path("C:\\music\\abc/def"),
+				Song::test_only_from_path("C:\\music\\abc/ghi")
+			]),
+			("C:\\music\\abc/".to_string(), "def\nghi\n".to_string())
+		);
+		assert_eq!(
+			strip_base_path(&[
+				Song::test_only_from_path("C:\\music\\abc\\def"),
+				Song::test_only_from_path("C:\\music/abc/ghi")
+			]),
+			(
+				"C:\\".to_string(),
+				"music\\abc\\def\nmusic/abc/ghi\n".to_string()
+			)
 		);
 	}
 
@@ -472,8 +852,10 @@ mod test {
 	use std::path::{Path, PathBuf};
 	use std::str::FromStr;
 
+	use crate::app::index::SongOrder;
 	use crate::app::playlist::{
-		strip_base_path, PlaylistExport, PlaylistType, M3U_COMMON_PATH, M3U_HEADER, M3U_RMIM_FIELDS,
+		strip_base_path, Dedupe, Error, PlaylistExport, PlaylistImport, PlaylistType,
+		M3U_COMMON_PATH, M3U_EXTINF, M3U_HEADER, M3U_RMIM_FIELDS,
 	};
 	use crate::app::test;
 	use crate::test_name;
@@ -515,7 +897,7 @@ mod test {
 
 		let playlist_content: Vec<String> = ctx
 			.index
-			.flatten(Path::new(TEST_MOUNT_NAME))
+			.flatten_page(Path::new(TEST_MOUNT_NAME), 0, None, SongOrder::Path)
 			.unwrap()
 			.into_iter()
 			.map(|s| s.path)
@@ -537,6 +919,51 @@ mod test {
 		assert_eq!(songs.len(), TEST_ALL_SONGS_COUNT);
 	}
 
+	#[test]
+	fn save_playlist_with_dedupe() {
+		let ctx = test::ContextBuilder::new(test_name!())
+			.user(TEST_USER, TEST_PASSWORD, false)
+			.mount(TEST_MOUNT_NAME, &test_songs_path())
+			.build();
+
+		ctx.index.update().unwrap();
+
+		let all_songs: Vec<String> = ctx
+			.index
+			.flatten_page(Path::new(TEST_MOUNT_NAME), 0, None, SongOrder::Path)
+			.unwrap()
+			.into_iter()
+			.map(|s| s.path)
+			.collect();
+		let (a, b, c) = (&all_songs[0], &all_songs[1], &all_songs[2]);
+		let playlist_content: Vec<String> = [a, a, b, a, c, c, b]
+			.iter()
+			.map(|s| s.to_string())
+			.collect();
+
+		let saved_paths = |dedupe| {
+			ctx.playlist_manager
+				.save_playlist_with_dedupe(TEST_PLAYLIST_NAME, TEST_USER, &playlist_content, dedupe)
+				.unwrap();
+			ctx.playlist_manager
+				.read_playlist(TEST_PLAYLIST_NAME, TEST_USER)
+				.unwrap()
+				.into_iter()
+				.map(|s| s.path)
+				.collect::<Vec<String>>()
+		};
+
+		assert_eq!(saved_paths(None), playlist_content);
+		assert_eq!(
+			saved_paths(Some(Dedupe::Consecutive)),
+			vec![a.clone(), b.clone(), a.clone(), c.clone(), b.clone()]
+		);
+		assert_eq!(
+			saved_paths(Some(Dedupe::All)),
+			vec![a.clone(), b.clone(), c.clone()]
+		);
+	}
+
 	#[test]
 	fn delete_playlist_golden_path() {
 		let ctx = test::ContextBuilder::new(test_name!())
@@ -568,7 +995,7 @@ mod test {
 
 		let playlist_content: Vec<String> = ctx
 			.index
-			.flatten(Path::new(TEST_MOUNT_NAME))
+			.flatten_page(Path::new(TEST_MOUNT_NAME), 0, None, SongOrder::Path)
 			.unwrap()
 			.into_iter()
 			.map(|s| s.path)
@@ -598,6 +1025,250 @@ mod test {
 		assert_eq!(songs[0].path, first_song_path.to_str().unwrap());
 	}
 
+	#[test]
+	fn read_playlist_with_repeated_song() {
+		let ctx = test::ContextBuilder::new(test_name!())
+			.user(TEST_USER, TEST_PASSWORD, false)
+			.mount(TEST_MOUNT_NAME, &test_songs_path())
+			.build();
+
+		ctx.index.update().unwrap();
+		let all_songs: Vec<String> = ctx
+			.index
+			.flatten_page(Path::new(TEST_MOUNT_NAME), 0, None, SongOrder::Path)
+			.unwrap()
+			.into_iter()
+			.map(|s| s.path)
+			.collect();
+		let playlist_content = vec![
+			all_songs[0].clone(),
+			all_songs[1].clone(),
+			all_songs[0].clone(),
+		];
+		ctx.playlist_manager
+			.save_playlist(TEST_PLAYLIST_NAME, TEST_USER, &playlist_content)
+			.unwrap();
+		// Entries of other playlists must not leak into this one.
+		ctx.playlist_manager
+			.save_playlist("other", TEST_USER, &all_songs[..1])
+			.unwrap();
+
+		let songs = ctx
+			.playlist_manager
+			.read_playlist(TEST_PLAYLIST_NAME, TEST_USER)
+			.unwrap();
+		let paths: Vec<String> = songs.into_iter().map(|s| s.path).collect();
+		assert_eq!(paths, playlist_content);
+	}
+
+	#[test]
+	fn append_and_remove_songs() {
+		let ctx = test::ContextBuilder::new(test_name!())
+			.user(TEST_USER, TEST_PASSWORD, false)
+			.mount(TEST_MOUNT_NAME, &test_songs_path())
+			.build();
+
+		ctx.index.update().unwrap();
+
+		let playlist_content: Vec<String> = ctx
+			.index
+			.flatten_page(Path::new(TEST_MOUNT_NAME), 0, None, SongOrder::Path)
+			.unwrap()
+			.into_iter()
+			.map(|s| s.path)
+			.collect();
+		assert!(matches!(
+			ctx.playlist_manager.append_to_playlist(
+				TEST_PLAYLIST_NAME,
+				TEST_USER,
+				&playlist_content
+			),
+			Err(Error::PlaylistNotFound(_))
+		));
+
+		ctx.playlist_manager
+			.save_playlist(TEST_PLAYLIST_NAME, TEST_USER, &playlist_content[..2])
+			.unwrap();
+		ctx.playlist_manager
+			.append_to_playlist(TEST_PLAYLIST_NAME, TEST_USER, &playlist_content[2..4])
+			.unwrap();
+		let songs = ctx
+			.playlist_manager
+			.read_playlist(TEST_PLAYLIST_NAME, TEST_USER)
+			.unwrap();
+		let paths: Vec<String> = songs.into_iter().map(|s| s.path).collect();
+		assert_eq!(paths, playlist_content[..4]);
+
+		ctx.playlist_manager
+			.remove_from_playlist(TEST_PLAYLIST_NAME, TEST_USER, &[0, 2])
+			.unwrap();
+		ctx.playlist_manager
+			.append_to_playlist(TEST_PLAYLIST_NAME, TEST_USER, &playlist_content[4..5])
+			.unwrap();
+		let songs = ctx
+			.playlist_manager
+			.read_playlist(TEST_PLAYLIST_NAME, TEST_USER)
+			.unwrap();
+		let paths: Vec<String> = songs.into_iter().map(|s| s.path).collect();
+		assert_eq!(
+			paths,
+			vec![
+				playlist_content[1].clone(),
+				playlist_content[3].clone(),
+				playlist_content[4].clone()
+			]
+		);
+	}
+
+	#[test]
+	fn search_playlists_by_name() {
+		let ctx = test::ContextBuilder::new(test_name!())
+			.user(TEST_USER, TEST_PASSWORD, false)
+			.user("other_user", TEST_PASSWORD, false)
+			.build();
+
+		for playlist_name in [TEST_PLAYLIST_NAME, "Grilled", "Workout"] {
+			ctx.playlist_manager
+				.save_playlist(playlist_name, TEST_USER, &Vec::new())
+				.unwrap();
+		}
+		ctx.playlist_manager
+			.save_playlist("Grill party", "other_user", &Vec::new())
+			.unwrap();
+
+		let found_playlists = ctx
+			.playlist_manager
+			.search_playlists(TEST_USER, "grill")
+			.unwrap();
+		assert_eq!(found_playlists, vec![TEST_PLAYLIST_NAME, "Grilled"]);
+
+		ctx.playlist_manager
+			.save_playlist("100% grill_master", TEST_USER, &Vec::new())
+			.unwrap();
+		for query in ["0%", "l_m"] {
+			let found_playlists = ctx
+				.playlist_manager
+				.search_playlists(TEST_USER, query)
+				.unwrap();
+			assert_eq!(found_playlists, vec!["100% grill_master"]);
+		}
+		assert!(ctx
+			.playlist_manager
+			.search_playlists(TEST_USER, "l%m")
+			.unwrap()
+			.is_empty());
+		assert!(matches!(
+			ctx.playlist_manager
+				.search_playlists("missing_user", "grill"),
+			Err(Error::UserNotFound)
+		));
+	}
+
+	#[test]
+	fn find_playlists_containing_song() {
+		let ctx = test::ContextBuilder::new(test_name!())
+			.user(TEST_USER, TEST_PASSWORD, false)
+			.mount(TEST_MOUNT_NAME, &test_songs_path())
+			.build();
+
+		ctx.index.update().unwrap();
+
+		let playlist_content: Vec<String> = ctx
+			.index
+			.flatten_page(Path::new(TEST_MOUNT_NAME), 0, None, SongOrder::Path)
+			.unwrap()
+			.into_iter()
+			.map(|s| s.path)
+			.collect();
+		ctx.playlist_manager
+			.save_playlist(TEST_PLAYLIST_NAME, TEST_USER, &playlist_content[..2])
+			.unwrap();
+		ctx.playlist_manager
+			.save_playlist("Later", TEST_USER, &playlist_content[1..3])
+			.unwrap();
+
+		let containing = |song: &String| {
+			ctx.playlist_manager
+				.playlists_containing(TEST_USER, Path::new(song))
+				.unwrap()
+		};
+		assert_eq!(containing(&playlist_content[0]), vec![TEST_PLAYLIST_NAME]);
+		assert_eq!(
+			containing(&playlist_content[1]),
+			vec![TEST_PLAYLIST_NAME, "Later"]
+		);
+		assert!(containing(&playlist_content[3]).is_empty());
+	}
+
+	#[test]
+	fn rename_playlist_golden_path() {
+		let ctx = test::ContextBuilder::new(test_name!())
+			.user(TEST_USER, TEST_PASSWORD, false)
+			.build();
+
+		ctx.playlist_manager
+			.save_playlist(TEST_PLAYLIST_NAME, TEST_USER, &Vec::new())
+			.unwrap();
+		ctx.playlist_manager
+			.save_playlist("Taken", TEST_USER, &Vec::new())
+			.unwrap();
+
+		assert!(matches!(
+			ctx.playlist_manager
+				.rename_playlist("Missing", "Renamed", TEST_USER),
+			Err(Error::PlaylistNotFound(_))
+		));
+		assert!(matches!(
+			ctx.playlist_manager
+				.rename_playlist(TEST_PLAYLIST_NAME, "Taken", TEST_USER),
+			Err(Error::PlaylistAlreadyExists(_))
+		));
+
+		ctx.playlist_manager
+			.rename_playlist(TEST_PLAYLIST_NAME, "Renamed", TEST_USER)
+			.unwrap();
+		let mut found_playlists = ctx.playlist_manager.list_playlists(TEST_USER).unwrap();
+		found_playlists.sort();
+		assert_eq!(found_playlists, vec!["Renamed", "Taken"]);
+	}
+
+	#[test]
+	fn playlist_stats_include_missing_songs() {
+		let ctx = test::ContextBuilder::new(test_name!())
+			.user(TEST_USER, TEST_PASSWORD, false)
+			.mount(TEST_MOUNT_NAME, &test_songs_path())
+			.build();
+
+		ctx.index.update().unwrap();
+		let all_songs = ctx
+			.index
+			.flatten_page(Path::new(TEST_MOUNT_NAME), 0, None, SongOrder::Path)
+			.unwrap();
+		let mut playlist_content: Vec<String> = all_songs.iter().map(|s| s.path.clone()).collect();
+		playlist_content.push(format!("{}-not-found.mp3", playlist_content[0]));
+		ctx.playlist_manager
+			.save_playlist(TEST_PLAYLIST_NAME, TEST_USER, &playlist_content)
+			.unwrap();
+
+		let stats = ctx
+			.playlist_manager
+			.playlist_stats(TEST_PLAYLIST_NAME, TEST_USER)
+			.unwrap();
+		assert_eq!(stats.song_count, TEST_ALL_SONGS_COUNT as i64 + 1);
+		assert_eq!(
+			stats.duration,
+			all_songs
+				.iter()
+				.map(|s| s.duration.unwrap_or(0) as i64)
+				.sum::<i64>()
+		);
+
+		assert!(matches!(
+			ctx.playlist_manager.playlist_stats("Missing", TEST_USER),
+			Err(Error::PlaylistNotFound(_))
+		));
+	}
+
 	#[test]
 	fn read_playlist_with_broken_path() {
 		let ctx = test::ContextBuilder::new(test_name!())
@@ -608,7 +1279,7 @@ mod test {
 		ctx.index.update().unwrap();
 		let mut playlist_content: Vec<String> = ctx
 			.index
-			.flatten(Path::new(TEST_MOUNT_NAME))
+			.flatten_page(Path::new(TEST_MOUNT_NAME), 0, None, SongOrder::Path)
 			.unwrap()
 			.into_iter()
 			.map(|s| s.path)
@@ -636,7 +1307,9 @@ mod test {
 		.iter()
 		.collect();
 		assert_eq!(songs[0].path, first_song_path.to_str().unwrap());
+		assert!(!songs[0].error);
 		let error_song = &songs[songs.len() - 1];
+		assert!(error_song.error);
 		let mut error_song_real_path = PathBuf::from_str(&test_songs_path()).unwrap();
 		error_song_real_path.push("Khemmis");
 		error_song_real_path.push("Hunted");
@@ -660,7 +1333,10 @@ mod test {
 
 		ctx.index.update().unwrap();
 
-		let all_songs = ctx.index.flatten(Path::new(TEST_MOUNT_NAME)).unwrap();
+		let all_songs = ctx
+			.index
+			.flatten_page(Path::new(TEST_MOUNT_NAME), 0, None, SongOrder::Path)
+			.unwrap();
 		let playlist_content: Vec<String> = all_songs.iter().map(|s| s.path.clone()).collect();
 		assert_eq!(playlist_content.len(), TEST_ALL_SONGS_COUNT);
 		ctx.playlist_manager
@@ -683,6 +1359,7 @@ mod test {
 				PlaylistExport {
 					name: TEST_PLAYLIST_NAME.to_string(),
 					kind: Some(PlaylistType::m3u),
+					virtual_paths: None,
 				},
 			)
 			.unwrap();
@@ -691,6 +1368,230 @@ mod test {
 			"{}\n{} {}={}\n{}",
 			M3U_HEADER, M3U_RMIM_FIELDS, M3U_COMMON_PATH, common_path, buffer
 		);
-		assert_eq!(expected, found);
+		let (info, paths): (Vec<&str>, Vec<&str>) =
+			found.lines().partition(|l| l.starts_with(M3U_EXTINF));
+		assert_eq!(expected, paths.join("\n") + "\n");
+		assert_eq!(
+			info.len(),
+			all_songs.iter().filter(|s| s.title.is_some()).count()
+		);
+	}
+
+	#[test]
+	fn test_export_playlist_with_virtual_paths() {
+		let ctx = test::ContextBuilder::new(test_name!())
+			.user(TEST_USER, TEST_PASSWORD, false)
+			.mount(TEST_MOUNT_NAME, "test-data/small-collection")
+			.build();
+
+		ctx.index.update().unwrap();
+
+		let all_songs = ctx
+			.index
+			.flatten_page(Path::new(TEST_MOUNT_NAME), 0, None, SongOrder::Path)
+			.unwrap();
+		let playlist_content: Vec<String> = all_songs.iter().map(|s| s.path.clone()).collect();
+		ctx.playlist_manager
+			.save_playlist(TEST_PLAYLIST_NAME, TEST_USER, &playlist_content)
+			.unwrap();
+
+		let found = ctx
+			.playlist_manager
+			.export_playlist(
+				TEST_USER,
+				PlaylistExport {
+					name: TEST_PLAYLIST_NAME.to_string(),
+					kind: Some(PlaylistType::m3u),
+					virtual_paths: Some(true),
+				},
+			)
+			.unwrap();
+		let (common_path, buffer) = strip_base_path(&all_songs);
+		assert!(common_path.starts_with(TEST_MOUNT_NAME));
+		let expected = format!(
+			"{}\n{} {}={}\n{}",
+			M3U_HEADER, M3U_RMIM_FIELDS, M3U_COMMON_PATH, common_path, buffer
+		);
+		let (_, paths): (Vec<&str>, Vec<&str>) =
+			found.lines().partition(|l| l.starts_with(M3U_EXTINF));
+		assert_eq!(expected, paths.join("\n") + "\n");
+	}
+
+	#[test]
+	fn test_import_playlist() {
+		let ctx = test::ContextBuilder::new(test_name!())
+			.user(TEST_USER, TEST_PASSWORD, false)
+			.mount(TEST_MOUNT_NAME, "test-data/small-collection")
+			.build();
+
+		ctx.index.update().unwrap();
+
+		let all_songs = ctx
+			.index
+			.flatten_page(Path::new(TEST_MOUNT_NAME), 0, None, SongOrder::Path)
+			.unwrap();
+		let playlist_content: Vec<String> = all_songs.iter().map(|s| s.path.clone()).collect();
+		ctx.playlist_manager
+			.save_playlist(TEST_PLAYLIST_NAME, TEST_USER, &playlist_content)
+			.unwrap();
+		let exported = ctx
+			.playlist_manager
+			.export_playlist(
+				TEST_USER,
+				PlaylistExport {
+					name: TEST_PLAYLIST_NAME.to_string(),
+					kind: Some(PlaylistType::m3u),
+					virtual_paths: None,
+				},
+			)
+			.unwrap();
+
+		let import = |name: &str, partial: bool, content: &str| {
+			ctx.playlist_manager.import_playlist(
+				TEST_USER,
+				PlaylistImport {
+					name: name.to_string(),
+					kind: Some(PlaylistType::m3u),
+					partial: Some(partial),
+					fuzzy_match: None,
+				},
+				content,
+			)
+		};
+
+		import("Imported", false, &exported).unwrap();
+		let songs = ctx
+			.playlist_manager
+			.read_playlist("Imported", TEST_USER)
+			.unwrap();
+		assert_eq!(songs.len(), TEST_ALL_SONGS_COUNT);
+		assert_eq!(songs[0].path, playlist_content[0]);
+
+		let broken = format!("{}{}-not-found.mp3\n", exported, playlist_content[0]);
+		assert!(import("Broken", false, &broken).is_err());
+		assert!(!ctx
+			.playlist_manager
+			.list_playlists(TEST_USER)
+			.unwrap()
+			.contains(&"Broken".to_owned()));
+
+		import("Partial", true, &broken).unwrap();
+		let songs = ctx
+			.playlist_manager
+			.read_playlist("Partial", TEST_USER)
+			.unwrap();
+		assert_eq!(songs.len(), TEST_ALL_SONGS_COUNT);
+	}
+
+	#[test]
+	fn test_import_jspf_playlist() {
+		let ctx = test::ContextBuilder::new(test_name!())
+			.user(TEST_USER, TEST_PASSWORD, false)
+			.mount(TEST_MOUNT_NAME, "test-data/small-collection")
+			.build();
+
+		ctx.index.update().unwrap();
+
+		let all_songs = ctx
+			.index
+			.flatten_page(Path::new(TEST_MOUNT_NAME), 0, None, SongOrder::Path)
+			.unwrap();
+		let playlist_content: Vec<String> = all_songs.iter().map(|s| s.path.clone()).collect();
+		ctx.playlist_manager
+			.save_playlist(TEST_PLAYLIST_NAME, TEST_USER, &playlist_content)
+			.unwrap();
+		let exported = ctx
+			.playlist_manager
+			.export_playlist(
+				TEST_USER,
+				PlaylistExport {
+					name: TEST_PLAYLIST_NAME.to_string(),
+					kind: Some(PlaylistType::jspf),
+					virtual_paths: None,
+				},
+			)
+			.unwrap();
+
+		let import = |content: &str| {
+			ctx.playlist_manager.import_playlist(
+				TEST_USER,
+				PlaylistImport {
+					name: "Imported".to_string(),
+					kind: Some(PlaylistType::jspf),
+					partial: None,
+					fuzzy_match: None,
+				},
+				content,
+			)
+		};
+
+		import(&exported).unwrap();
+		let songs = ctx
+			.playlist_manager
+			.read_playlist("Imported", TEST_USER)
+			.unwrap();
+		assert_eq!(songs.len(), TEST_ALL_SONGS_COUNT);
+		assert_eq!(songs[0].path, playlist_content[0]);
+
+		assert!(matches!(
+			import("#EXTM3U\n"),
+			Err(Error::InvalidPlaylist(_))
+		));
+	}
+
+	#[test]
+	fn test_import_exported_playlist_without_common_path() {
+		let ctx = test::ContextBuilder::new(test_name!())
+			.user(TEST_USER, TEST_PASSWORD, false)
+			.mount("khemmis", "test-data/small-collection/Khemmis")
+			.mount("tobokegao", "test-data/small-collection/Tobokegao")
+			.build();
+
+		ctx.index.update().unwrap();
+
+		let mut playlist_content: Vec<String> = Vec::new();
+		for mount in ["khemmis", "tobokegao"] {
+			let songs = ctx
+				.index
+				.flatten_page(Path::new(mount), 0, None, SongOrder::Path)
+				.unwrap();
+			playlist_content.extend(songs.iter().map(|s| s.path.clone()));
+		}
+		ctx.playlist_manager
+			.save_playlist(TEST_PLAYLIST_NAME, TEST_USER, &playlist_content)
+			.unwrap();
+		let exported = ctx
+			.playlist_manager
+			.export_playlist(
+				TEST_USER,
+				PlaylistExport {
+					name: TEST_PLAYLIST_NAME.to_string(),
+					kind: Some(PlaylistType::m3u),
+					virtual_paths: Some(true),
+				},
+			)
+			.unwrap();
+		assert!(!exported.contains(M3U_COMMON_PATH));
+
+		ctx.playlist_manager
+			.import_playlist(
+				TEST_USER,
+				PlaylistImport {
+					name: "Imported".to_string(),
+					kind: Some(PlaylistType::m3u),
+					partial: None,
+					fuzzy_match: None,
+				},
+				&exported,
+			)
+			.unwrap();
+		let songs: Vec<String> = ctx
+			.playlist_manager
+			.read_playlist("Imported", TEST_USER)
+			.unwrap()
+			.into_iter()
+			.map(|s| s.path)
+			.collect();
+		assert_eq!(songs, playlist_content);
 	}
 }
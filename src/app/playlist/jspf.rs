@@ -0,0 +1,117 @@
+use serde::{Deserialize, Serialize};
+
+use super::*;
+use crate::app::index::Song;
+
+// See https://www.xspf.org/jspf for the full format. Only the fields polaris knows
+// about are written, and only locations are needed to import a playlist.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Jspf {
+	playlist: JspfPlaylist,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct JspfPlaylist {
+	#[serde(default)]
+	track: Vec<JspfTrack>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct JspfTrack {
+	#[serde(default)]
+	location: Vec<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	title: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	creator: Option<String>,
+	// Milliseconds
+	#[serde(skip_serializing_if = "Option::is_none")]
+	duration: Option<i64>,
+}
+
+impl From<&Song> for JspfTrack {
+	fn from(song: &Song) -> Self {
+		Self {
+			location: vec![song.path.clone()],
+			title: song.title.clone(),
+			creator: song.artist.clone(),
+			duration: song.duration.map(|d| d as i64 * 1000),
+		}
+	}
+}
+
+pub(crate) fn create_jspf_playlist(songs: &[Song]) -> Result<String, Error> {
+	let jspf = Jspf {
+		playlist: JspfPlaylist {
+			track: songs.iter().map(JspfTrack::from).collect(),
+		},
+	};
+	serde_json::to_string_pretty(&jspf).map_err(|e| Error::InvalidPlaylist(e.to_string()))
+}
+
+// Returns the first location of every track in a jspf playlist.
+pub(crate) fn parse_jspf_playlist(content: &str) -> Result<Vec<String>, Error> {
+	let jspf: Jspf =
+		serde_json::from_str(content).map_err(|e| Error::InvalidPlaylist(e.to_string()))?;
+	Ok(jspf
+		.playlist
+		.track
+		.into_iter()
+		.filter_map(|t| t.location.into_iter().next())
+		.collect())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn create_jspf_playlist_with_song_details() {
+		let mut song = Song::test_only_from_path("a/bc/d/ef");
+		song.title = Some("Anthem of the World".to_owned());
+		song.artist = Some("Stratovarius".to_owned());
+		song.duration = Some(571);
+		let playlist =
+			create_jspf_playlist(&[song, Song::test_only_from_path("abc/j/kl")]).unwrap();
+		let json: serde_json::Value = serde_json::from_str(&playlist).unwrap();
+		assert_eq!(
+			json,
+			serde_json::json!({
+				"playlist": {
+					"track": [
+						{
+							"location": ["a/bc/d/ef"],
+							"title": "Anthem of the World",
+							"creator": "Stratovarius",
+							"duration": 571000
+						},
+						{ "location": ["abc/j/kl"] }
+					]
+				}
+			})
+		);
+	}
+
+	#[test]
+	fn parse_jspf_playlist_reads_locations() {
+		let songs = [
+			Song::test_only_from_path("a/bc/d/ef"),
+			Song::test_only_from_path("a/bc/g/hi"),
+		];
+		assert_eq!(
+			parse_jspf_playlist(&create_jspf_playlist(&songs).unwrap()).unwrap(),
+			vec!["a/bc/d/ef".to_owned(), "a/bc/g/hi".to_owned()],
+		);
+		assert_eq!(
+			parse_jspf_playlist(
+				r#"{"playlist": {"title": "Mix", "track": [{"title": "No location"}, {"location": ["a/b.mp3", "c/d.mp3"]}]}}"#
+			)
+			.unwrap(),
+			vec!["a/b.mp3".to_owned()],
+		);
+		assert!(matches!(
+			parse_jspf_playlist("#EXTM3U\na/b.mp3\n"),
+			Err(Error::InvalidPlaylist(_))
+		));
+	}
+}
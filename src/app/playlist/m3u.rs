@@ -7,6 +7,23 @@ pub static M3U_HEADER: &str = "#EXTM3U";
 pub static M3U_RMIM_FIELDS: &str = "#EXT-X-POLARIS:";
 pub static M3U_COMMON_PATH: &str = "COMMON_PATH";
 
+pub static M3U_EXTINF: &str = "#EXTINF:";
+
+// Returns the `#EXTINF` line for a song, if its title is known.
+fn extinf(song: &Song) -> Option<String> {
+	let title = song.title.as_ref()?;
+	let display = match &song.artist {
+		Some(artist) => format!("{} - {}", artist, title),
+		None => title.clone(),
+	};
+	Some(format!(
+		"{}{},{}",
+		M3U_EXTINF,
+		song.duration.unwrap_or(-1),
+		display
+	))
+}
+
 pub(crate) fn create_m3u_playlist(songs: &[Song]) -> Result<String, Error> {
 	let (common_path, buffer) = strip_base_path(songs);
 	let mut ret = String::new();
@@ -19,10 +36,41 @@ pub(crate) fn create_m3u_playlist(songs: &[Song]) -> Result<String, Error> {
 		)
 		.unwrap();
 	}
-	write!(ret, "{}", buffer).unwrap();
+	for (song, path) in songs.iter().zip(buffer.lines()) {
+		if let Some(info) = extinf(song) {
+			writeln!(ret, "{}", info).unwrap();
+		}
+		writeln!(ret, "{}", path).unwrap();
+	}
 	Ok(ret)
 }
 
+// Returns the full paths listed in an m3u playlist, with the common path restored.
+// Entries are used as full paths when the playlist has no common path.
+pub(crate) fn parse_m3u_playlist(content: &str) -> Vec<String> {
+	let mut common_path = "";
+	let mut paths = Vec::new();
+	// Only line endings, "\n" or "\r\n", are dropped since file names may start or end with spaces.
+	for line in content.lines().filter(|l| !l.trim().is_empty()) {
+		let line = match line.trim_start().starts_with('#') {
+			true => line.trim(),
+			false => line,
+		};
+		if let Some(fields) = line.strip_prefix(M3U_RMIM_FIELDS) {
+			if let Some(path) = fields
+				.trim_start()
+				.strip_prefix(M3U_COMMON_PATH)
+				.and_then(|f| f.strip_prefix('='))
+			{
+				common_path = path;
+			}
+		} else if !line.starts_with('#') {
+			paths.push(format!("{}{}", common_path, line));
+		}
+	}
+	paths
+}
+
 #[cfg(test)]
 mod tests {
 	use super::*;
@@ -55,4 +103,59 @@ mod tests {
 			format!("{}\n{}", M3U_HEADER, "a/bc/d/ef\nab/c/g/hi\nabc/j/kl\n"),
 		);
 	}
+
+	#[test]
+	fn parse_m3u_playlist_restores_common_path() {
+		let songs = [
+			Song::test_only_from_path("a/bc/d/ef"),
+			Song::test_only_from_path("a/bc/g/hi"),
+		];
+		assert_eq!(
+			parse_m3u_playlist(&create_m3u_playlist(&songs).unwrap()),
+			vec!["a/bc/d/ef".to_owned(), "a/bc/g/hi".to_owned()],
+		);
+		assert_eq!(
+			parse_m3u_playlist("#EXTM3U\r\n#EXTINF:123,Song\r\na/b.mp3\r\n\r\n"),
+			vec!["a/b.mp3".to_owned()],
+		);
+	}
+
+	#[test]
+	fn create_m3u_playlist_with_extinf() {
+		let mut song = Song::test_only_from_path("a/bc/d/ef");
+		song.title = Some("Anthem of the World".to_owned());
+		song.artist = Some("Stratovarius".to_owned());
+		song.duration = Some(571);
+		let mut untimed = Song::test_only_from_path("ab/c/g/hi");
+		untimed.title = Some("Destiny".to_owned());
+		assert_eq!(
+			create_m3u_playlist(&[song, untimed, Song::test_only_from_path("abc/j/kl")]).unwrap(),
+			format!(
+				"{}\n{}\n{}\n{}\n{}\n{}\n",
+				M3U_HEADER,
+				"#EXTINF:571,Stratovarius - Anthem of the World",
+				"a/bc/d/ef",
+				"#EXTINF:-1,Destiny",
+				"ab/c/g/hi",
+				"abc/j/kl"
+			),
+		);
+	}
+
+	#[test]
+	fn parse_m3u_playlist_without_common_path() {
+		let songs = [
+			Song::test_only_from_path("a/bc/d/ef"),
+			Song::test_only_from_path("ab/c/g/hi "),
+			Song::test_only_from_path(" abc/j/kl"),
+		];
+		assert_eq!(
+			parse_m3u_playlist(&create_m3u_playlist(&songs).unwrap()),
+			vec![
+				"a/bc/d/ef".to_owned(),
+				"ab/c/g/hi ".to_owned(),
+				" abc/j/kl".to_owned()
+			],
+		);
+	}
 }
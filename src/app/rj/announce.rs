@@ -1,42 +1,167 @@
 use std::path::Path;
 
-use crate::app::{index, rj::error::ParseError};
+use crate::app::{
+	index::{self, Song},
+	rj::{error::ParseError, sanitize, ConjunctionPosition, Manager},
+};
 
-fn get_path_announcement(
-	index: &index::Index,
-	path: &Option<String>,
-	present_tense: bool,
-) -> Result<String, ParseError> {
+fn get_path_song(index: &index::Index, path: &Option<String>) -> Result<Option<Song>, ParseError> {
 	let path = match path {
 		Some(s) => s,
-		None => return Ok("".to_string()),
+		None => return Ok(None),
 	};
-	let song = index
-		.get_song(Path::new(path))
-		.map_err(|op| ParseError::FailedToBuild(op.to_string()))?;
 	index
-		.rj_manager
-		.read()
-		.unwrap()
-		.get_announcement(&song, present_tense)
+		.get_song(Path::new(path))
+		.map(Some)
+		.map_err(|op| ParseError::FailedToBuild(op.to_string()))
+}
+
+fn get_song_text(
+	rj_manager: &Manager,
+	song: &Option<Song>,
+	present_tense: bool,
+) -> Result<String, ParseError> {
+	match song {
+		Some(song) => rj_manager.get_announcement(song, present_tense),
+		None => Ok("".to_string()),
+	}
+}
+
+// Returns the content type of packets built by `rj_manager`.
+fn packet_content_type(rj_manager: &Manager) -> String {
+	if rj_manager.is_ssml_enabled() {
+		"application/ssml+xml".to_owned()
+	} else {
+		"text/plain; charset=utf-8".to_owned()
+	}
+}
+
+/// An announcement before and after it is wrapped for the TTS server.
+pub struct AnnouncementScript {
+	/// Human-readable text of the announcement, without any markup.
+	pub text: String,
+	/// What is sent to the TTS server, see `Manager::build_packet`.
+	pub packet: String,
+	/// Name of the RJ voicing the announcement, None when SSML is disabled.
+	pub host: Option<String>,
+}
+
+impl AnnouncementScript {
+	fn silent() -> Self {
+		AnnouncementScript {
+			text: "".to_owned(),
+			packet: "".to_owned(),
+			host: None,
+		}
+	}
+
+	/// Returns true if there is nothing to synthesize, like when the request opted out.
+	pub fn is_silent(&self) -> bool {
+		self.text.is_empty() && self.packet.is_empty()
+	}
 }
 
-pub fn get_announcement(
+// Drops SSML elements, like say-as, that wrap song fields, and unescapes the text
+// between them.
+fn strip_markup(text: &str) -> String {
+	let mut stripped = String::with_capacity(text.len());
+	let mut in_element = false;
+	for c in text.chars() {
+		match c {
+			'<' => in_element = true,
+			'>' if in_element => in_element = false,
+			_ if !in_element => stripped.push(c),
+			_ => (),
+		}
+	}
+	stripped
+		.replace("&lt;", "<")
+		.replace("&gt;", ">")
+		.replace("&quot;", "\"")
+		.replace("&apos;", "'")
+		.replace("&amp;", "&")
+}
+
+// Returns the text of an announcement as spoken. Plain text packets are returned as is
+// since they hold no markup, only song fields that may look like it.
+fn spoken_text(rj_manager: &Manager, text: &str) -> String {
+	match rj_manager.is_ssml_enabled() {
+		true => strip_markup(text),
+		false => text.to_owned(),
+	}
+}
+
+fn build_request_script(
+	index: &index::Index,
+	rj_manager: &Manager,
+	request: &index::RjRequest,
+) -> Result<AnnouncementScript, ParseError> {
+	if request.announce == Some(false) {
+		return Ok(AnnouncementScript::silent());
+	}
+	let prev = get_path_song(index, &request.prev)?;
+	let next = get_path_song(index, &request.next)?;
+	let next_next = get_path_song(index, &request.next_next)?;
+
+	let mut parts = vec![
+		get_song_text(rj_manager, &prev, false)?,
+		rj_manager.get_conjunction(ConjunctionPosition::PrevNext),
+		get_song_text(rj_manager, &next, true)?,
+		rj_manager.get_conjunction(ConjunctionPosition::NextNextNext),
+		get_song_text(rj_manager, &next_next, true)?,
+	];
+	// Missing songs and conjunctions would otherwise leave back to back pauses.
+	parts.retain(|part| !part.trim().is_empty());
+	let natural_pause = rj_manager.get_natural_pause();
+	let announcement = parts.join(&natural_pause);
+	let text = parts
+		.iter()
+		.map(|part| spoken_text(rj_manager, part).trim().to_owned())
+		.filter(|part| !part.is_empty())
+		.collect::<Vec<_>>()
+		.join(&spoken_text(rj_manager, &natural_pause));
+
+	// The host is picked for the song about to be played.
+	let host_song = next.as_ref().or(next_next.as_ref()).or(prev.as_ref());
+	Ok(AnnouncementScript {
+		text: sanitize(&text),
+		packet: rj_manager.build_packet(announcement, host_song),
+		host: rj_manager.get_host_name(host_song),
+	})
+}
+
+/// Gets the text and the packet of an announcement without synthesizing it.
+pub fn get_announcement_script(
+	index: &index::Index,
+	request: index::RjRequest,
+) -> Result<AnnouncementScript, ParseError> {
+	let rj_manager = index.rj_manager.read().unwrap();
+	build_request_script(index, &rj_manager, &request)
+}
+
+/// Gets the announcement packet, SSML or plain text, without synthesizing it.
+/// This lets clients synthesize the announcement locally.
+/// Returns the content type of the packet along with the script holding the packet.
+pub fn get_announcement_packet(
 	index: &index::Index,
 	request: index::RjRequest,
-) -> Result<(String, Vec<u8>), ParseError> {
-	let mut announcement = get_path_announcement(index, &request.prev, false)?;
-	let natural_pause = ". ".to_owned();
-	announcement += &(natural_pause.clone() + &get_path_announcement(index, &request.next, true)?);
-	announcement += &(natural_pause.clone() + &index.rj_manager.read().unwrap().get_conjunction());
-	announcement += &(natural_pause + &get_path_announcement(index, &request.next_next, true)?);
-	announcement = String::from_utf8(announcement.into_bytes())
-		.map_err(|op| ParseError::FailedToBuild(op.to_string()))?;
-
-	// TODO: String rarely contains a null byte which is causing tts server to panic.
-	// Root cause the issue.
-	// This is a workaround for that issue.
-	announcement = str::replace(&announcement, "\0", " ");
-	announcement = index.rj_manager.read().unwrap().build_packet(announcement);
-	index.rj_manager.read().unwrap().get_speech(&announcement)
+) -> Result<(String, AnnouncementScript), ParseError> {
+	let rj_manager = index.rj_manager.read().unwrap();
+	let script = build_request_script(index, &rj_manager, &request)?;
+	Ok((packet_content_type(&rj_manager), script))
+}
+
+/// Gets the text and the packet of an announcement for a song the caller already holds.
+/// Unlike `get_announcement_script`, the song is not looked up in the index.
+pub fn get_song_announcement_script(
+	rj_manager: &Manager,
+	song: &Song,
+	present_tense: bool,
+) -> Result<AnnouncementScript, ParseError> {
+	let announcement = rj_manager.get_announcement(song, present_tense)?;
+	Ok(AnnouncementScript {
+		text: sanitize(spoken_text(rj_manager, &announcement).trim()),
+		packet: rj_manager.build_packet(announcement, Some(song)),
+		host: rj_manager.get_host_name(Some(song)),
+	})
 }
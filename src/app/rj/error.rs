@@ -62,9 +62,27 @@ pub enum ParseError {
 	#[error("Invalid input: {0}")]
 	InvalidInput(String),
 
+	#[error("weights of name:({name:?}) must be positive and one per fragment")]
+	InvalidWeights { name: String },
+
+	#[error(
+		"optional probability of field:{field:?} must be between 0 and 1 and refer to a song field"
+	)]
+	InvalidOptionalProbability { field: String },
+
 	#[error("Delimiter({delimiter:?}) not allowed in conjunctions: {conjunction:?}")]
 	DelimiterNotAllowed {
 		delimiter: char,
 		conjunction: String,
 	},
+
+	#[error("tense pattern name:({name:?}) refers to unknown field:{field:?} in {text:?}")]
+	UnresolvedTenseField {
+		name: String,
+		field: String,
+		text: String,
+	},
+
+	#[error("tense pattern name:({name:?}) is declared but never used")]
+	UnusedTenseField { name: String },
 }
@@ -9,18 +9,36 @@ mod user_opts;
 
 pub use announce::*;
 
-use crate::app::index::Song;
+use crate::app::index::{Song, DEFAULT_TAG_DELIMITERS};
+use crate::utils::get_audio_format;
 pub use error::ParseError;
+use log::{error, warn};
 use script::ScriptCache;
 use serde::{Deserialize, Serialize};
-use std::io::Read;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::future::Future;
+use std::io::{Cursor, Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 use ureq;
+pub use user_opts::{ConjunctionPosition, UserAnnouncementOptions};
 
 static SSML_HEADER_OPEN: &str = r#"<speak version='1.0' xmlns='http://www.w3.org/2001/10/synthesis' xmlns:mstts='http://www.w3.org/2001/mstts' xmlns:emo='http://www.w3.org/2009/10/emotionml' xml:lang="#;
 static SSML_VOICE_ELEMENT_OPEN: &str = r#"<voice name="#;
 static SSML_ELEMENT_CLOSE: &str = r#">"#;
 static SSML_VOICE_ELEMENT_FOOTER: &str = r#"</voice>"#;
 static SSML_FOOTER: &str = r#"</speak>"#;
+static SSML_PROSODY_ELEMENT_OPEN: &str = r#"<prosody "#;
+static SSML_PROSODY_ELEMENT_FOOTER: &str = r#"</prosody>"#;
+static SSML_EMOTION_ELEMENT_OPEN: &str = r#"<emo:emotion><emo:category name="#;
+static SSML_EMOTION_ELEMENT_CLOSE: &str = r#"/>"#;
+static SSML_EMOTION_ELEMENT_FOOTER: &str = r#"</emo:emotion>"#;
 
 /// The structure defines the profile of an RJ.
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
@@ -28,6 +46,67 @@ pub struct Person {
 	name: String,
 	voice_model: String,
 	language: String,
+	/// Speaking rate, like "fast" or "+10%". Only used with SSML.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	rate: Option<String>,
+	/// Baseline pitch, like "high" or "-2st". Only used with SSML.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pitch: Option<String>,
+	/// Volume, like "loud" or "+20%". Only used with SSML.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	volume: Option<String>,
+	/// EmotionML category, like "excited" or "calm". Only used with SSML.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	emotion: Option<String>,
+}
+
+impl Person {
+	// Returns the attributes of the prosody element, or None when the host has no
+	// prosody preferences.
+	fn prosody_attributes(&self) -> Option<String> {
+		let attributes: Vec<String> = [
+			("rate", &self.rate),
+			("pitch", &self.pitch),
+			("volume", &self.volume),
+		]
+		.iter()
+		.filter_map(|(name, value)| {
+			value
+				.as_ref()
+				.map(|v| format!("{}='{}'", name, escape_xml(v)))
+		})
+		.collect();
+		if attributes.is_empty() {
+			None
+		} else {
+			Some(attributes.join(" "))
+		}
+	}
+}
+
+/// Picks the RJ, by name, who announces songs of a genre.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct VoiceRule {
+	pub genre: String,
+	pub person: String,
+}
+
+impl VoiceRule {
+	fn matches(&self, song: &Song) -> bool {
+		song.genres(DEFAULT_TAG_DELIMITERS)
+			.iter()
+			.any(|genre| genre.eq_ignore_ascii_case(&self.genre))
+	}
+}
+
+/// Returns the primary subtag of a BCP 47 tag, like "hi" for "hi-IN".
+fn primary_subtag(tag: &str) -> &str {
+	tag.split(|c| c == '-' || c == '_').next().unwrap_or(tag)
+}
+
+/// Returns true if a BCP 47 tag like "hi-IN" has `code` as its primary subtag.
+fn language_matches(tag: &str, code: &str) -> bool {
+	primary_subtag(tag).eq_ignore_ascii_case(code)
 }
 
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
@@ -35,6 +114,12 @@ pub struct UserSettings {
 	pub scripts: Option<String>,
 	pub enable_by_default: Option<bool>,
 	pub tts_people: Vec<Person>,
+	#[serde(default)]
+	pub voice_rules: Vec<VoiceRule>,
+	/// Name of the person who hosts when no voice rule or language picks one.
+	/// The first person hosts when unset.
+	#[serde(default)]
+	pub default_host: Option<String>,
 }
 
 impl UserSettings {
@@ -54,51 +139,644 @@ impl UserSettings {
 		}
 		true
 	}
+
+	fn are_voice_rules_valid(&self) -> bool {
+		self.voice_rules
+			.iter()
+			.all(|r| self.tts_people.iter().any(|p| p.name == r.person))
+	}
+
+	fn is_default_host_valid(&self) -> bool {
+		self.default_host
+			.as_ref()
+			.map_or(true, |name| self.tts_people.iter().any(|p| &p.name == name))
+	}
+
+	// Drops the people that are not allowed, along with the voice rules and the default
+	// host that refer to them. Settings saved before the allow-lists were configured may
+	// hold such people, and they should not keep the server from starting.
+	fn retain_allowed_people(
+		&mut self,
+		allowed_voice_models: &[String],
+		allowed_languages: &[String],
+	) {
+		self.tts_people.retain(|person| {
+			match check_people_allowed(
+				std::slice::from_ref(person),
+				allowed_voice_models,
+				allowed_languages,
+			) {
+				Ok(()) => true,
+				Err(e) => {
+					warn!("Ignoring RJ {}: {}", person.name, e);
+					false
+				}
+			}
+		});
+		let people = &self.tts_people;
+		self.voice_rules
+			.retain(|rule| people.iter().any(|p| p.name == rule.person));
+		if !self.is_default_host_valid() {
+			self.default_host = None;
+		}
+	}
+
+	/// Returns the person marked as default host, or the first person if none is.
+	pub fn default_person(&self) -> Option<&Person> {
+		self.default_host
+			.as_ref()
+			.and_then(|name| self.tts_people.iter().find(|p| &p.name == name))
+			.or_else(|| self.tts_people.first())
+	}
 }
 
 pub struct RestorableUserSettings {
 	cache: Option<ScriptCache>,
 	enable_by_default: bool,
 	pub tts_people: Vec<Person>,
+	voice_rules: Vec<VoiceRule>,
+	default_host: Option<String>,
 }
 
-#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct AdminSettings {
 	pub tts_url: Option<String>,
 	pub tts_key: Option<String>,
 	pub enable_ssml: bool,
+	/// Static headers, like `Authorization`, attached to every TTS request.
+	#[serde(default)]
+	pub tts_headers: BTreeMap<String, String>,
+	/// Audio clip played instead of the announcement when synthesis fails.
+	#[serde(default)]
+	pub tts_fallback_path: Option<String>,
+	/// Voice models the TTS server supports. Any voice model is allowed when empty.
+	#[serde(default)]
+	pub allowed_voice_models: Vec<String>,
+	/// Languages the TTS server supports. Any language is allowed when empty.
+	#[serde(default)]
+	pub allowed_languages: Vec<String>,
+	/// SSML spoken instead of a song field, or of a word in it, that TTS mispronounces.
+	/// For example "deadmau5" to `<sub alias="dead mouse">deadmau5</sub>`. Pronunciations
+	/// that are not markup, like "dead mouse", are escaped and spoken as text.
+	#[serde(default)]
+	pub pronunciations: BTreeMap<String, String>,
+	/// Pause between the announcements of consecutive songs. Joins them as is when SSML is
+	/// disabled, otherwise it is the duration of an SSML break, like "750ms".
+	#[serde(default)]
+	pub natural_pause: Option<String>,
+	/// Prefix the content type of synthesized speech must start with. Defaults to
+	/// "audio/" so that error pages are not played. An empty prefix accepts anything.
+	#[serde(default)]
+	pub tts_content_type: Option<String>,
+	/// Whether speech comes from the tts server or from the local command, see `TtsCommand`.
+	#[serde(default)]
+	pub tts_backend: TtsBackend,
+	/// Language, like "hi-IN", of the songs of a genre, like "Bollywood". Their announcements
+	/// go to a person speaking that language. Genres are compared regardless of ASCII case.
+	#[serde(default)]
+	pub genre_languages: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TtsBackend {
+	Http,
+	Command,
+}
+
+impl Default for TtsBackend {
+	fn default() -> Self {
+		Self::Http
+	}
+}
+
+/// Executable, like espeak-ng or piper, that reads the script from stdin and writes audio
+/// to stdout. It can only be set from the environment of the server, so that web admins
+/// cannot run programs on the host.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TtsCommand {
+	pub program: String,
+	pub args: Vec<String>,
+	/// The command is killed when it runs for longer than this.
+	pub timeout: Duration,
+}
+
+const DEFAULT_TTS_COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+impl TtsCommand {
+	pub fn new(program: &str, args: &[&str]) -> Self {
+		Self {
+			program: program.to_owned(),
+			args: args.iter().map(|a| (*a).to_owned()).collect(),
+			timeout: DEFAULT_TTS_COMMAND_TIMEOUT,
+		}
+	}
+
+	/// Reads `POLARIS_RJ_TTS_COMMAND`, its whitespace separated arguments from
+	/// `POLARIS_RJ_TTS_COMMAND_ARGS` and its timeout in seconds from
+	/// `POLARIS_RJ_TTS_COMMAND_TIMEOUT`.
+	pub fn from_env() -> Option<Self> {
+		let program = std::env::var("POLARIS_RJ_TTS_COMMAND")
+			.ok()
+			.filter(|p| !p.is_empty())?;
+		let args = std::env::var("POLARIS_RJ_TTS_COMMAND_ARGS")
+			.map(|a| a.split_whitespace().map(str::to_owned).collect())
+			.unwrap_or_default();
+		let timeout = std::env::var("POLARIS_RJ_TTS_COMMAND_TIMEOUT")
+			.ok()
+			.and_then(|t| u64::from_str(&t).ok())
+			.map_or(DEFAULT_TTS_COMMAND_TIMEOUT, Duration::from_secs);
+		Some(Self {
+			program,
+			args,
+			timeout,
+		})
+	}
+}
+
+// Header values usually carry credentials. Keep them out of the logs.
+impl fmt::Debug for AdminSettings {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("AdminSettings")
+			.field("tts_url", &self.tts_url)
+			.field("tts_key", &self.tts_key)
+			.field("enable_ssml", &self.enable_ssml)
+			.field("tts_headers", &self.tts_headers.keys().collect::<Vec<_>>())
+			.field("tts_fallback_path", &self.tts_fallback_path)
+			.field("allowed_voice_models", &self.allowed_voice_models)
+			.field("allowed_languages", &self.allowed_languages)
+			.field("pronunciations", &self.pronunciations)
+			.field("natural_pause", &self.natural_pause)
+			.field("tts_content_type", &self.tts_content_type)
+			.field("tts_backend", &self.tts_backend)
+			.field("genre_languages", &self.genre_languages)
+			.finish()
+	}
+}
+
+// Returns true if `name` is a valid header field name as per RFC 7230.
+fn is_header_name_valid(name: &str) -> bool {
+	!name.is_empty()
+		&& name
+			.bytes()
+			.all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+// Returns true if `value` has only visible characters, spaces and tabs.
+fn is_header_value_valid(value: &str) -> bool {
+	value
+		.bytes()
+		.all(|b| b == b'\t' || (b' '..=b'~').contains(&b))
 }
 
 impl AdminSettings {
-	fn is_valid(&self) -> bool {
-		self.tts_url.is_some() && self.tts_key.is_some()
+	fn is_valid(&self, tts_command: &Option<TtsCommand>) -> bool {
+		match self.tts_backend {
+			TtsBackend::Http => self.tts_url.is_some() && self.tts_key.is_some(),
+			TtsBackend::Command => tts_command.is_some(),
+		}
+	}
+
+	fn are_headers_valid(&self) -> bool {
+		self.tts_headers
+			.iter()
+			.all(|(name, value)| is_header_name_valid(name) && is_header_value_valid(value))
+	}
+
+	// SSML breaks only accept a time in seconds or milliseconds.
+	fn is_natural_pause_valid(&self) -> bool {
+		match &self.natural_pause {
+			Some(pause) if self.enable_ssml => {
+				let digits = pause
+					.strip_suffix("ms")
+					.or_else(|| pause.strip_suffix('s'))
+					.unwrap_or("");
+				!digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+			}
+			_ => true,
+		}
+	}
+}
+
+// Makes sure that people only use voice models and languages the TTS server supports.
+fn check_people_allowed(
+	people: &[Person],
+	allowed_voice_models: &[String],
+	allowed_languages: &[String],
+) -> Result<(), ParseError> {
+	for person in people {
+		if !allowed_voice_models.is_empty() && !allowed_voice_models.contains(&person.voice_model) {
+			return Err(ParseError::InvalidInput(format!(
+				"voice model {} is not allowed",
+				person.voice_model
+			)));
+		}
+		if !allowed_languages.is_empty() && !allowed_languages.contains(&person.language) {
+			return Err(ParseError::InvalidInput(format!(
+				"language {} is not allowed",
+				person.language
+			)));
+		}
+	}
+	Ok(())
+}
+
+// Stored scripts are not checked again, so that a script saved by an older version does
+// not keep the server from starting. The default script is used if it cannot be parsed.
+fn load_stored_script(script: &str) -> ScriptCache {
+	ScriptCache::load(script).unwrap_or_else(|e| {
+		error!("Could not load the RJ script, using the default one: {}", e);
+		ScriptCache::default()
+	})
+}
+
+/// Parses and verifies an announcement script without building a manager for it.
+pub fn verify_script(script: &str) -> Result<(), ParseError> {
+	ScriptCache::create(script).map(|_| ())
+}
+
+// Replaces control characters, which are invalid in XML and make some tts servers panic,
+// with spaces. Song tags occasionally carry null bytes, so every script goes through this.
+fn sanitize(script: &str) -> String {
+	script
+		.chars()
+		.map(|c| match c {
+			'\t' | '\n' | '\r' => c,
+			_ if c.is_control() => ' ',
+			_ => c,
+		})
+		.collect()
+}
+
+// Escapes text so that it can be put in SSML, as element content or as a quoted attribute.
+fn escape_xml(text: &str) -> String {
+	let mut escaped = String::with_capacity(text.len());
+	for c in text.chars() {
+		match c {
+			'&' => escaped.push_str("&amp;"),
+			'<' => escaped.push_str("&lt;"),
+			'>' => escaped.push_str("&gt;"),
+			'"' => escaped.push_str("&quot;"),
+			'\'' => escaped.push_str("&apos;"),
+			_ => escaped.push(c),
+		}
+	}
+	escaped
+}
+
+/// Audio being received from the tts server, along with its content type.
+pub type SpeechStream = (String, Box<dyn Read + Send>);
+
+// Sends the script to the tts server and returns the response body without
+// waiting for it to be fully received.
+fn open_speech(
+	agent: &ureq::Agent,
+	url: &str,
+	tts_key: &str,
+	tts_headers: &BTreeMap<String, String>,
+	content_type_prefix: &str,
+	script: &str,
+) -> Result<SpeechStream, ParseError> {
+	let mut request = agent.get(url);
+	for (name, value) in tts_headers {
+		request.set(name, value);
+	}
+	let body = request.query(tts_key, &sanitize(script)).call();
+	if let Some(error) = body.synthetic_error() {
+		return Err(ParseError::FailedToTTS(error.to_string()));
+	}
+	if body.error() {
+		return Err(ParseError::FailedToTTS(format!(
+			"{} {}",
+			body.status(),
+			body.status_text()
+		)));
+	}
+	let content_type = body.content_type().to_owned();
+	check_content_type(&content_type, content_type_prefix)?;
+	Ok((content_type, Box::new(body.into_reader())))
+}
+
+// Some tts servers answer errors with a successful html page. Playing it would be noise.
+fn check_content_type(content_type: &str, prefix: &str) -> Result<(), ParseError> {
+	let matches = content_type
+		.get(..prefix.len())
+		.map_or(false, |start| start.eq_ignore_ascii_case(prefix));
+	if !matches {
+		return Err(ParseError::FailedToTTS(format!(
+			"unexpected content type {}",
+			content_type
+		)));
+	}
+	Ok(())
+}
+
+fn read_speech((content_type, mut reader): SpeechStream) -> Result<Speech, ParseError> {
+	let mut buf = vec![];
+	reader
+		.read_to_end(&mut buf)
+		.map_err(|op| ParseError::FailedToTTS(op.to_string()))?;
+	Ok((content_type, buf))
+}
+
+fn stream_speech((content_type, bytes): Speech) -> SpeechStream {
+	(content_type, Box::new(Cursor::new(bytes)))
+}
+
+// Guesses the content type of audio written by a tts command from its magic bytes.
+fn sniff_content_type(audio: &[u8]) -> Option<&'static str> {
+	if audio.starts_with(b"RIFF") && audio.get(8..12) == Some(&b"WAVE"[..]) {
+		Some("audio/wav")
+	} else if audio.starts_with(b"OggS") {
+		Some("audio/ogg")
+	} else if audio.starts_with(b"fLaC") {
+		Some("audio/flac")
+	} else if audio.starts_with(b"ID3") || is_mpeg_frame_sync(audio) {
+		Some("audio/mpeg")
+	} else {
+		None
+	}
+}
+
+// MPEG audio frames start with 11 set bits, whatever their version and layer.
+fn is_mpeg_frame_sync(audio: &[u8]) -> bool {
+	matches!(audio, [0xFF, second, ..] if second & 0xE0 == 0xE0)
+}
+
+fn read_pipe<R: Read + Send + 'static>(mut pipe: R) -> thread::JoinHandle<Vec<u8>> {
+	thread::spawn(move || {
+		let mut buf = vec![];
+		let _ = pipe.read_to_end(&mut buf);
+		buf
+	})
+}
+
+// Pipes the script to the local tts executable and reads the audio it writes to stdout.
+fn run_speech_command(
+	command: &TtsCommand,
+	content_type_prefix: &str,
+	script: &str,
+) -> Result<(String, Vec<u8>), ParseError> {
+	let program = &command.program;
+	let mut child = Command::new(program)
+		.args(&command.args)
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.spawn()
+		.map_err(|e| ParseError::FailedToTTS(format!("cannot run {}: {}", program, e)))?;
+	// Pipes are served from other threads so that a command that answers before reading
+	// all of its input, or that fills stderr, does not block on a full pipe.
+	let mut stdin = child.stdin.take().unwrap();
+	let script = sanitize(script);
+	let writer = thread::spawn(move || stdin.write_all(script.as_bytes()));
+	let stdout = read_pipe(child.stdout.take().unwrap());
+	let stderr = read_pipe(child.stderr.take().unwrap());
+
+	let deadline = Instant::now() + command.timeout;
+	let status = loop {
+		match child.try_wait() {
+			Ok(Some(status)) => break Ok(status),
+			Ok(None) if Instant::now() < deadline => thread::sleep(Duration::from_millis(10)),
+			Ok(None) => {
+				break Err(format!(
+					"{} did not finish within {:?}",
+					program, command.timeout
+				))
+			}
+			Err(e) => break Err(e.to_string()),
+		}
+	};
+	let status = match status {
+		Ok(status) => status,
+		Err(e) => {
+			// Whatever went wrong, the command is not left running nor as a zombie.
+			let _ = child.kill();
+			let _ = child.wait();
+			return Err(ParseError::FailedToTTS(e));
+		}
+	};
+	// Commands that exit without reading all of the script fail the write, which is fine.
+	let _ = writer.join();
+	let audio = stdout.join().unwrap_or_default();
+	if !status.success() {
+		let stderr = stderr.join().unwrap_or_default();
+		return Err(ParseError::FailedToTTS(format!(
+			"{} {}: {}",
+			program,
+			status,
+			String::from_utf8_lossy(&stderr).trim()
+		)));
+	}
+	if audio.is_empty() {
+		return Err(ParseError::FailedToTTS(format!(
+			"{} did not write any audio",
+			program
+		)));
+	}
+	// Formats that cannot be recognized are passed through, the command is trusted to
+	// write audio. Recognized ones still have to match the expected content type.
+	match sniff_content_type(&audio) {
+		Some(content_type) => {
+			check_content_type(content_type, content_type_prefix)?;
+			Ok((content_type.to_owned(), audio))
+		}
+		None => Ok(("application/octet-stream".to_owned(), audio)),
+	}
+}
+
+type Speech = (String, Vec<u8>);
+
+const TEST_SYNTHESIS_PHRASE: &str = "Polaris TTS test";
+
+fn load_fallback(path: &str) -> Result<Arc<Speech>, ParseError> {
+	let path = Path::new(path);
+	let content_type = get_audio_format(path)
+		.map(|format| format.mime_type())
+		.ok_or_else(|| {
+			ParseError::InvalidInput(format!("{} is not an audio file", path.display()))
+		})?;
+	let bytes = fs::read(path)
+		.map_err(|e| ParseError::InvalidInput(format!("cannot read {}: {}", path.display(), e)))?;
+	Ok(Arc::new((content_type.to_owned(), bytes)))
+}
+
+// Replaces a failed synthesis with the fallback clip, if there is one.
+fn or_fallback(
+	speech: Result<Speech, ParseError>,
+	fallback: &Option<Arc<Speech>>,
+) -> Result<Speech, ParseError> {
+	match (speech, fallback) {
+		(Err(ParseError::FailedToTTS(e)), Some(fallback)) => {
+			warn!("Speech synthesis failed, playing fallback: {}", e);
+			Ok(fallback.as_ref().clone())
+		}
+		(speech, _) => speech,
+	}
+}
+
+/// A snapshot of the active RJ configuration that is safe to share for troubleshooting.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RjDiagnostics {
+	pub enabled: bool,
+	pub url: String,
+	pub enable_ssml: bool,
+	pub people_count: usize,
+	pub past_fragment_count: usize,
+	pub present_fragment_count: usize,
+	pub conjunction_count: usize,
+}
+
+// Hides credentials and query values that may be embedded in the TTS url.
+fn redact_url(url: &str) -> String {
+	match url::Url::parse(url) {
+		Ok(mut parsed) => {
+			if parsed.password().is_some() {
+				let _ = parsed.set_password(Some("redacted"));
+			}
+			if parsed.query().is_some() {
+				parsed.set_query(Some("redacted"));
+			}
+			parsed.to_string()
+		}
+		Err(_) => "redacted".to_owned(),
+	}
+}
+
+/// Speech synthesis settings copied out of a `Manager`.
+#[derive(Clone, Debug)]
+pub struct Synthesizer {
+	enabled: bool,
+	agent: ureq::Agent,
+	url: String,
+	tts_key: String,
+	tts_headers: BTreeMap<String, String>,
+	content_type_prefix: String,
+	tts_backend: TtsBackend,
+	tts_command: Option<TtsCommand>,
+	fallback: Option<Arc<Speech>>,
+}
+
+impl Synthesizer {
+	/// Gets announcement speech for a song.
+	/// This is a blocking call and it may take really long to synthesize voice.
+	/// Make sure that you call this on a thread that is not running async tasks.
+	pub fn get_speech(&self, script: &str) -> Result<(String, Vec<u8>), ParseError> {
+		if !self.enabled {
+			return Err(ParseError::RjServiceDisabled);
+		}
+		or_fallback(self.synthesize(script), &self.fallback)
+	}
+
+	// Requests the speech of `script` from the tts server at `url`.
+	fn open_url(&self, url: &str, script: &str) -> Result<SpeechStream, ParseError> {
+		open_speech(
+			&self.agent,
+			url,
+			&self.tts_key,
+			&self.tts_headers,
+			&self.content_type_prefix,
+			script,
+		)
+	}
+
+	fn synthesize(&self, script: &str) -> Result<(String, Vec<u8>), ParseError> {
+		match self.tts_backend {
+			TtsBackend::Http => self.open_url(&self.url, script).and_then(read_speech),
+			TtsBackend::Command => match &self.tts_command {
+				Some(command) => run_speech_command(command, &self.content_type_prefix, script),
+				None => Err(ParseError::FailedToTTS(
+					"no tts command is configured".to_owned(),
+				)),
+			},
+		}
+	}
+
+	/// Streaming variant of `get_speech`. It returns as soon as the tts server
+	/// starts responding so that audio can be forwarded while it is synthesized.
+	/// Opening the stream and reading from it are both blocking. Commands are not
+	/// streamed, their audio is returned once they exit.
+	pub fn get_speech_stream(&self, script: &str) -> Result<SpeechStream, ParseError> {
+		if !self.enabled {
+			return Err(ParseError::RjServiceDisabled);
+		}
+		let speech = match self.tts_backend {
+			TtsBackend::Http => self.open_url(&self.url, script),
+			TtsBackend::Command => self.synthesize(script).map(stream_speech),
+		};
+		match speech {
+			Err(e @ ParseError::FailedToTTS(_)) => {
+				or_fallback(Err(e), &self.fallback).map(stream_speech)
+			}
+			speech => speech,
+		}
+	}
+
+	/// Synthesizes `packet`, usually `Manager::test_packet`, so that admins can verify the
+	/// tts configuration. The fallback clip is never used here, so that failures are reported.
+	pub fn test_synthesis(&self, packet: &str) -> Result<(String, Vec<u8>), ParseError> {
+		if !self.enabled {
+			return Err(ParseError::RjServiceDisabled);
+		}
+		self.synthesize(packet)
 	}
 }
 
 #[derive(Debug)]
 pub struct Manager {
 	enabled: bool,
+	// Keeps connections to the tts server alive between announcements.
+	agent: ureq::Agent,
 	cache: Option<ScriptCache>,
 	url: String,
 	tts_key: String,
+	tts_headers: BTreeMap<String, String>,
+	fallback_path: Option<String>,
+	fallback: Option<Arc<Speech>>,
+	allowed_voice_models: Vec<String>,
+	allowed_languages: Vec<String>,
+	pronunciations: BTreeMap<String, String>,
+	natural_pause: Option<String>,
+	tts_content_type: Option<String>,
+	tts_backend: TtsBackend,
+	tts_command: Option<TtsCommand>,
+	genre_languages: BTreeMap<String, String>,
 	enable_by_default: bool,
 	enable_ssml: bool,
 	tts_people: Vec<Person>,
+	voice_rules: Vec<VoiceRule>,
+	default_host: Option<String>,
 }
 
 static DEFAULT_URL: &str = "http://devel.lan:12345/api/tts";
 static DEFAULT_TTS_KEY: &str = "text";
+static DEFAULT_NATURAL_PAUSE: &str = ". ";
+static DEFAULT_TTS_CONTENT_TYPE: &str = "audio/";
 
 impl Default for Manager {
 	fn default() -> Self {
 		Self {
 			enabled: false,
+			agent: ureq::agent(),
 			cache: None,
 			url: DEFAULT_URL.to_owned(),
 			tts_key: DEFAULT_TTS_KEY.to_owned(),
+			tts_headers: BTreeMap::new(),
+			fallback_path: None,
+			fallback: None,
+			allowed_voice_models: vec![],
+			allowed_languages: vec![],
+			pronunciations: BTreeMap::new(),
+			natural_pause: None,
+			tts_content_type: None,
+			tts_backend: TtsBackend::Http,
+			tts_command: TtsCommand::from_env(),
+			genre_languages: BTreeMap::new(),
 			enable_by_default: false,
 			enable_ssml: false,
 			tts_people: vec![],
+			voice_rules: vec![],
+			default_host: None,
 		}
 	}
 }
@@ -106,48 +784,119 @@ impl Default for Manager {
 impl Manager {
 	pub fn create(
 		admin_settings: AdminSettings,
-		user_settings: UserSettings,
+		mut user_settings: UserSettings,
 	) -> Result<Manager, ParseError> {
 		if admin_settings == AdminSettings::default() && user_settings == UserSettings::default() {
 			return Ok(Manager::default());
 		}
 
+		user_settings.retain_allowed_people(
+			&admin_settings.allowed_voice_models,
+			&admin_settings.allowed_languages,
+		);
+
 		if admin_settings.enable_ssml && !user_settings.is_people_valid() {
 			return Ok(Manager::default());
 		}
 
-		if admin_settings.is_valid() && user_settings.is_valid() {
-			return Ok(Manager {
-				enabled: true,
-				cache: Some(ScriptCache::create(
-					user_settings.scripts.as_ref().unwrap(),
-				)?),
-				url: admin_settings.tts_url.to_owned().unwrap(),
-				tts_key: admin_settings.tts_key.unwrap(),
-				enable_by_default: user_settings.enable_by_default.unwrap(),
-				enable_ssml: admin_settings.enable_ssml,
-				tts_people: user_settings.tts_people,
-			});
-		}
-		if admin_settings.is_valid() {
-			return Ok(Manager {
-				enabled: true,
-				cache: Some(ScriptCache::default()),
-				url: admin_settings.tts_url.to_owned().unwrap(),
-				tts_key: admin_settings.tts_key.unwrap(),
-				enable_by_default: false,
-				enable_ssml: admin_settings.enable_ssml,
-				tts_people: user_settings.tts_people,
-			});
-		}
-		Ok(Manager::default())
-	}
-
-	fn get_current_host(&self) -> Option<&Person> {
+		// A missing fallback clip should not keep the server from starting.
+		let fallback = match admin_settings
+			.tts_fallback_path
+			.as_deref()
+			.map(load_fallback)
+		{
+			Some(Ok(fallback)) => Some(fallback),
+			Some(Err(e)) => {
+				error!("Could not load RJ fallback: {}", e);
+				None
+			}
+			None => None,
+		};
+
+		let tts_command = TtsCommand::from_env();
+		if !admin_settings.is_valid(&tts_command) {
+			return Ok(Manager::default());
+		}
+		// Until users save a valid script, the default one is used and announcements are off.
+		let (cache, enable_by_default) = match user_settings.is_valid() {
+			true => (
+				load_stored_script(user_settings.scripts.as_ref().unwrap()),
+				user_settings.enable_by_default.unwrap(),
+			),
+			false => (ScriptCache::default(), false),
+		};
+		Ok(Manager {
+			enabled: true,
+			agent: ureq::agent(),
+			cache: Some(cache),
+			url: admin_settings.tts_url.unwrap_or_default(),
+			tts_key: admin_settings.tts_key.unwrap_or_default(),
+			tts_headers: admin_settings.tts_headers,
+			fallback_path: admin_settings.tts_fallback_path,
+			fallback,
+			allowed_voice_models: admin_settings.allowed_voice_models,
+			allowed_languages: admin_settings.allowed_languages,
+			pronunciations: admin_settings.pronunciations,
+			natural_pause: admin_settings.natural_pause,
+			tts_content_type: admin_settings.tts_content_type,
+			tts_backend: admin_settings.tts_backend,
+			tts_command,
+			genre_languages: admin_settings.genre_languages,
+			enable_by_default,
+			enable_ssml: admin_settings.enable_ssml,
+			tts_people: user_settings.tts_people,
+			voice_rules: user_settings.voice_rules,
+			default_host: user_settings.default_host,
+		})
+	}
+
+	/// Returns the RJ who announces `song`. A matching voice rule wins, then a person
+	/// speaking the language configured for its genre, otherwise the default host.
+	fn get_current_host(&self, song: Option<&Song>) -> Option<&Person> {
 		if !self.enable_ssml {
 			return None;
 		}
-		Some(&self.tts_people[0])
+		if let Some(song) = song {
+			let ruled = self
+				.voice_rules
+				.iter()
+				.filter(|r| r.matches(song))
+				.find_map(|r| self.tts_people.iter().find(|p| p.name == r.person));
+			if ruled.is_some() {
+				return ruled;
+			}
+			if let Some(language) = self.get_song_language(song) {
+				let code = primary_subtag(language);
+				let speaker = self
+					.tts_people
+					.iter()
+					.find(|p| language_matches(&p.language, code));
+				if speaker.is_some() {
+					return speaker;
+				}
+			}
+		}
+		self.default_host
+			.as_ref()
+			.and_then(|name| self.tts_people.iter().find(|p| &p.name == name))
+			.or_else(|| self.tts_people.first())
+	}
+
+	/// Language of `song` as configured for its genre, see `AdminSettings::genre_languages`.
+	fn get_song_language(&self, song: &Song) -> Option<&str> {
+		song.genres(DEFAULT_TAG_DELIMITERS)
+			.iter()
+			.find_map(|genre| {
+				self.genre_languages
+					.iter()
+					.find(|(name, _)| genre.eq_ignore_ascii_case(name))
+					.map(|(_, language)| language.as_str())
+			})
+	}
+
+	/// Name of the RJ whose voice announces `song`, None when SSML is disabled.
+	pub fn get_host_name(&self, song: Option<&Song>) -> Option<String> {
+		self.get_current_host(song).map(|host| host.name.clone())
 	}
 
 	pub fn get_announcement(
@@ -162,65 +911,216 @@ impl Manager {
 			.cache
 			.as_ref()
 			.unwrap()
-			.get_announcement(song, present, self.enable_ssml)
+			.get_announcement(song, present, self.enable_ssml, &self.pronunciations)
 			.unwrap_or_else(|| "".to_owned()))
 	}
 
-	fn build_ssml_header(&self) -> String {
+	/// Returns the announcement text for `song` without synthesizing it. This works even
+	/// when the tts server is not configured, in which case the default script is used.
+	pub fn preview_announcement(&self, song: &Song, present: bool) -> String {
+		let default_cache;
+		let cache = match &self.cache {
+			Some(cache) => cache,
+			None => {
+				default_cache = ScriptCache::default();
+				&default_cache
+			}
+		};
+		cache
+			.get_announcement(song, present, self.enable_ssml, &self.pronunciations)
+			.unwrap_or_default()
+	}
+
+	/// Returns the names of the required fields that `song` lacks, so that users can be
+	/// told why it is not announced properly. Like `preview_announcement`, the default
+	/// script is checked when the tts server is not configured.
+	pub fn missing_required_fields(&self, song: &Song) -> Vec<String> {
+		match &self.cache {
+			Some(cache) => cache.missing_required(song),
+			None => ScriptCache::default().missing_required(song),
+		}
+	}
+
+	fn build_ssml_header(&self, host: &Person) -> String {
 		assert!(self.enable_ssml);
 		format!(
 			r#"{}'{}'{}"#,
 			SSML_HEADER_OPEN,
-			self.get_current_host().unwrap().language,
+			escape_xml(&host.language),
 			SSML_ELEMENT_CLOSE,
 		)
 	}
 
-	fn build_ssml_voice(&self) -> String {
+	fn build_ssml_voice(&self, host: &Person) -> String {
 		assert!(self.enable_ssml);
 		format!(
 			r#"{}'{}'{}"#,
 			SSML_VOICE_ELEMENT_OPEN,
-			self.get_current_host().unwrap().voice_model,
+			escape_xml(&host.voice_model),
 			SSML_ELEMENT_CLOSE,
 		)
 	}
 
-	pub fn build_packet(&self, script: String) -> String {
+	/// Wraps the script for the TTS server. `song` is used to pick the host.
+	pub fn build_packet(&self, script: String, song: Option<&Song>) -> String {
+		let script = sanitize(&script);
 		if !self.enable_ssml {
 			return script;
 		}
+		let host = self.get_current_host(song).unwrap();
+		let script = match host.prosody_attributes() {
+			Some(attributes) => format!(
+				r#"{}{}{}{}{}"#,
+				SSML_PROSODY_ELEMENT_OPEN,
+				attributes,
+				SSML_ELEMENT_CLOSE,
+				script,
+				SSML_PROSODY_ELEMENT_FOOTER
+			),
+			None => script,
+		};
+		let script = match &host.emotion {
+			Some(emotion) => format!(
+				r#"{}'{}'{}{}{}"#,
+				SSML_EMOTION_ELEMENT_OPEN,
+				escape_xml(emotion),
+				SSML_EMOTION_ELEMENT_CLOSE,
+				script,
+				SSML_EMOTION_ELEMENT_FOOTER
+			),
+			None => script,
+		};
 		format!(
 			r#"{}{}{}{}{}"#,
-			&self.build_ssml_header(),
-			&self.build_ssml_voice(),
+			&self.build_ssml_header(host),
+			&self.build_ssml_voice(host),
 			&script,
 			SSML_VOICE_ELEMENT_FOOTER,
 			SSML_FOOTER
 		)
 	}
 
-	/// Gets announcement speech for a song.
-	/// This is a blocking call and it may take really long to synthesize voice.
-	/// Make sure that you call this on a thread that is not running async tasks.
+	/// Copies what speech synthesis needs, so that it can run without holding the
+	/// manager's lock for the whole time the tts server or command takes.
+	pub fn synthesizer(&self) -> Synthesizer {
+		Synthesizer {
+			enabled: self.enabled,
+			agent: self.agent.clone(),
+			url: self.url.clone(),
+			tts_key: self.tts_key.clone(),
+			tts_headers: self.tts_headers.clone(),
+			content_type_prefix: self.get_tts_content_type().to_owned(),
+			tts_backend: self.tts_backend,
+			tts_command: self.tts_command.clone(),
+			fallback: self.fallback.clone(),
+		}
+	}
+
+	/// See `Synthesizer::get_speech`.
 	pub fn get_speech(&self, script: &str) -> Result<(String, Vec<u8>), ParseError> {
-		if !self.enabled {
-			return Err(ParseError::RjServiceDisabled);
+		self.synthesizer().get_speech(script)
+	}
+
+	/// See `Synthesizer::get_speech_stream`.
+	pub fn get_speech_stream(&self, script: &str) -> Result<SpeechStream, ParseError> {
+		self.synthesizer().get_speech_stream(script)
+	}
+
+	/// Async variant of `get_speech` that synthesizes voice on tokio's blocking
+	/// thread pool. The returned future does not borrow the manager, so callers can
+	/// release the manager's lock before awaiting it.
+	pub fn get_speech_async(
+		&self,
+		script: String,
+	) -> impl Future<Output = Result<(String, Vec<u8>), ParseError>> + 'static {
+		let synthesizer = self.synthesizer();
+		async move {
+			if !synthesizer.enabled {
+				return Err(ParseError::RjServiceDisabled);
+			}
+			let fallback = synthesizer.fallback.clone();
+			let synthesis =
+				tokio::task::spawn_blocking(move || synthesizer.synthesize(&script)).await;
+			let speech = match synthesis {
+				Ok(speech) => speech,
+				Err(op) => Err(ParseError::FailedToTTS(op.to_string())),
+			};
+			or_fallback(speech, &fallback)
 		}
-		let body = ureq::get(&self.url).query(&self.tts_key, script).call();
-		let mut buf = vec![];
-		let content_type = body.content_type().to_owned();
-		body.into_reader()
-			.read_to_end(&mut buf)
-			.map_err(|op| ParseError::FailedToTTS(op.to_string()))?;
-		Ok((content_type, buf))
 	}
 
-	/// Returns a randomly selected conjunction that can be used to join announcements of next song
-	/// and the song after that.
-	pub fn get_conjunction(&self) -> String {
+	/// Script spoken by `test_synthesis`.
+	pub fn test_packet(&self) -> String {
+		self.build_packet(TEST_SYNTHESIS_PHRASE.to_owned(), None)
+	}
+
+	/// Synthesizes a canned phrase so that admins can verify the tts configuration.
+	pub fn test_synthesis(&self) -> Result<(String, Vec<u8>), ParseError> {
+		self.synthesizer().test_synthesis(&self.test_packet())
+	}
+
+	/// Returns the effective configuration without exposing the tts key.
+	pub fn diagnostics(&self) -> RjDiagnostics {
+		let (past_fragment_count, present_fragment_count) = self
+			.cache
+			.as_ref()
+			.map(|c| c.fragment_counts())
+			.unwrap_or((0, 0));
+		RjDiagnostics {
+			enabled: self.enabled,
+			url: redact_url(&self.url),
+			enable_ssml: self.enable_ssml,
+			people_count: self.tts_people.len(),
+			past_fragment_count,
+			present_fragment_count,
+			conjunction_count: self
+				.cache
+				.as_ref()
+				.map(|c| c.conjunction_count())
+				.unwrap_or(0),
+		}
+	}
+
+	/// Returns true if packets are wrapped in SSML.
+	pub fn is_ssml_enabled(&self) -> bool {
+		self.enable_ssml
+	}
+
+	/// Returns true if users who have no preference of their own get announcements.
+	pub fn is_enabled_by_default(&self) -> bool {
+		self.enable_by_default
+	}
+
+	fn get_tts_content_type(&self) -> &str {
+		self.tts_content_type
+			.as_deref()
+			.unwrap_or(DEFAULT_TTS_CONTENT_TYPE)
+	}
+
+	/// Returns what joins the announcements of consecutive songs in a packet.
+	pub fn get_natural_pause(&self) -> String {
+		match &self.natural_pause {
+			Some(time) if self.enable_ssml => {
+				format!(
+					"{}<break time=\"{}\"/>",
+					DEFAULT_NATURAL_PAUSE,
+					escape_xml(time)
+				)
+			}
+			Some(pause) => pause.clone(),
+			None => DEFAULT_NATURAL_PAUSE.to_owned(),
+		}
+	}
+
+	/// Returns a randomly selected conjunction that can be used to join announcements of songs
+	/// at the given position.
+	pub fn get_conjunction(&self, position: ConjunctionPosition) -> String {
 		if let Some(cache) = &self.cache {
-			return cache.get_conjunction();
+			let conjunction = cache.get_conjunction(position);
+			return match self.enable_ssml {
+				true => escape_xml(&conjunction),
+				false => conjunction,
+			};
 		}
 		"".to_string()
 	}
@@ -237,22 +1137,56 @@ impl Manager {
 				"arguments cannot be null".to_string(),
 			));
 		}
+		if !user_settings.are_voice_rules_valid() {
+			return Err(ParseError::InvalidInput(
+				"voice rules must refer to configured people".to_string(),
+			));
+		}
+		if !user_settings.is_default_host_valid() {
+			return Err(ParseError::InvalidInput(
+				"default host must refer to a configured person".to_string(),
+			));
+		}
+		check_people_allowed(
+			&user_settings.tts_people,
+			&self.allowed_voice_models,
+			&self.allowed_languages,
+		)?;
 		let cache = ScriptCache::create(user_settings.scripts.as_ref().unwrap())?;
 		let ret = RestorableUserSettings {
 			cache: self.cache.take(),
 			enable_by_default: self.enable_by_default,
 			tts_people: self.tts_people.clone(),
+			voice_rules: self.voice_rules.clone(),
+			default_host: self.default_host.clone(),
 		};
 		self.cache = Some(cache);
 		self.enable_by_default = user_settings.enable_by_default.unwrap();
 		self.tts_people = user_settings.tts_people;
+		self.voice_rules = user_settings.voice_rules;
+		self.default_host = user_settings.default_host;
 		Ok(ret)
 	}
 
+	/// Marks `name` as the default host. Returns the previous default host.
+	pub fn set_default_host(&mut self, name: Option<String>) -> Result<Option<String>, ParseError> {
+		if let Some(name) = &name {
+			if !self.tts_people.iter().any(|p| &p.name == name) {
+				return Err(ParseError::InvalidInput(format!(
+					"{} is not a configured person",
+					name
+				)));
+			}
+		}
+		Ok(std::mem::replace(&mut self.default_host, name))
+	}
+
 	pub fn restore_user_settings(&mut self, mut to_restore: RestorableUserSettings) {
 		self.cache = to_restore.cache.take();
 		self.enable_by_default = to_restore.enable_by_default;
 		self.tts_people = to_restore.tts_people;
+		self.voice_rules = to_restore.voice_rules;
+		self.default_host = to_restore.default_host;
 	}
 
 	/// Updates TTS server details.
@@ -262,20 +1196,67 @@ impl Manager {
 		&mut self,
 		admin_settings: AdminSettings,
 	) -> Result<AdminSettings, ParseError> {
-		if !admin_settings.is_valid() {
+		if !admin_settings.is_valid(&self.tts_command) {
+			return Err(ParseError::InvalidInput(match admin_settings.tts_backend {
+				TtsBackend::Http => "arguments cannot be null".to_string(),
+				TtsBackend::Command => {
+					"the tts command must be set with POLARIS_RJ_TTS_COMMAND".to_string()
+				}
+			}));
+		}
+
+		if !admin_settings.are_headers_valid() {
 			return Err(ParseError::InvalidInput(
-				"arguments cannot be null".to_string(),
+				"tts headers must be well-formed".to_string(),
 			));
 		}
 
+		if !admin_settings.is_natural_pause_valid() {
+			return Err(ParseError::InvalidInput(
+				"natural pause must be a time, like 750ms, when ssml is enabled".to_string(),
+			));
+		}
+
+		check_people_allowed(
+			&self.tts_people,
+			&admin_settings.allowed_voice_models,
+			&admin_settings.allowed_languages,
+		)?;
+
+		let fallback = match &admin_settings.tts_fallback_path {
+			Some(path) => Some(load_fallback(path)?),
+			None => None,
+		};
+
 		let old = AdminSettings {
 			tts_url: Some(self.url.clone()),
 			tts_key: Some(self.tts_key.clone()),
 			enable_ssml: self.enable_ssml,
+			tts_headers: self.tts_headers.clone(),
+			tts_fallback_path: self.fallback_path.clone(),
+			allowed_voice_models: self.allowed_voice_models.clone(),
+			allowed_languages: self.allowed_languages.clone(),
+			pronunciations: self.pronunciations.clone(),
+			natural_pause: self.natural_pause.clone(),
+			tts_content_type: self.tts_content_type.clone(),
+			tts_backend: self.tts_backend,
+			genre_languages: self.genre_languages.clone(),
 		};
-		self.url = admin_settings.tts_url.unwrap();
-		self.tts_key = admin_settings.tts_key.unwrap();
+		// Pooled connections belong to the old server when the url changes.
+		self.agent = ureq::agent();
+		self.url = admin_settings.tts_url.unwrap_or_default();
+		self.tts_key = admin_settings.tts_key.unwrap_or_default();
 		self.enable_ssml = admin_settings.enable_ssml;
+		self.tts_headers = admin_settings.tts_headers;
+		self.fallback_path = admin_settings.tts_fallback_path;
+		self.fallback = fallback;
+		self.allowed_voice_models = admin_settings.allowed_voice_models;
+		self.allowed_languages = admin_settings.allowed_languages;
+		self.pronunciations = admin_settings.pronunciations;
+		self.natural_pause = admin_settings.natural_pause;
+		self.tts_content_type = admin_settings.tts_content_type;
+		self.tts_backend = admin_settings.tts_backend;
+		self.genre_languages = admin_settings.genre_languages;
 		Ok(old)
 	}
 }
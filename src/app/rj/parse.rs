@@ -1,6 +1,9 @@
 use crate::app::rj::error::ParseError as Error;
-use crate::app::rj::user_opts::{FieldsToAnnounce, TensedUserField, UserAnnouncementOptions};
+use crate::app::rj::user_opts::{
+	ConjunctionPools, FieldsToAnnounce, TensedUserField, UserAnnouncementOptions,
+};
 use lazy_static::lazy_static;
+use log::warn;
 use std::collections::{BTreeMap, HashSet};
 use std::mem;
 
@@ -15,11 +18,16 @@ use std::mem;
 
 pub static DEFAULT_DEPTH_LIMIT: usize = 5;
 
+/// Weight of fragments that were not given one.
+pub static DEFAULT_FRAGMENT_WEIGHT: u32 = 1;
+
 #[derive(Debug, Clone)]
 pub struct Field {
 	delimited_name: String,
 	whole: bool,
 	fragments: BTreeMap<String, bool>,
+	// Only fragments with a non-default weight are present.
+	weights: BTreeMap<String, u32>,
 }
 
 impl Field {
@@ -40,8 +48,16 @@ impl Field {
 		self.whole
 	}
 
+	pub fn weight(&self, fragment: &str) -> u32 {
+		*self
+			.weights
+			.get(fragment)
+			.unwrap_or(&DEFAULT_FRAGMENT_WEIGHT)
+	}
+
 	pub fn replace_and_keep(&mut self, from: &str, to: &str) -> Result<(), Error> {
 		let mut new_fragments = self.fragments.clone();
+		let mut new_weights = self.weights.clone();
 
 		if let Some(fragment) = self.has_self_dependency() {
 			return Err(Error::RecursiveDependency {
@@ -52,12 +68,18 @@ impl Field {
 
 		for (fragment, used) in self.fragments.iter() {
 			if fragment.contains(from) {
-				new_fragments.insert(fragment.replace(from, to), *used);
+				let new_fragment = fragment.replace(from, to);
+				// Expanded fragments inherit the weight of the fragment they came from.
+				if let Some(weight) = self.weights.get(fragment) {
+					new_weights.insert(new_fragment.clone(), *weight);
+				}
+				new_fragments.insert(new_fragment, *used);
 				let new_used = new_fragments.get_mut(fragment).unwrap();
 				*new_used = true;
 			}
 		}
 		let _ = mem::replace(&mut self.fragments, new_fragments);
+		let _ = mem::replace(&mut self.weights, new_weights);
 		Ok(())
 	}
 
@@ -140,11 +162,11 @@ lazy_static! {
 	};
 }
 
-fn get_delimited_name(name: &str) -> String {
+pub fn get_delimited_name(name: &str) -> String {
 	FIELD_DELIMITER.to_string() + name + &FIELD_DELIMITER.to_string()
 }
 
-fn strip_delimiters(name: &str) -> String {
+pub fn strip_delimiters(name: &str) -> String {
 	name.replace(FIELD_DELIMITER, "")
 }
 
@@ -195,6 +217,7 @@ pub struct AnnouncementOptions {
 	tense: BTreeMap<String, TensedUserField>,
 	pub tags_to_announce: FieldsToAnnounce,
 	pub conjunctions: Vec<String>,
+	pub conjunction_pools: ConjunctionPools,
 }
 
 impl AnnouncementOptions {
@@ -207,12 +230,27 @@ impl AnnouncementOptions {
 				for f in &user_field.fragments {
 					set.insert(f.clone(), false);
 				}
+				let mut weights = BTreeMap::new();
+				if let Some(user_weights) = &user_field.weights {
+					if user_weights.len() != user_field.fragments.len() || user_weights.contains(&0)
+					{
+						return Err(Error::InvalidWeights {
+							name: user_field.name.to_owned(),
+						});
+					}
+					for (f, weight) in user_field.fragments.iter().zip(user_weights) {
+						if *weight != DEFAULT_FRAGMENT_WEIGHT {
+							weights.insert(f.clone(), *weight);
+						}
+					}
+				}
 				self.neutral.insert(
 					user_field.name.clone(),
 					Field {
 						delimited_name: get_delimited_name(&user_field.name),
 						whole: user_field.whole,
 						fragments: set,
+						weights,
 					},
 				);
 			}
@@ -282,6 +320,17 @@ impl AnnouncementOptions {
 		Ok(())
 	}
 
+	fn verify_optional_probabilities(&self) -> Result<(), Error> {
+		for (field, probability) in &self.tags_to_announce.optional_probabilities {
+			if !is_reserved(field) || !(0.0..=1.0).contains(probability) {
+				return Err(Error::InvalidOptionalProbability {
+					field: field.to_owned(),
+				});
+			}
+		}
+		Ok(())
+	}
+
 	fn uses_reserved_name(&self) -> Result<(), Error> {
 		Self::uses_reserved_name_internal(&self.neutral)?;
 		Self::uses_reserved_name_internal(&self.tense)
@@ -296,40 +345,57 @@ impl AnnouncementOptions {
 				});
 			}
 		}
+		for (name, tense) in &self.tense {
+			let delimited_name = get_delimited_name(name);
+			for text in [&tense.past, &tense.present] {
+				if text.contains(&delimited_name) {
+					return Err(Error::SelfRecursion {
+						name: name.to_owned(),
+						fragment: text.to_owned(),
+					});
+				}
+			}
+		}
+		Ok(())
+	}
+
+	fn check_delimiters(name: &str, fragment: &str, word: &str) -> Result<(), Error> {
+		if let Err(count) = is_field_name(word) {
+			if count != 2 {
+				return Err(Error::OddNumberOfDelimiters {
+					count,
+					delimiter: FIELD_DELIMITER,
+					name: name.to_owned(),
+					fragment: fragment.to_owned(),
+					word: word.to_owned(),
+				});
+			}
+			return Err(Error::InterleavedDelimiter {
+				delimiter: FIELD_DELIMITER,
+				name: name.to_owned(),
+				fragment: fragment.to_owned(),
+				word: word.to_owned(),
+			});
+		}
 		Ok(())
 	}
 
 	fn has_delimiter_only_at_start_end(&self) -> Result<(), Error> {
 		self.iterate_all_words(
 			&mut |name, _: &Field, fragment: &str, word: &str| -> (bool, Result<(), Error>) {
-				let x = is_field_name(word);
-				if let Err(count) = x {
-					if count != 2 {
-						return (
-							true,
-							Err(Error::OddNumberOfDelimiters {
-								count,
-								delimiter: FIELD_DELIMITER,
-								name: name.to_owned(),
-								fragment: fragment.to_owned(),
-								word: word.to_owned(),
-							}),
-						);
-					}
-					return (
-						true,
-						Err(Error::InterleavedDelimiter {
-							delimiter: FIELD_DELIMITER,
-							name: name.to_owned(),
-							fragment: fragment.to_owned(),
-							word: word.to_owned(),
-						}),
-					);
-				}
-				(false, Ok(()))
+				let checked = Self::check_delimiters(name, fragment, word);
+				(checked.is_err(), checked)
 			},
 			Ok(()),
-		)
+		)?;
+		for (name, tense) in &self.tense {
+			for text in [&tense.past, &tense.present] {
+				for word in text.split_whitespace() {
+					Self::check_delimiters(name, text, word)?;
+				}
+			}
+		}
+		Ok(())
 	}
 
 	fn verify_missing_name(&self) -> Result<(), Error> {
@@ -357,6 +423,52 @@ impl AnnouncementOptions {
 		)
 	}
 
+	// Tense patterns are substituted verbatim, so they can only refer to reserved fields, and
+	// each of them has to be used by at least one fragment.
+	fn check_tense_field(&self, name: &str, tense: &TensedUserField) -> Result<(), Error> {
+		for text in [&tense.past, &tense.present] {
+			for word in text.split_whitespace() {
+				let field = strip_delimiters(word);
+				if word.contains(FIELD_DELIMITER) && !is_reserved(&field) {
+					return Err(Error::UnresolvedTenseField {
+						name: name.to_owned(),
+						field,
+						text: text.to_owned(),
+					});
+				}
+			}
+		}
+
+		let delimited_name = get_delimited_name(name);
+		let used = self.iterate_all_fragments(
+			&mut |_name, _field: &Field, fragment: &str| {
+				let used = fragment.contains(&delimited_name);
+				(used, used)
+			},
+			false,
+		);
+		if !used {
+			return Err(Error::UnusedTenseField {
+				name: name.to_owned(),
+			});
+		}
+		Ok(())
+	}
+
+	// Scripts stored before these checks existed are loaded as they were, with a warning,
+	// rather than rejected.
+	fn verify_tense_fields(&self, strict: bool) -> Result<(), Error> {
+		for (name, tense) in &self.tense {
+			if let Err(e) = self.check_tense_field(name, tense) {
+				if strict {
+					return Err(e);
+				}
+				warn!("Invalid RJ tense pattern {}: {}", name, e);
+			}
+		}
+		Ok(())
+	}
+
 	fn has_unresolved(&self) -> bool {
 		self.iterate_all_words(
 			&mut |_name, _field: &Field, _fragment: &str, word: &str| {
@@ -394,6 +506,7 @@ impl AnnouncementOptions {
 		map: &mut BTreeMap<String, Field>,
 		name: &str,
 		fragment: String,
+		weight: u32,
 		field: &Field,
 	) {
 		if !map.contains_key(name) {
@@ -403,10 +516,15 @@ impl AnnouncementOptions {
 					delimited_name: field.delimited_name.clone(),
 					whole: field.whole,
 					fragments: BTreeMap::new(),
+					weights: BTreeMap::new(),
 				},
 			);
 		}
-		map.get_mut(name).unwrap().fragments.insert(fragment, false);
+		let tensed_field = map.get_mut(name).unwrap();
+		if weight != DEFAULT_FRAGMENT_WEIGHT {
+			tensed_field.weights.insert(fragment.clone(), weight);
+		}
+		tensed_field.fragments.insert(fragment, false);
 	}
 
 	pub fn deflate_tense(&mut self) -> Result<(), Error> {
@@ -414,7 +532,7 @@ impl AnnouncementOptions {
 		let mut tmp_present = vec![];
 		let mut used = vec![];
 		self.iterate_all_fragments(
-			&mut |name: &str, _field: &Field, fragment: &str| -> (bool, Result<(), Error>) {
+			&mut |name: &str, field: &Field, fragment: &str| -> (bool, Result<(), Error>) {
 				let mut past_fragment = fragment.to_string();
 				let mut present_fragment = fragment.to_string();
 				for (field_name, field) in &self.tense {
@@ -427,8 +545,9 @@ impl AnnouncementOptions {
 					used.push((name.to_string(), fragment.to_string()));
 					// *_field.fragments.get_mut(fragment).unwrap() = true;
 				}
-				tmp_past.push((name.to_string(), past_fragment));
-				tmp_present.push((name.to_string(), present_fragment));
+				let weight = field.weight(fragment);
+				tmp_past.push((name.to_string(), past_fragment, weight));
+				tmp_present.push((name.to_string(), present_fragment, weight));
 				(false, Ok(()))
 			},
 			Ok(()),
@@ -442,19 +561,21 @@ impl AnnouncementOptions {
 				.get_mut(&fragment)
 				.unwrap() = true;
 		}
-		for (name, fragment) in tmp_past {
+		for (name, fragment, weight) in tmp_past {
 			Self::add_and_insert(
 				&mut self.past,
 				&name,
 				fragment,
+				weight,
 				self.neutral.get(&name).unwrap(),
 			);
 		}
-		for (name, fragment) in tmp_present {
+		for (name, fragment, weight) in tmp_present {
 			Self::add_and_insert(
 				&mut self.present,
 				&name,
 				fragment,
+				weight,
 				self.neutral.get(&name).unwrap(),
 			);
 		}
@@ -535,21 +656,44 @@ impl AnnouncementOptions {
 		Ok(())
 	}
 
-	fn conjunctions_have_no_delimiter(&self) -> Result<(), Error> {
-		for c in &self.conjunctions {
+	// Conjunctions that hold a delimiter would be spoken as is, so stored ones are skipped.
+	fn conjunctions_have_no_delimiter(&mut self, strict: bool) -> Result<(), Error> {
+		for c in self
+			.conjunctions
+			.iter()
+			.chain(self.conjunction_pools.iter())
+		{
 			if c.contains(FIELD_DELIMITER) {
-				return Err(Error::DelimiterNotAllowed {
+				let error = Error::DelimiterNotAllowed {
 					delimiter: FIELD_DELIMITER,
 					conjunction: c.clone(),
-				});
+				};
+				if strict {
+					return Err(error);
+				}
+				warn!("Skipping RJ conjunction: {}", error);
 			}
 		}
+		let keep = |c: &String| !c.contains(FIELD_DELIMITER);
+		self.conjunctions.retain(keep);
+		for pool in [
+			&mut self.conjunction_pools.prev_next,
+			&mut self.conjunction_pools.next_next_next,
+		]
+		.into_iter()
+		.flatten()
+		{
+			pool.retain(keep);
+		}
 		Ok(())
 	}
 
+	/// Parses the script of a user. Scripts being saved are `strict`ly checked, while stored
+	/// scripts are loaded without the parts that fail checks added since they were saved.
 	pub fn from_user(
 		user_opts: &UserAnnouncementOptions,
 		depth_limit: usize,
+		strict: bool,
 	) -> Result<Self, Error> {
 		let mut opts = Self {
 			present: BTreeMap::new(),
@@ -562,17 +706,20 @@ impl AnnouncementOptions {
 				.unwrap_or(&FieldsToAnnounce::default())
 				.clone(),
 			conjunctions: user_opts.conjunctions.as_ref().unwrap_or(&vec![]).clone(),
+			conjunction_pools: user_opts.conjunction_pools.clone().unwrap_or_default(),
 		};
 		opts.build_map(user_opts)?;
 		opts.has_self_dependency()?;
 		opts.uses_reserved_name()?;
 		opts.has_delimiter_only_at_start_end()?;
 		opts.verify_missing_name()?;
+		opts.verify_tense_fields(strict)?;
+		opts.verify_optional_probabilities()?;
 		opts.deflate(depth_limit)?;
 		opts.deflate_tense()?;
 		opts.each_field_is_resolved_once(depth_limit)?;
 		opts.remove_unresolved(depth_limit)?;
-		opts.conjunctions_have_no_delimiter()?;
+		opts.conjunctions_have_no_delimiter(strict)?;
 		Ok(opts)
 	}
 
@@ -590,17 +737,17 @@ impl AnnouncementOptions {
 
 	#[cfg(test)]
 	pub fn hi_default() -> Self {
-		Self::from_user(&UserAnnouncementOptions::hi_default(), 5).unwrap()
+		Self::from_user(&UserAnnouncementOptions::hi_default(), 5, true).unwrap()
 	}
 
 	#[cfg(test)]
 	pub fn en_default() -> Self {
-		Self::from_user(&UserAnnouncementOptions::en_default(), 5).unwrap()
+		Self::from_user(&UserAnnouncementOptions::en_default(), 5, true).unwrap()
 	}
 
 	#[cfg(test)]
 	pub fn tutorial_default() -> Self {
-		Self::from_user(&UserAnnouncementOptions::tutorial_default(), 5).unwrap()
+		Self::from_user(&UserAnnouncementOptions::tutorial_default(), 5, true).unwrap()
 	}
 }
 
@@ -651,7 +798,7 @@ mod tests {
 	fn from_user_valid() {
 		let user_opts: UserAnnouncementOptions = serde_json::from_str(sample_input()).unwrap();
 
-		let r = AnnouncementOptions::from_user(&user_opts, DEFAULT_DEPTH_LIMIT);
+		let r = AnnouncementOptions::from_user(&user_opts, DEFAULT_DEPTH_LIMIT, true);
 		assert!(r.is_ok());
 	}
 
@@ -663,8 +810,9 @@ mod tests {
 			name: "announce_title".to_string(),
 			whole: true,
 			fragments: vec![],
+			weights: None,
 		});
-		let r = AnnouncementOptions::from_user(&user_opts, DEFAULT_DEPTH_LIMIT);
+		let r = AnnouncementOptions::from_user(&user_opts, DEFAULT_DEPTH_LIMIT, true);
 		assert!(matches!(r.unwrap_err(), Error::DuplicateFragment(..)));
 	}
 
@@ -676,8 +824,9 @@ mod tests {
 			name: "user1".to_string(),
 			whole: true,
 			fragments: vec![get_delimited_name("user2"), get_delimited_name("user1")],
+			weights: None,
 		});
-		let r = AnnouncementOptions::from_user(&user_opts, DEFAULT_DEPTH_LIMIT);
+		let r = AnnouncementOptions::from_user(&user_opts, DEFAULT_DEPTH_LIMIT, true);
 		assert!(matches!(r.unwrap_err(), Error::SelfRecursion { .. }));
 	}
 
@@ -689,8 +838,9 @@ mod tests {
 			name: "title".to_string(),
 			whole: true,
 			fragments: vec![get_delimited_name("user2"), get_delimited_name("user1")],
+			weights: None,
 		});
-		let r = AnnouncementOptions::from_user(&user_opts, DEFAULT_DEPTH_LIMIT);
+		let r = AnnouncementOptions::from_user(&user_opts, DEFAULT_DEPTH_LIMIT, true);
 		assert!(matches!(
 			r.unwrap_err(),
 			Error::FragmentUsesReservedName { .. }
@@ -705,8 +855,9 @@ mod tests {
 			name: "user1".to_string(),
 			whole: true,
 			fragments: vec!["adf ^user2".to_string()],
+			weights: None,
 		});
-		let r = AnnouncementOptions::from_user(&user_opts, DEFAULT_DEPTH_LIMIT);
+		let r = AnnouncementOptions::from_user(&user_opts, DEFAULT_DEPTH_LIMIT, true);
 		assert!(matches!(
 			r.unwrap_err(),
 			Error::OddNumberOfDelimiters { .. }
@@ -721,8 +872,9 @@ mod tests {
 			name: "user1".to_string(),
 			whole: true,
 			fragments: vec!["adf ^us^er2".to_string()],
+			weights: None,
 		});
-		let r = AnnouncementOptions::from_user(&user_opts, DEFAULT_DEPTH_LIMIT);
+		let r = AnnouncementOptions::from_user(&user_opts, DEFAULT_DEPTH_LIMIT, true);
 		assert!(matches!(r.unwrap_err(), Error::InterleavedDelimiter { .. }));
 	}
 
@@ -734,11 +886,86 @@ mod tests {
 			name: "user1".to_string(),
 			whole: true,
 			fragments: vec!["Next one is a ^cat^ song.".to_string()],
+			weights: None,
 		});
-		let r = AnnouncementOptions::from_user(&user_opts, DEFAULT_DEPTH_LIMIT);
+		let r = AnnouncementOptions::from_user(&user_opts, DEFAULT_DEPTH_LIMIT, true);
 		assert!(matches!(r.unwrap_err(), Error::ExpansionFailed { .. }));
 	}
 
+	#[test]
+	fn from_user_uses_unknown_field_in_tense() {
+		let mut user_opts: UserAnnouncementOptions = serde_json::from_str(sample_input()).unwrap();
+
+		user_opts.patterns.push(UserField {
+			name: "user1".to_string(),
+			whole: true,
+			fragments: vec!["^was^ ^title^".to_string()],
+			weights: None,
+		});
+		user_opts.tense_patterns = Some(vec![TensedUserField {
+			name: "was".to_string(),
+			past: "^cat^ was".to_string(),
+			present: "is".to_string(),
+		}]);
+		let r = AnnouncementOptions::from_user(&user_opts, DEFAULT_DEPTH_LIMIT, true);
+		assert!(matches!(r.unwrap_err(), Error::UnresolvedTenseField { .. }));
+	}
+
+	#[test]
+	fn from_user_self_reference_in_tense() {
+		let mut user_opts: UserAnnouncementOptions = serde_json::from_str(sample_input()).unwrap();
+
+		user_opts.patterns.push(UserField {
+			name: "user1".to_string(),
+			whole: true,
+			fragments: vec!["^was^ ^title^".to_string()],
+			weights: None,
+		});
+		user_opts.tense_patterns = Some(vec![TensedUserField {
+			name: "was".to_string(),
+			past: "was".to_string(),
+			present: "^was^ ^was^".to_string(),
+		}]);
+		let r = AnnouncementOptions::from_user(&user_opts, DEFAULT_DEPTH_LIMIT, true);
+		assert!(matches!(r.unwrap_err(), Error::SelfRecursion { .. }));
+	}
+
+	#[test]
+	fn from_user_uses_odd_delimiter_in_tense() {
+		let mut user_opts: UserAnnouncementOptions = serde_json::from_str(sample_input()).unwrap();
+
+		user_opts.patterns.push(UserField {
+			name: "user1".to_string(),
+			whole: true,
+			fragments: vec!["^was^ ^title^".to_string()],
+			weights: None,
+		});
+		user_opts.tense_patterns = Some(vec![TensedUserField {
+			name: "was".to_string(),
+			past: "^title was".to_string(),
+			present: "is".to_string(),
+		}]);
+		let r = AnnouncementOptions::from_user(&user_opts, DEFAULT_DEPTH_LIMIT, true);
+		assert!(matches!(
+			r.unwrap_err(),
+			Error::OddNumberOfDelimiters { .. }
+		));
+	}
+
+	#[test]
+	fn from_user_unused_tense() {
+		let mut user_opts: UserAnnouncementOptions = serde_json::from_str(sample_input()).unwrap();
+
+		user_opts.tense_patterns = Some(vec![TensedUserField {
+			name: "was".to_string(),
+			past: "was".to_string(),
+			present: "is".to_string(),
+		}]);
+		let r = AnnouncementOptions::from_user(&user_opts, DEFAULT_DEPTH_LIMIT, true);
+		assert!(matches!(r.unwrap_err(), Error::UnusedTenseField { .. }));
+		assert!(AnnouncementOptions::from_user(&user_opts, DEFAULT_DEPTH_LIMIT, false).is_ok());
+	}
+
 	#[test]
 	fn from_user_recursive_dependency() {
 		let user_opts: UserAnnouncementOptions = serde_json::from_str(
@@ -777,7 +1004,7 @@ mod tests {
         "#,
 		)
 		.unwrap();
-		let r = AnnouncementOptions::from_user(&user_opts, DEFAULT_DEPTH_LIMIT);
+		let r = AnnouncementOptions::from_user(&user_opts, DEFAULT_DEPTH_LIMIT, true);
 		assert!(matches!(r.unwrap_err(), Error::RecursiveDependency { .. }));
 	}
 
@@ -848,7 +1075,7 @@ mod tests {
         "#,
 		)
 		.unwrap();
-		let r = AnnouncementOptions::from_user(&user_opts, 5);
+		let r = AnnouncementOptions::from_user(&user_opts, 5, true);
 		assert!(r.is_ok());
 	}
 
@@ -1,14 +1,19 @@
 use lazy_static::lazy_static;
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use std::collections::BTreeMap;
-use std::collections::BTreeSet;
 use std::collections::HashMap;
+use std::sync::Mutex;
 
 use crate::app::{
 	index::Song,
 	rj::{
 		error::ParseError as Error,
+		escape_xml,
 		parse::*,
-		user_opts::{FieldsToAnnounce, Inclusion, UserAnnouncementOptions},
+		user_opts::{
+			ConjunctionPools, ConjunctionPosition, FieldsToAnnounce, Inclusion,
+			UserAnnouncementOptions,
+		},
 	},
 };
 use bitflags::bitflags;
@@ -106,6 +111,20 @@ impl FieldSet {
 		]
 	}
 
+	// Returns the names of the fields in the set, like "title".
+	fn names(self) -> Vec<String> {
+		Self::iter_flags()
+			.into_iter()
+			.filter(|flag| self.contains(*flag))
+			.filter_map(|flag| {
+				DELIMITED_FIELD_TO_FIELDSET
+					.iter()
+					.find(|(_, set)| **set == flag)
+					.map(|(word, _)| strip_delimiters(word))
+			})
+			.collect()
+	}
+
 	pub fn from_word(value: &str) -> FieldSet {
 		*DELIMITED_FIELD_TO_FIELDSET
 			.get(value)
@@ -131,6 +150,27 @@ impl FieldSet {
 		let mut optional = FieldSet::empty();
 		let mut exclude = FieldSet::empty();
 
+		Self::update_from_tags(
+			&mut include,
+			&mut optional,
+			&mut exclude,
+			tags.id,
+			FieldSet::ID,
+		);
+		Self::update_from_tags(
+			&mut include,
+			&mut optional,
+			&mut exclude,
+			tags.path,
+			FieldSet::PATH,
+		);
+		Self::update_from_tags(
+			&mut include,
+			&mut optional,
+			&mut exclude,
+			tags.parent,
+			FieldSet::PARENT,
+		);
 		Self::update_from_tags(
 			&mut include,
 			&mut optional,
@@ -180,6 +220,13 @@ impl FieldSet {
 			tags.album,
 			FieldSet::ALBUM,
 		);
+		Self::update_from_tags(
+			&mut include,
+			&mut optional,
+			&mut exclude,
+			tags.artwork,
+			FieldSet::ARTWORK,
+		);
 		Self::update_from_tags(
 			&mut include,
 			&mut optional,
@@ -220,11 +267,61 @@ impl FieldSet {
 	}
 }
 
-fn wrap_name(name: &str, ssml: bool) -> String {
+fn wrap_name(name: &str, ssml: bool, pronunciations: &BTreeMap<String, String>) -> String {
 	if !ssml {
 		return name.to_string();
 	}
-	format!(r#"<say-as interpret-as="name">{}</say-as>"#, name)
+	if let Some(pronunciation) = pronunciations.get(name) {
+		return pronunciation_ssml(pronunciation);
+	}
+	if !name
+		.split_whitespace()
+		.any(|word| pronunciations.contains_key(word))
+	{
+		return say_as_name(name);
+	}
+
+	// Words with a pronunciation are replaced, the words around them are still said as names.
+	let mut parts = Vec::new();
+	let mut plain_words: Vec<&str> = Vec::new();
+	for word in name.split_whitespace() {
+		match pronunciations.get(word) {
+			Some(pronunciation) => {
+				if !plain_words.is_empty() {
+					parts.push(say_as_name(&plain_words.join(" ")));
+					plain_words.clear();
+				}
+				parts.push(pronunciation_ssml(pronunciation));
+			}
+			None => plain_words.push(word),
+		}
+	}
+	if !plain_words.is_empty() {
+		parts.push(say_as_name(&plain_words.join(" ")));
+	}
+	parts.join(" ")
+}
+
+fn say_as_name(name: &str) -> String {
+	format!(
+		r#"<say-as interpret-as="name">{}</say-as>"#,
+		escape_xml(name)
+	)
+}
+
+// Pronunciations are usually SSML, those that are plain text are escaped like any other text.
+fn pronunciation_ssml(pronunciation: &str) -> String {
+	match pronunciation.trim_start().starts_with('<') {
+		true => pronunciation.to_owned(),
+		false => escape_xml(pronunciation),
+	}
+}
+
+fn wrap_text(text: &str, ssml: bool) -> String {
+	match ssml {
+		true => escape_xml(text),
+		false => text.to_owned(),
+	}
 }
 
 fn wrap_year(year: i32, ssml: bool) -> String {
@@ -241,11 +338,74 @@ fn wrap_number(number: i32, ssml: bool) -> String {
 	format!(r#"<say-as interpret-as="cardinal">{}</say-as>"#, number)
 }
 
-fn extract_map_and_fieldset(song: &Song, ssml: bool) -> (HashMap<FieldSet, String>, FieldSet) {
+// Spells out a duration in seconds, like "4 minutes and 23 seconds".
+fn duration_prose(duration: i32) -> String {
+	let duration = duration.max(0);
+	let units = [
+		(duration / 3600, "hour"),
+		(duration % 3600 / 60, "minute"),
+		(duration % 60, "second"),
+	];
+	let mut parts: Vec<String> = units
+		.iter()
+		.filter(|(count, _)| *count > 0)
+		.map(|(count, unit)| match count {
+			1 => format!("1 {}", unit),
+			_ => format!("{} {}s", count, unit),
+		})
+		.collect();
+	match parts.len() {
+		0 => "0 seconds".to_owned(),
+		1 => parts.remove(0),
+		_ => {
+			let last = parts.pop().unwrap();
+			format!("{} and {}", parts.join(", "), last)
+		}
+	}
+}
+
+fn wrap_duration(duration: i32, ssml: bool) -> String {
+	if !ssml {
+		return duration_prose(duration);
+	}
+	let duration = duration.max(0);
+	let (format, value) = if duration >= 3600 {
+		(
+			"hms",
+			format!(
+				"{}:{:02}:{:02}",
+				duration / 3600,
+				duration % 3600 / 60,
+				duration % 60
+			),
+		)
+	} else {
+		("ms", format!("{}:{:02}", duration / 60, duration % 60))
+	};
+	format!(
+		r#"<say-as interpret-as="duration" format="{}">{}</say-as>"#,
+		format, value
+	)
+}
+
+fn extract_map_and_fieldset(
+	song: &Song,
+	ssml: bool,
+	pronunciations: &BTreeMap<String, String>,
+) -> (HashMap<FieldSet, String>, FieldSet) {
 	let mut map = HashMap::new();
 
 	let mut set = FieldSet::empty();
 
+	set |= FieldSet::ID;
+	map.insert(FieldSet::ID, wrap_number(song.id(), ssml));
+
+	set |= FieldSet::PATH;
+	map.insert(FieldSet::PATH, wrap_text(&song.path, ssml));
+
+	set |= FieldSet::PARENT;
+	map.insert(FieldSet::PARENT, wrap_text(&song.parent, ssml));
+
 	if let Some(track_number) = song.track_number {
 		set |= FieldSet::TRACK_NUMBER;
 		map.insert(FieldSet::TRACK_NUMBER, wrap_number(track_number, ssml));
@@ -258,17 +418,26 @@ fn extract_map_and_fieldset(song: &Song, ssml: bool) -> (HashMap<FieldSet, Strin
 
 	if let Some(title) = &song.title {
 		set |= FieldSet::TITLE;
-		map.insert(FieldSet::TITLE, wrap_name(title, ssml));
+		map.insert(FieldSet::TITLE, wrap_name(title, ssml, pronunciations));
 	}
 
-	if let Some(artist) = &song.artist {
+	// Slashes are left alone since they show up in artist names such as "AC/DC"
+	let artists = song.artists(&[';']);
+	if !artists.is_empty() {
 		set |= FieldSet::ARTIST;
-		map.insert(FieldSet::ARTIST, wrap_name(artist, ssml));
+		let artists: Vec<String> = artists
+			.iter()
+			.map(|artist| wrap_name(artist, ssml, pronunciations))
+			.collect();
+		map.insert(FieldSet::ARTIST, artists.join(", "));
 	}
 
 	if let Some(album_artist) = &song.album_artist {
 		set |= FieldSet::ALBUM_ARTIST;
-		map.insert(FieldSet::ALBUM_ARTIST, wrap_name(album_artist, ssml));
+		map.insert(
+			FieldSet::ALBUM_ARTIST,
+			wrap_name(album_artist, ssml, pronunciations),
+		);
 	}
 
 	if let Some(year) = song.year {
@@ -278,32 +447,43 @@ fn extract_map_and_fieldset(song: &Song, ssml: bool) -> (HashMap<FieldSet, Strin
 
 	if let Some(album) = &song.album {
 		set |= FieldSet::ALBUM;
-		map.insert(FieldSet::ALBUM, wrap_name(album, ssml));
+		map.insert(FieldSet::ALBUM, wrap_name(album, ssml, pronunciations));
+	}
+
+	if let Some(artwork) = &song.artwork {
+		set |= FieldSet::ARTWORK;
+		map.insert(FieldSet::ARTWORK, wrap_text(artwork, ssml));
 	}
 
 	if let Some(duration) = song.duration {
 		set |= FieldSet::DURATION;
-		map.insert(FieldSet::DURATION, wrap_number(duration, ssml));
+		map.insert(FieldSet::DURATION, wrap_duration(duration, ssml));
 	}
 
 	if let Some(lyricist) = &song.lyricist {
 		set |= FieldSet::LYRICIST;
-		map.insert(FieldSet::LYRICIST, wrap_name(lyricist, ssml));
+		map.insert(
+			FieldSet::LYRICIST,
+			wrap_name(lyricist, ssml, pronunciations),
+		);
 	}
 
 	if let Some(composer) = &song.composer {
 		set |= FieldSet::COMPOSER;
-		map.insert(FieldSet::COMPOSER, wrap_name(composer, ssml));
+		map.insert(
+			FieldSet::COMPOSER,
+			wrap_name(composer, ssml, pronunciations),
+		);
 	}
 
 	if let Some(genre) = &song.genre {
 		set |= FieldSet::GENRE;
-		map.insert(FieldSet::GENRE, wrap_name(genre, ssml));
+		map.insert(FieldSet::GENRE, wrap_name(genre, ssml, pronunciations));
 	}
 
 	if let Some(label) = &song.label {
 		set |= FieldSet::LABEL;
-		map.insert(FieldSet::LABEL, wrap_name(label, ssml));
+		map.insert(FieldSet::LABEL, wrap_name(label, ssml, pronunciations));
 	}
 
 	(map, set)
@@ -319,7 +499,10 @@ impl From<&str> for FieldSet {
 	}
 }
 
-fn walk_map(self_map: &mut BTreeMap<FieldSet, BTreeSet<String>>, map: &BTreeMap<String, Field>) {
+fn walk_map(
+	self_map: &mut BTreeMap<FieldSet, BTreeMap<String, u32>>,
+	map: &BTreeMap<String, Field>,
+) {
 	for (_, field) in map.iter() {
 		if !field.is_whole() {
 			continue;
@@ -327,25 +510,25 @@ fn walk_map(self_map: &mut BTreeMap<FieldSet, BTreeSet<String>>, map: &BTreeMap<
 
 		for fragment in field.iter_fragments() {
 			let key = FieldSet::from(fragment.as_str());
-			if let Some(v) = self_map.get_mut(&key) {
-				v.insert(fragment.to_owned());
-			} else {
-				let mut v = BTreeSet::new();
-				v.insert(fragment.to_owned());
-				self_map.insert(key, v);
-			}
+			let weight = field.weight(fragment);
+			let fragments = self_map.entry(key).or_insert_with(BTreeMap::new);
+			let entry = fragments.entry(fragment.to_owned()).or_insert(weight);
+			*entry = (*entry).max(weight);
 		}
 	}
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct ScriptCache {
-	past: BTreeMap<FieldSet, BTreeSet<String>>,
-	present: BTreeMap<FieldSet, BTreeSet<String>>,
+	past: BTreeMap<FieldSet, BTreeMap<String, u32>>,
+	present: BTreeMap<FieldSet, BTreeMap<String, u32>>,
 	conjunctions: Vec<String>,
+	conjunction_pools: ConjunctionPools,
 	include: FieldSet,
 	optional: FieldSet,
+	optional_probabilities: BTreeMap<FieldSet, f64>,
 	exclude: FieldSet,
+	rng: Mutex<StdRng>,
 }
 
 impl From<&AnnouncementOptions> for ScriptCache {
@@ -355,9 +538,17 @@ impl From<&AnnouncementOptions> for ScriptCache {
 			past: BTreeMap::new(),
 			present: BTreeMap::new(),
 			conjunctions: opts.conjunctions.clone(),
+			conjunction_pools: opts.conjunction_pools.clone(),
 			include,
 			optional,
+			optional_probabilities: opts
+				.tags_to_announce
+				.optional_probabilities
+				.iter()
+				.map(|(name, p)| (FieldSet::from_word(&get_delimited_name(name)), *p))
+				.collect(),
 			exclude,
+			rng: Mutex::new(StdRng::from_entropy()),
 		};
 
 		walk_map(&mut cache.past, opts.get_past());
@@ -374,56 +565,95 @@ impl From<&AnnouncementOptions> for ScriptCache {
 
 impl ScriptCache {
 	pub fn create(opts_str: &str) -> Result<ScriptCache, Error> {
+		Self::create_with_seed(opts_str, None)
+	}
+
+	/// Same as `create` for a script that was already saved. Parts of it that fail checks
+	/// added since then are skipped instead of failing the whole script.
+	pub fn load(opts_str: &str) -> Result<ScriptCache, Error> {
+		Self::build(opts_str, None, false)
+	}
+
+	/// Same as `create` but announcements are picked using an RNG seeded with `seed`.
+	/// With `None` the RNG is seeded from entropy.
+	pub fn create_with_seed(opts_str: &str, seed: Option<u64>) -> Result<ScriptCache, Error> {
+		Self::build(opts_str, seed, true)
+	}
+
+	fn build(opts_str: &str, seed: Option<u64>, strict: bool) -> Result<ScriptCache, Error> {
 		let mut user_opts: UserAnnouncementOptions =
 			toml::from_str(opts_str).map_err(|e| Error::FailedToDeserialize(e.to_string()))?;
-		let opts = AnnouncementOptions::from_user(&user_opts, DEFAULT_DEPTH_LIMIT)?;
+		let depth_limit = user_opts.depth_limit.unwrap_or(DEFAULT_DEPTH_LIMIT);
+		let opts = AnnouncementOptions::from_user(&user_opts, depth_limit, strict)?;
 		if user_opts.tags_to_announce.is_none() {
 			user_opts.tags_to_announce = Some(FieldsToAnnounce::default());
 		}
 
-		let cache = ScriptCache::from(&opts);
+		let mut cache = ScriptCache::from(&opts);
+		if let Some(seed) = seed {
+			cache.rng = Mutex::new(StdRng::seed_from_u64(seed));
+		}
 		Ok(cache)
 	}
 
+	// Picks a fragment with probability proportional to its weight.
+	fn pick_fragment(rng: &mut StdRng, fragments: &BTreeMap<String, u32>) -> String {
+		if fragments
+			.values()
+			.all(|weight| *weight == DEFAULT_FRAGMENT_WEIGHT)
+		{
+			let rand_index = rng.gen::<usize>() % (fragments.len());
+			return fragments.keys().nth(rand_index).unwrap().to_owned();
+		}
+		let total: u64 = fragments.values().map(|weight| *weight as u64).sum();
+		let mut point = rng.gen_range(0..total);
+		for (fragment, weight) in fragments {
+			if point < *weight as u64 {
+				return fragment.to_owned();
+			}
+			point -= *weight as u64;
+		}
+		unreachable!()
+	}
+
 	fn get_subset_tags(
-		map: &BTreeMap<FieldSet, BTreeSet<String>>,
+		rng: &mut StdRng,
+		map: &BTreeMap<FieldSet, BTreeMap<String, u32>>,
 		set: FieldSet,
+		flag: FieldSet,
 	) -> Option<(FieldSet, String)> {
-		let start_point = rand::random::<usize>() % map.len();
-		let mut found = None;
-		for (index, (current_tag, current_set)) in map.iter().enumerate() {
-			if !set.contains(*current_tag) {
-				continue;
-			}
-			if index >= start_point {
-				let rand_index = rand::random::<usize>() % (current_set.len());
-				return Some((
-					current_tag.to_owned(),
-					current_set.iter().nth(rand_index).unwrap().to_owned(),
-				));
-			}
-			if found.is_none() {
-				let rand_index = rand::random::<usize>() % (current_set.len());
-				found = Some((
-					current_tag.to_owned(),
-					current_set.iter().nth(rand_index).unwrap().to_owned(),
-				));
-			}
+		// Any bucket that announces `flag` and is covered by `set` is eligible, and every
+		// one of them is equally likely to be picked.
+		let candidates: Vec<_> = map
+			.iter()
+			.filter(|(current_tag, _)| set.contains(**current_tag) && current_tag.contains(flag))
+			.collect();
+		if candidates.is_empty() {
+			return None;
 		}
-		found
+		let (current_tag, current_set) = candidates[rng.gen::<usize>() % candidates.len()];
+		Some((
+			current_tag.to_owned(),
+			Self::pick_fragment(rng, current_set),
+		))
 	}
 
-	fn get_tag_announcement(map: &BTreeMap<FieldSet, BTreeSet<String>>, set: FieldSet) -> String {
+	// Fields are covered in the order of `FieldSet::iter_flags`, so that the same seed and
+	// song always assemble the same announcement.
+	fn get_tag_announcement(
+		rng: &mut StdRng,
+		map: &BTreeMap<FieldSet, BTreeMap<String, u32>>,
+		set: FieldSet,
+	) -> String {
 		let mut need = set;
-		let mut have = FieldSet::empty();
 		let mut announcement = "".to_owned();
-		while !need.is_empty() {
-			if let Some((found_set, found_str)) = Self::get_subset_tags(map, need) {
+		for flag in FieldSet::iter_flags() {
+			if !need.contains(flag) {
+				continue;
+			}
+			if let Some((found_set, found_str)) = Self::get_subset_tags(rng, map, need, flag) {
 				announcement = announcement + " " + &found_str;
 				need = need.difference(found_set);
-				have = have.union(found_set);
-			} else {
-				break;
 			}
 		}
 		announcement
@@ -434,20 +664,30 @@ impl ScriptCache {
 		song: &Song,
 		present: bool,
 		enable_ssml: bool,
+		pronunciations: &BTreeMap<String, String>,
 	) -> Option<String> {
-		let (field_song, mut have) = extract_map_and_fieldset(song, enable_ssml);
+		let (field_song, mut have) = extract_map_and_fieldset(song, enable_ssml, pronunciations);
 		have = have.difference(self.exclude);
 		let filtered_include = have.intersection(self.include);
 		let mut filtered_optional = have.intersection(self.optional);
+		let mut rng = self.rng.lock().unwrap();
 
 		// Randomly select a subset of optional fields.
 		for flag in FieldSet::iter_flags() {
-			if filtered_optional & flag == flag && !rand::random::<bool>() {
+			if filtered_optional & flag != flag {
+				continue;
+			}
+			let keep = match self.optional_probabilities.get(&flag) {
+				Some(probability) => rng.gen_bool(*probability),
+				None => rng.gen::<bool>(),
+			};
+			if !keep {
 				filtered_optional.toggle(flag);
 			}
 		}
 
 		let mut announcement = Self::get_tag_announcement(
+			&mut rng,
 			match present {
 				true => &self.present,
 				false => &self.past,
@@ -455,6 +695,9 @@ impl ScriptCache {
 			filtered_include.union(filtered_optional),
 		);
 		announcement = announcement.trim().to_string();
+		if enable_ssml {
+			announcement = escape_xml(&announcement);
+		}
 		let tmp = announcement.clone();
 
 		for word in tmp.split_whitespace() {
@@ -469,8 +712,41 @@ impl ScriptCache {
 		}
 	}
 
-	pub fn get_conjunction(&self) -> String {
-		self.conjunctions[rand::random::<usize>() % self.conjunctions.len()].to_string()
+	/// Returns the names of the required fields that `song` has no value for. Such songs
+	/// are announced without those fields, if at all.
+	pub fn missing_required(&self, song: &Song) -> Vec<String> {
+		let (_, have) = extract_map_and_fieldset(song, false, &BTreeMap::new());
+		self.include.difference(have).names()
+	}
+
+	/// Returns the number of cached (past, present) fragments.
+	pub fn fragment_counts(&self) -> (usize, usize) {
+		let count = |map: &BTreeMap<FieldSet, BTreeMap<String, u32>>| -> usize {
+			map.values().map(|fragments| fragments.len()).sum()
+		};
+		(count(&self.past), count(&self.present))
+	}
+
+	/// Returns the number of conjunctions configured by the script.
+	pub fn conjunction_count(&self) -> usize {
+		self.conjunctions
+			.iter()
+			.chain(self.conjunction_pools.iter())
+			.filter(|c| !c.is_empty())
+			.count()
+	}
+
+	pub fn get_conjunction(&self, position: ConjunctionPosition) -> String {
+		let conjunctions = match self.conjunction_pools.get(position) {
+			Some(pool) if !pool.is_empty() => pool,
+			_ if position == ConjunctionPosition::NextNextNext => &self.conjunctions,
+			_ => return "".to_string(),
+		};
+		if conjunctions.is_empty() {
+			return "".to_string();
+		}
+		let index = self.rng.lock().unwrap().gen::<usize>() % conjunctions.len();
+		conjunctions[index].to_string()
 	}
 }
 
@@ -493,4 +769,383 @@ mod tests {
 		let ex = ScriptCache::create(&UserAnnouncementOptions::tutorial_script_toml()).unwrap();
 		println!("ex_script: {:#?}", ex);
 	}
+
+	#[test]
+	fn depth_limit_from_script() {
+		let script = r#"
+[[pattern]]
+name = 'outer'
+whole = true
+fragments = ['^inner^']
+
+[[pattern]]
+name = 'inner'
+whole = false
+fragments = ['song ^title^']
+"#;
+		assert!(ScriptCache::create(script).is_ok());
+
+		let shallow = format!("depth_limit = 0\n{}", script);
+		assert!(matches!(
+			ScriptCache::create(&shallow).unwrap_err(),
+			Error::TooDeep { .. }
+		));
+	}
+
+	#[test]
+	fn durations_are_spelled_out() {
+		assert_eq!(wrap_duration(263, false), "4 minutes and 23 seconds");
+		assert_eq!(wrap_duration(60, false), "1 minute");
+		assert_eq!(wrap_duration(3601, false), "1 hour and 1 second");
+		assert_eq!(
+			wrap_duration(3723, false),
+			"1 hour, 2 minutes and 3 seconds"
+		);
+		assert_eq!(wrap_duration(0, false), "0 seconds");
+		assert_eq!(
+			wrap_duration(263, true),
+			r#"<say-as interpret-as="duration" format="ms">4:23</say-as>"#
+		);
+		assert_eq!(
+			wrap_duration(3723, true),
+			r#"<say-as interpret-as="duration" format="hms">1:02:03</say-as>"#
+		);
+		assert_eq!(
+			wrap_number(7, true),
+			r#"<say-as interpret-as="cardinal">7</say-as>"#
+		);
+	}
+
+	#[test]
+	fn weighted_fragments() {
+		let script = r#"
+[[pattern]]
+name = 'next'
+whole = true
+fragments = ['Here is ^title^', 'Coming up ^title^']
+weights = [1, 9]
+"#;
+		let cache = ScriptCache::create_with_seed(script, Some(7)).unwrap();
+		let fragments = cache.present.get(&FieldSet::TITLE).unwrap();
+		assert_eq!(fragments.get("Coming up ^title^"), Some(&9));
+		assert_eq!(fragments.get("Here is ^title^"), Some(&1));
+
+		let mut rng = StdRng::seed_from_u64(7);
+		let picked_heavy = (0..1000)
+			.filter(|_| ScriptCache::pick_fragment(&mut rng, fragments) == "Coming up ^title^")
+			.count();
+		assert!(picked_heavy > 800);
+
+		let mismatched = script.replace("[1, 9]", "[1]");
+		assert!(matches!(
+			ScriptCache::create(&mismatched).unwrap_err(),
+			Error::InvalidWeights { .. }
+		));
+	}
+
+	#[test]
+	fn conjunction_pools_by_position() {
+		let script = r#"
+conjunctions = ['and then']
+
+[conjunction_pools]
+prev_next = ['moving on']
+
+[[pattern]]
+name = 'next'
+whole = true
+fragments = ['Here is ^title^']
+"#;
+		let cache = ScriptCache::create(script).unwrap();
+		assert_eq!(
+			cache.get_conjunction(ConjunctionPosition::PrevNext),
+			"moving on"
+		);
+		assert_eq!(
+			cache.get_conjunction(ConjunctionPosition::NextNextNext),
+			"and then"
+		);
+		assert_eq!(cache.conjunction_count(), 2);
+
+		let flat = ScriptCache::create(&script.replace("prev_next = ['moving on']", "")).unwrap();
+		assert_eq!(flat.get_conjunction(ConjunctionPosition::PrevNext), "");
+
+		let delimited = script.replace("'moving on'", "'^title^'");
+		assert!(matches!(
+			ScriptCache::create(&delimited).unwrap_err(),
+			Error::DelimiterNotAllowed { .. }
+		));
+		let stored = ScriptCache::load(&delimited).unwrap();
+		assert_eq!(stored.get_conjunction(ConjunctionPosition::PrevNext), "");
+		assert_eq!(stored.conjunction_count(), 1);
+	}
+
+	#[test]
+	fn required_fields_are_always_announced() {
+		let script = UserAnnouncementOptions::en_default_script_toml();
+		let song = Song::builder("root/song.mp3")
+			.title("Title")
+			.artist("Artist")
+			.album("Album")
+			.lyricist("Lyricist")
+			.composer("Composer")
+			.build();
+		for seed in 0..100 {
+			let cache = ScriptCache::create_with_seed(&script, Some(seed)).unwrap();
+			for present in [true, false] {
+				let announcement = cache
+					.get_announcement(&song, present, false, &BTreeMap::new())
+					.unwrap();
+				for field in ["Title", "Artist", "Album", "Lyricist", "Composer"] {
+					assert!(announcement.contains(field), "{}", announcement);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn empty_maps_do_not_panic() {
+		let mut cache =
+			ScriptCache::create(&UserAnnouncementOptions::en_default_script_toml()).unwrap();
+		cache.present.clear();
+		cache.conjunctions.clear();
+		let song = Song::builder("root/song.mp3").title("Title").build();
+		assert_eq!(
+			cache.get_announcement(&song, true, false, &BTreeMap::new()),
+			None
+		);
+		assert!(cache
+			.get_announcement(&song, false, false, &BTreeMap::new())
+			.is_some());
+		assert_eq!(cache.get_conjunction(ConjunctionPosition::NextNextNext), "");
+	}
+
+	#[test]
+	fn pronunciations_replace_names() {
+		let mut pronunciations = BTreeMap::new();
+		pronunciations.insert(
+			"deadmau5".to_owned(),
+			r#"<sub alias="dead mouse">deadmau5</sub>"#.to_owned(),
+		);
+		pronunciations.insert(
+			"Rós".to_owned(),
+			r#"<phoneme alphabet="ipa" ph="roʊs">Rós</phoneme>"#.to_owned(),
+		);
+
+		assert_eq!(
+			wrap_name("deadmau5", true, &pronunciations),
+			r#"<sub alias="dead mouse">deadmau5</sub>"#
+		);
+		assert_eq!(
+			wrap_name("Sigur Rós", true, &pronunciations),
+			r#"<say-as interpret-as="name">Sigur</say-as> <phoneme alphabet="ipa" ph="roʊs">Rós</phoneme>"#
+		);
+		assert_eq!(
+			wrap_name("Metallica", true, &pronunciations),
+			r#"<say-as interpret-as="name">Metallica</say-as>"#
+		);
+		assert_eq!(wrap_name("deadmau5", false, &pronunciations), "deadmau5");
+
+		pronunciations.insert("R&B".to_owned(), "R and B".to_owned());
+		assert_eq!(wrap_name("R&B", true, &pronunciations), "R and B");
+		pronunciations.insert("AT&T".to_owned(), "A T & T".to_owned());
+		assert_eq!(wrap_name("AT&T", true, &pronunciations), "A T &amp; T");
+		assert_eq!(
+			wrap_name("Tom & Jerry", true, &pronunciations),
+			r#"<say-as interpret-as="name">Tom &amp; Jerry</say-as>"#
+		);
+	}
+
+	#[test]
+	fn seeded_announcements_are_reproducible() {
+		let script = UserAnnouncementOptions::en_default_script_toml();
+		let first = ScriptCache::create_with_seed(&script, Some(42)).unwrap();
+		let second = ScriptCache::create_with_seed(&script, Some(42)).unwrap();
+
+		let song = Song::builder("root/song.mp3")
+			.title("Title")
+			.artist("Artist")
+			.album("Album")
+			.year(1999)
+			.genre("Genre")
+			.build();
+
+		for _ in 0..10 {
+			assert_eq!(
+				first.get_announcement(&song, true, false, &BTreeMap::new()),
+				second.get_announcement(&song, true, false, &BTreeMap::new())
+			);
+			assert_eq!(
+				first.get_conjunction(ConjunctionPosition::NextNextNext),
+				second.get_conjunction(ConjunctionPosition::NextNextNext)
+			);
+		}
+	}
+
+	#[test]
+	fn announcements_follow_field_order() {
+		let script = r#"
+[[pattern]]
+name = 'song_genre'
+whole = true
+fragments = ['in ^genre^']
+
+[[pattern]]
+name = 'song_artist'
+whole = true
+fragments = ['by ^artist^', 'from ^artist^']
+
+[[pattern]]
+name = 'song_title'
+whole = true
+fragments = ['now ^title^']
+"#;
+		let song = Song::builder("root/song.mp3")
+			.title("Title")
+			.artist("Artist")
+			.genre("Genre")
+			.build();
+		for seed in 0..50 {
+			let first = ScriptCache::create_with_seed(script, Some(seed)).unwrap();
+			let second = ScriptCache::create_with_seed(script, Some(seed)).unwrap();
+			for present in [true, false] {
+				let announcement = first
+					.get_announcement(&song, present, false, &BTreeMap::new())
+					.unwrap();
+				assert_eq!(
+					Some(&announcement),
+					second
+						.get_announcement(&song, present, false, &BTreeMap::new())
+						.as_ref()
+				);
+				// Genre is optional, it follows the artist when it is announced.
+				assert!(announcement.starts_with("now Title "), "{}", announcement);
+				assert!(
+					announcement.ends_with(" Artist") || announcement.ends_with(" Artist in Genre"),
+					"{}",
+					announcement
+				);
+			}
+		}
+	}
+
+	#[test]
+	fn optional_fields_follow_their_probability() {
+		let script = r#"
+[[pattern]]
+name = 'song_title'
+whole = true
+fragments = ['now ^title^']
+
+[[pattern]]
+name = 'song_year'
+whole = true
+fragments = ['from ^year^']
+
+[[pattern]]
+name = 'song_album_artist'
+whole = true
+fragments = ['by ^album_artist^']
+
+[[pattern]]
+name = 'song_genre'
+whole = true
+fragments = ['in ^genre^']
+
+[tags_to_announce]
+track_number = 'Exclude'
+disc_number = 'Exclude'
+title = 'Required'
+artist = 'Exclude'
+album_artist = 'Optional'
+year = 'Optional'
+album = 'Exclude'
+duration = 'Exclude'
+lyricist = 'Exclude'
+composer = 'Exclude'
+genre = 'Optional'
+label = 'Exclude'
+
+[tags_to_announce.optional_probabilities]
+year = 1.0
+album_artist = 0.0
+"#;
+		let song = Song::builder("root/song.mp3")
+			.title("Title")
+			.year(1999)
+			.album_artist("Band")
+			.genre("Metal")
+			.build();
+		let mut genre_count = 0;
+		for seed in 0..100 {
+			let cache = ScriptCache::create_with_seed(script, Some(seed)).unwrap();
+			let announcement = cache
+				.get_announcement(&song, true, false, &BTreeMap::new())
+				.unwrap();
+			assert!(announcement.contains("1999"), "{}", announcement);
+			assert!(!announcement.contains("Band"), "{}", announcement);
+			if announcement.contains("Metal") {
+				genre_count += 1;
+			}
+		}
+		// Fields without a probability are still a coin flip.
+		assert!(genre_count > 0 && genre_count < 100);
+
+		let invalid = script.replace("year = 1.0", "year = 1.5");
+		assert!(matches!(
+			ScriptCache::create(&invalid).unwrap_err(),
+			Error::InvalidOptionalProbability { .. }
+		));
+		let unknown = script.replace("year = 1.0", "mood = 1.0");
+		assert!(matches!(
+			ScriptCache::create(&unknown).unwrap_err(),
+			Error::InvalidOptionalProbability { .. }
+		));
+	}
+
+	#[test]
+	fn missing_required_fields_are_reported() {
+		let cache =
+			ScriptCache::create(&UserAnnouncementOptions::en_default_script_toml()).unwrap();
+		let song = Song::builder("root/song.mp3")
+			.title("Title")
+			.album("Album")
+			.lyricist("Lyricist")
+			.year(1999)
+			.build();
+		assert_eq!(
+			cache.missing_required(&song),
+			vec!["artist".to_owned(), "composer".to_owned()]
+		);
+
+		let song = Song::builder("root/song.mp3")
+			.title("Title")
+			.artist("Artist")
+			.album("Album")
+			.lyricist("Lyricist")
+			.composer("Composer")
+			.build();
+		assert!(cache.missing_required(&song).is_empty());
+	}
+
+	#[test]
+	fn paths_are_escaped_in_ssml() {
+		let song = Song::builder("/music/AC&DC/<live>/song.mp3")
+			.parent("/music/AC&DC/<live>")
+			.artwork("/music/AC&DC/<live>/cover.jpg")
+			.build();
+		let (map, _) = extract_map_and_fieldset(&song, true, &BTreeMap::new());
+		assert_eq!(
+			map[&FieldSet::PATH],
+			"/music/AC&amp;DC/&lt;live&gt;/song.mp3"
+		);
+		assert_eq!(map[&FieldSet::PARENT], "/music/AC&amp;DC/&lt;live&gt;");
+		assert_eq!(
+			map[&FieldSet::ARTWORK],
+			"/music/AC&amp;DC/&lt;live&gt;/cover.jpg"
+		);
+
+		let (map, _) = extract_map_and_fieldset(&song, false, &BTreeMap::new());
+		assert_eq!(map[&FieldSet::PATH], "/music/AC&DC/<live>/song.mp3");
+	}
 }
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 #[derive(Debug, Serialize, Deserialize, Clone, Copy)]
 pub enum Inclusion {
@@ -7,8 +8,21 @@ pub enum Inclusion {
 	Exclude,
 }
 
+fn exclude() -> Inclusion {
+	Inclusion::Exclude
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct FieldsToAnnounce {
+	#[serde(default = "exclude")]
+	pub id: Inclusion,
+	#[serde(default = "exclude")]
+	pub path: Inclusion,
+	#[serde(default = "exclude")]
+	pub parent: Inclusion,
+	/// Artwork is an image path and can't be spoken. Leave it excluded.
+	#[serde(default = "exclude")]
+	pub artwork: Inclusion,
 	pub track_number: Inclusion,
 	pub disc_number: Inclusion,
 	pub title: Inclusion,
@@ -21,11 +35,19 @@ pub struct FieldsToAnnounce {
 	pub composer: Inclusion,
 	pub genre: Inclusion,
 	pub label: Inclusion,
+	/// Chance, between 0 and 1, that an optional field is announced, keyed by field
+	/// name like "year". Other optional fields are announced half of the time.
+	#[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+	pub optional_probabilities: BTreeMap<String, f64>,
 }
 
 impl Default for FieldsToAnnounce {
 	fn default() -> Self {
 		FieldsToAnnounce {
+			id: Inclusion::Exclude,
+			path: Inclusion::Exclude,
+			parent: Inclusion::Exclude,
+			artwork: Inclusion::Exclude,
 			track_number: Inclusion::Exclude,
 			disc_number: Inclusion::Exclude,
 			title: Inclusion::Required,
@@ -38,6 +60,7 @@ impl Default for FieldsToAnnounce {
 			composer: Inclusion::Required,
 			genre: Inclusion::Optional,
 			label: Inclusion::Exclude,
+			optional_probabilities: BTreeMap::new(),
 		}
 	}
 }
@@ -48,6 +71,10 @@ pub struct UserField {
 	pub name: String,
 	pub whole: bool,
 	pub fragments: Vec<String>,
+	/// Relative weight of each fragment, in the same order as `fragments`.
+	/// Fragments are picked uniformly when weights are not given.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub weights: Option<Vec<u32>>,
 }
 
 // This is user input field. Keep it simple.
@@ -58,6 +85,35 @@ pub struct TensedUserField {
 	pub present: String,
 }
 
+/// Where a conjunction joins two announcements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConjunctionPosition {
+	/// Between the previous song and the next song.
+	PrevNext,
+	/// Between the next song and the song after that.
+	NextNextNext,
+}
+
+// This is user input field. Keep it simple.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ConjunctionPools {
+	pub prev_next: Option<Vec<String>>,
+	pub next_next_next: Option<Vec<String>>,
+}
+
+impl ConjunctionPools {
+	pub fn get(&self, position: ConjunctionPosition) -> Option<&Vec<String>> {
+		match position {
+			ConjunctionPosition::PrevNext => self.prev_next.as_ref(),
+			ConjunctionPosition::NextNextNext => self.next_next_next.as_ref(),
+		}
+	}
+
+	pub fn iter(&self) -> impl Iterator<Item = &String> {
+		self.prev_next.iter().chain(&self.next_next_next).flatten()
+	}
+}
+
 // This is user input field. Keep it simple.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UserAnnouncementOptions {
@@ -66,7 +122,14 @@ pub struct UserAnnouncementOptions {
 	#[serde(rename = "tense_pattern")]
 	pub tense_patterns: Option<Vec<TensedUserField>>,
 	pub conjunctions: Option<Vec<String>>,
+	/// Conjunctions for a specific position. `conjunctions` are used between the next song
+	/// and the song after that when no pool is given for that position.
+	pub conjunction_pools: Option<ConjunctionPools>,
 	pub tags_to_announce: Option<FieldsToAnnounce>,
+	/// Maximum number of expansion passes over nested fragments. Defaults to
+	/// `DEFAULT_DEPTH_LIMIT`. Each pass clones every pattern, so large values slow
+	/// down script verification considerably for big scripts.
+	pub depth_limit: Option<usize>,
 }
 
 impl UserAnnouncementOptions {
@@ -111,7 +174,9 @@ impl Default for UserAnnouncementOptions {
 			patterns: vec![],
 			tense_patterns: None,
 			conjunctions: None,
+			conjunction_pools: None,
 			tags_to_announce: Some(FieldsToAnnounce::default()),
+			depth_limit: None,
 		}
 	}
 }
@@ -1,10 +1,11 @@
 use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
 use regex::Regex;
 use serde::Deserialize;
 use std::convert::TryInto;
 use std::time::Duration;
 
-use crate::app::rj::{AdminSettings, UserSettings};
+use crate::app::rj::{self, AdminSettings, Person, UserAnnouncementOptions, UserSettings};
 use crate::db::{self, misc_settings, DB};
 
 #[derive(thiserror::Error, Debug)]
@@ -19,10 +20,16 @@ pub enum Error {
 	MiscSettingsNotFound,
 	#[error("Index album art pattern is not a valid regex")]
 	IndexAlbumArtPatternInvalid,
+	#[error("Index insert buffer size cannot be negative")]
+	IndexInsertBufferSizeInvalid,
 	#[error(transparent)]
 	Database(#[from] diesel::result::Error),
 	#[error("Error from settings")]
 	SettingsError,
+	#[error("RJ host not found: {0}")]
+	RjHostNotFound(String),
+	#[error("Invalid RJ script: {0}")]
+	RjScriptInvalid(String),
 }
 
 #[derive(Clone, Default)]
@@ -34,12 +41,15 @@ pub struct AuthSecret {
 pub struct Settings {
 	pub index_sleep_duration_seconds: i32,
 	pub index_album_art_pattern: String,
+	pub index_insert_buffer_size: Option<i32>,
 }
 
 #[derive(Debug, Default, Deserialize)]
 pub struct NewSettings {
 	pub reindex_every_n_seconds: Option<i32>,
 	pub album_art_pattern: Option<String>,
+	/// Zero clears the setting so that the built-in default is used again.
+	pub index_insert_buffer_size: Option<i32>,
 }
 
 #[derive(Clone)]
@@ -77,128 +87,743 @@ impl Manager {
 
 	pub fn get_index_album_art_pattern(&self) -> Result<Regex, Error> {
 		let settings = self.read()?;
-		let regex = Regex::new(&format!("(?i){}", &settings.index_album_art_pattern))
-			.map_err(|_| Error::IndexAlbumArtPatternInvalid)?;
-		Ok(regex)
+		compile_album_art_pattern(&settings.index_album_art_pattern)
+	}
+
+	/// Number of index entries written per transaction, None to use the built-in default.
+	pub fn get_index_insert_buffer_size(&self) -> Result<Option<usize>, Error> {
+		let settings = self.read()?;
+		Ok(settings.index_insert_buffer_size.map(|s| s as usize))
 	}
 
 	pub fn read(&self) -> Result<Settings, Error> {
-		use self::misc_settings::dsl::*;
 		let mut connection = self.db.connect()?;
+		read_settings(&mut connection)
+	}
 
-		let settings: Settings = misc_settings
-			.select((index_sleep_duration_seconds, index_album_art_pattern))
-			.get_result(&mut connection)
-			.map_err(|e| match e {
-				diesel::result::Error::NotFound => Error::MiscSettingsNotFound,
-				e => e.into(),
-			})?;
+	/// Applies `new_settings` and returns the settings they replaced.
+	pub fn amend(&self, new_settings: &NewSettings) -> Result<Settings, Error> {
+		if let Some(ref album_art_pattern) = new_settings.album_art_pattern {
+			compile_album_art_pattern(album_art_pattern)?;
+		}
+
+		if let Some(buffer_size) = new_settings.index_insert_buffer_size {
+			if buffer_size < 0 {
+				return Err(Error::IndexInsertBufferSizeInvalid);
+			}
+		}
+
+		let mut connection = self.db.connect()?;
+		connection.transaction::<_, Error, _>(|connection| {
+			let previous = read_settings(connection)?;
+
+			if let Some(sleep_duration) = new_settings.reindex_every_n_seconds {
+				diesel::update(misc_settings::table)
+					.set(misc_settings::index_sleep_duration_seconds.eq(sleep_duration))
+					.execute(connection)?;
+			}
+
+			if let Some(ref album_art_pattern) = new_settings.album_art_pattern {
+				diesel::update(misc_settings::table)
+					.set(misc_settings::index_album_art_pattern.eq(album_art_pattern))
+					.execute(connection)?;
+			}
+
+			if let Some(buffer_size) = new_settings.index_insert_buffer_size {
+				let buffer_size = Some(buffer_size).filter(|s| *s > 0);
+				diesel::update(misc_settings::table)
+					.set(misc_settings::index_insert_buffer_size.eq(buffer_size))
+					.execute(connection)?;
+			}
 
-		Ok(settings)
+			Ok(previous)
+		})
 	}
 
-	pub fn amend(&self, new_settings: &NewSettings) -> Result<(), Error> {
+	pub fn get_rj_user_settings(&self) -> Result<UserSettings, Error> {
 		let mut connection = self.db.connect()?;
+		read_rj_user_settings(&mut connection)
+	}
 
-		if let Some(sleep_duration) = new_settings.reindex_every_n_seconds {
-			diesel::update(misc_settings::table)
-				.set(misc_settings::index_sleep_duration_seconds.eq(sleep_duration))
-				.execute(&mut connection)?;
-		}
+	pub fn get_rj_hosts(&self) -> Result<Vec<Person>, Error> {
+		Ok(self.get_rj_user_settings()?.tts_people)
+	}
 
-		if let Some(ref album_art_pattern) = new_settings.album_art_pattern {
-			diesel::update(misc_settings::table)
-				.set(misc_settings::index_album_art_pattern.eq(album_art_pattern))
-				.execute(&mut connection)?;
-		}
+	pub fn get_rj_host(&self, name: &str) -> Result<Person, Error> {
+		self.get_rj_hosts()?
+			.into_iter()
+			.find(|p| p.name == name)
+			.ok_or_else(|| Error::RjHostNotFound(name.to_owned()))
+	}
+
+	/// Returns the person marked as default host, or the first person if none is.
+	pub fn get_rj_default_host(&self) -> Result<Option<Person>, Error> {
+		Ok(self.get_rj_user_settings()?.default_person().cloned())
+	}
 
+	/// Returns whether `username` wants announcements, None when they follow the default.
+	pub fn get_rj_user_enabled(&self, username: &str) -> Result<Option<bool>, Error> {
+		use crate::db::users::dsl::*;
+		let mut connection = self.db.connect()?;
+		let enabled: Option<i32> = users
+			.select(rj_enabled)
+			.filter(name.eq(username))
+			.get_result(&mut connection)?;
+		Ok(enabled.map(|e| e != 0))
+	}
+
+	/// Overrides `enable_by_default` for `username`. None restores the default.
+	pub fn put_rj_user_enabled(&self, username: &str, enabled: Option<bool>) -> Result<(), Error> {
+		use crate::db::users::dsl::*;
+		let mut connection = self.db.connect()?;
+		diesel::update(users.filter(name.eq(username)))
+			.set(rj_enabled.eq(enabled.map(|e| e as i32)))
+			.execute(&mut connection)?;
 		Ok(())
 	}
 
-	pub fn get_rj_user_settings(&self) -> Result<UserSettings, Error> {
-		use crate::db::rj_user_settings::dsl::*;
+	pub fn set_rj_default_host(&self, name: &str) -> Result<(), Error> {
+		use crate::db::rj_user_settings;
+		self.get_rj_host(name)?;
 		let mut connection = self.db.connect()?;
-		let (user_scripts, enable, person_names): (Option<String>, Option<i32>, String) =
-			rj_user_settings
-				.select((scripts, enable_by_default, tts_people))
-				.get_result(&mut connection)
-				.map_err(|e| match e {
-					diesel::result::Error::NotFound => Error::SettingsError,
-					_ => Error::SettingsError,
-				})?;
-
-		Ok(UserSettings {
-			scripts: user_scripts,
-			enable_by_default: enable.map(|f| f != 0),
-			tts_people: serde_json::from_str(&person_names).unwrap(),
-		})
+		diesel::update(rj_user_settings::table)
+			.set(rj_user_settings::default_host.eq(name))
+			.execute(&mut connection)
+			.map_err(|_| Error::SettingsError)?;
+		Ok(())
 	}
 
 	pub fn get_rj_admin_settings(&self) -> Result<AdminSettings, Error> {
-		use crate::db::rj_admin_settings::dsl::*;
 		let mut connection = self.db.connect()?;
-		let (url, key, enable_ssml): (Option<String>, Option<String>, i32) = rj_admin_settings
-			.select((tts_service_url, tts_text_param_key, tts_enable_ssml))
-			.get_result::<(Option<String>, Option<String>, i32)>(&mut connection)
-			.map_err(|e| match e {
-				diesel::result::Error::NotFound => Error::SettingsError,
-				_ => Error::SettingsError,
-			})?;
-		Ok(AdminSettings {
-			tts_url: url,
-			tts_key: key,
-			enable_ssml: enable_ssml != 0,
-		})
+		read_rj_admin_settings(&mut connection)
 	}
 
 	pub fn put_rj_user_settings(&self, new_settings: &UserSettings) -> Result<(), Error> {
 		use crate::db::rj_user_settings;
-		let mut connection = self.db.connect()?;
-
 		if let Some(user_script) = &new_settings.scripts {
-			diesel::update(rj_user_settings::table)
-				.set(rj_user_settings::scripts.eq(user_script))
-				.execute(&mut connection)
-				.map_err(|_| Error::SettingsError)?;
+			rj::verify_script(user_script).map_err(|e| Error::RjScriptInvalid(e.to_string()))?;
 		}
+		let mut connection = self.db.connect()?;
+		let person_names = serde_json::to_string(&new_settings.tts_people).unwrap();
+		let rules = serde_json::to_string(&new_settings.voice_rules).unwrap();
 
-		if let Some(enable) = new_settings.enable_by_default {
-			diesel::update(rj_user_settings::table)
-				.set(rj_user_settings::enable_by_default.eq(enable as i32))
-				.execute(&mut connection)
-				.map_err(|_| Error::SettingsError)?;
-		}
+		connection
+			.transaction::<_, diesel::result::Error, _>(|connection| {
+				if let Some(user_script) = &new_settings.scripts {
+					diesel::update(rj_user_settings::table)
+						.set(rj_user_settings::scripts.eq(user_script))
+						.execute(connection)?;
+				}
 
-		let person_names = serde_json::to_string(&new_settings.tts_people).unwrap();
-		diesel::update(rj_user_settings::table)
-			.set(rj_user_settings::tts_people.eq(person_names))
-			.execute(&mut connection)
-			.map_err(|_| Error::SettingsError)?;
-		Ok(())
+				if let Some(enable) = new_settings.enable_by_default {
+					diesel::update(rj_user_settings::table)
+						.set(rj_user_settings::enable_by_default.eq(enable as i32))
+						.execute(connection)?;
+				}
+
+				diesel::update(rj_user_settings::table)
+					.set(rj_user_settings::tts_people.eq(person_names))
+					.execute(connection)?;
+
+				diesel::update(rj_user_settings::table)
+					.set((
+						rj_user_settings::voice_rules.eq(rules),
+						rj_user_settings::default_host.eq(&new_settings.default_host),
+					))
+					.execute(connection)?;
+				Ok(())
+			})
+			.map_err(|_| Error::SettingsError)
 	}
 
 	pub fn put_rj_admin_settings(&self, new_settings: &AdminSettings) -> Result<(), Error> {
 		use crate::db::rj_admin_settings;
 		let mut connection = self.db.connect()?;
+		let headers = serde_json::to_string(&new_settings.tts_headers).unwrap();
+		let voice_models = serde_json::to_string(&new_settings.allowed_voice_models).unwrap();
+		let languages = serde_json::to_string(&new_settings.allowed_languages).unwrap();
+		let pronunciations = serde_json::to_string(&new_settings.pronunciations).unwrap();
+		let genre_languages = serde_json::to_string(&new_settings.genre_languages).unwrap();
+		let backend = serde_json::to_string(&new_settings.tts_backend).unwrap();
 
-		if let Some(url) = &new_settings.tts_url {
-			diesel::update(rj_admin_settings::table)
-				.set(rj_admin_settings::tts_service_url.eq(url))
-				.execute(&mut connection)
-				.map_err(|_| Error::SettingsError)?;
-		}
+		connection
+			.transaction::<_, diesel::result::Error, _>(|connection| {
+				if let Some(url) = &new_settings.tts_url {
+					diesel::update(rj_admin_settings::table)
+						.set(rj_admin_settings::tts_service_url.eq(url))
+						.execute(connection)?;
+				}
+
+				if let Some(key) = &new_settings.tts_key {
+					diesel::update(rj_admin_settings::table)
+						.set(rj_admin_settings::tts_text_param_key.eq(key))
+						.execute(connection)?;
+				}
+
+				diesel::update(rj_admin_settings::table)
+					.set(rj_admin_settings::tts_enable_ssml.eq(new_settings.enable_ssml as i32))
+					.execute(connection)?;
+
+				diesel::update(rj_admin_settings::table)
+					.set(rj_admin_settings::tts_headers.eq(headers))
+					.execute(connection)?;
+
+				diesel::update(rj_admin_settings::table)
+					.set(rj_admin_settings::tts_fallback_path.eq(&new_settings.tts_fallback_path))
+					.execute(connection)?;
+
+				diesel::update(rj_admin_settings::table)
+					.set((
+						rj_admin_settings::allowed_voice_models.eq(voice_models),
+						rj_admin_settings::allowed_languages.eq(languages),
+						rj_admin_settings::pronunciations.eq(pronunciations),
+						rj_admin_settings::natural_pause.eq(&new_settings.natural_pause),
+						rj_admin_settings::tts_content_type.eq(&new_settings.tts_content_type),
+						rj_admin_settings::tts_backend.eq(backend),
+						rj_admin_settings::genre_languages.eq(genre_languages),
+					))
+					.execute(connection)?;
+				Ok(())
+			})
+			.map_err(|_| Error::SettingsError)
+	}
 
-		if let Some(key) = &new_settings.tts_key {
+	/// Restores the built-in script and forgets every person, voice rule and
+	/// default host. Returns the settings that were replaced so the reset can be undone.
+	pub fn reset_rj_user_settings(&self) -> Result<UserSettings, Error> {
+		use crate::db::rj_user_settings;
+		let mut connection = self.db.connect()?;
+		connection.transaction::<_, Error, _>(|connection| {
+			let previous = read_rj_user_settings(connection)?;
+			diesel::update(rj_user_settings::table)
+				.set((
+					rj_user_settings::scripts.eq(UserAnnouncementOptions::en_default_script_toml()),
+					rj_user_settings::tts_people.eq("[]"),
+					rj_user_settings::voice_rules.eq("[]"),
+					rj_user_settings::default_host.eq(None::<String>),
+				))
+				.execute(connection)?;
+			Ok(previous)
+		})
+	}
+
+	/// Forgets the TTS service and disables SSML. Returns the settings that were
+	/// replaced so the reset can be undone.
+	pub fn reset_rj_admin_settings(&self) -> Result<AdminSettings, Error> {
+		use crate::db::rj_admin_settings;
+		let mut connection = self.db.connect()?;
+		connection.transaction::<_, Error, _>(|connection| {
+			let previous = read_rj_admin_settings(connection)?;
 			diesel::update(rj_admin_settings::table)
-				.set(rj_admin_settings::tts_text_param_key.eq(key))
-				.execute(&mut connection)
-				.map_err(|_| Error::SettingsError)?;
-		}
+				.set((
+					rj_admin_settings::tts_service_url.eq(None::<String>),
+					rj_admin_settings::tts_text_param_key.eq(None::<String>),
+					rj_admin_settings::tts_enable_ssml.eq(0),
+					rj_admin_settings::tts_backend.eq(r#""http""#),
+				))
+				.execute(connection)?;
+			Ok(previous)
+		})
+	}
+}
 
-		diesel::update(rj_admin_settings::table)
-			.set(rj_admin_settings::tts_enable_ssml.eq(new_settings.enable_ssml as i32))
-			.execute(&mut connection)
-			.map_err(|_| Error::SettingsError)?;
-		Ok(())
+fn read_settings(connection: &mut SqliteConnection) -> Result<Settings, Error> {
+	use self::misc_settings::dsl::*;
+	misc_settings
+		.select((
+			index_sleep_duration_seconds,
+			index_album_art_pattern,
+			index_insert_buffer_size,
+		))
+		.get_result(connection)
+		.map_err(|e| match e {
+			diesel::result::Error::NotFound => Error::MiscSettingsNotFound,
+			e => e.into(),
+		})
+}
+
+fn read_rj_user_settings(connection: &mut SqliteConnection) -> Result<UserSettings, Error> {
+	use crate::db::rj_user_settings::dsl::*;
+	let (user_scripts, enable, person_names, rules, default_person): (
+		Option<String>,
+		Option<i32>,
+		String,
+		String,
+		Option<String>,
+	) = rj_user_settings
+		.select((
+			scripts,
+			enable_by_default,
+			tts_people,
+			voice_rules,
+			default_host,
+		))
+		.get_result(connection)
+		.map_err(|e| match e {
+			diesel::result::Error::NotFound => Error::SettingsError,
+			_ => Error::SettingsError,
+		})?;
+
+	Ok(UserSettings {
+		scripts: user_scripts,
+		enable_by_default: enable.map(|f| f != 0),
+		tts_people: serde_json::from_str(&person_names).unwrap(),
+		voice_rules: serde_json::from_str(&rules).unwrap(),
+		default_host: default_person,
+	})
+}
+
+fn read_rj_admin_settings(connection: &mut SqliteConnection) -> Result<AdminSettings, Error> {
+	use crate::db::rj_admin_settings::dsl::*;
+	let (
+		url,
+		key,
+		enable_ssml,
+		headers,
+		fallback_path,
+		voice_models,
+		languages,
+		overrides,
+		pause,
+		content_type,
+		backend,
+		genres,
+	): (
+		Option<String>,
+		Option<String>,
+		i32,
+		String,
+		Option<String>,
+		String,
+		String,
+		String,
+		Option<String>,
+		Option<String>,
+		String,
+		String,
+	) = rj_admin_settings
+		.select((
+			tts_service_url,
+			tts_text_param_key,
+			tts_enable_ssml,
+			tts_headers,
+			tts_fallback_path,
+			allowed_voice_models,
+			allowed_languages,
+			pronunciations,
+			natural_pause,
+			tts_content_type,
+			tts_backend,
+			genre_languages,
+		))
+		.get_result(connection)
+		.map_err(|e| match e {
+			diesel::result::Error::NotFound => Error::SettingsError,
+			_ => Error::SettingsError,
+		})?;
+	Ok(AdminSettings {
+		tts_url: url,
+		tts_key: key,
+		enable_ssml: enable_ssml != 0,
+		tts_headers: serde_json::from_str(&headers).map_err(|_| Error::SettingsError)?,
+		tts_fallback_path: fallback_path,
+		allowed_voice_models: serde_json::from_str(&voice_models)
+			.map_err(|_| Error::SettingsError)?,
+		allowed_languages: serde_json::from_str(&languages).map_err(|_| Error::SettingsError)?,
+		pronunciations: serde_json::from_str(&overrides).map_err(|_| Error::SettingsError)?,
+		natural_pause: pause,
+		tts_content_type: content_type,
+		tts_backend: serde_json::from_str(&backend).map_err(|_| Error::SettingsError)?,
+		genre_languages: serde_json::from_str(&genres).map_err(|_| Error::SettingsError)?,
+	})
+}
+
+fn compile_album_art_pattern(pattern: &str) -> Result<Regex, Error> {
+	Regex::new(&format!("(?i){}", pattern)).map_err(|_| Error::IndexAlbumArtPatternInvalid)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::app::test;
+	use crate::test_name;
+
+	#[test]
+	fn invalid_album_art_pattern_is_rejected() {
+		let ctx = test::ContextBuilder::new(test_name!()).build();
+		let original = ctx.settings_manager.read().unwrap();
+
+		let new_settings = NewSettings {
+			reindex_every_n_seconds: Some(original.index_sleep_duration_seconds + 1),
+			album_art_pattern: Some("Folder.(jpeg|png".to_owned()),
+			..Default::default()
+		};
+		assert!(matches!(
+			ctx.settings_manager.amend(&new_settings),
+			Err(Error::IndexAlbumArtPatternInvalid)
+		));
+
+		let settings = ctx.settings_manager.read().unwrap();
+		assert_eq!(
+			settings.index_sleep_duration_seconds,
+			original.index_sleep_duration_seconds
+		);
+		assert_eq!(
+			settings.index_album_art_pattern,
+			original.index_album_art_pattern
+		);
+	}
+
+	#[test]
+	fn rj_hosts_by_name() {
+		let ctx = test::ContextBuilder::new(test_name!()).build();
+		let person = |name: &str| Person {
+			name: name.to_owned(),
+			voice_model: "voice".to_owned(),
+			language: "en-US".to_owned(),
+			..Default::default()
+		};
+		let settings = UserSettings {
+			tts_people: vec![person("morning"), person("night")],
+			..ctx.settings_manager.get_rj_user_settings().unwrap()
+		};
+		ctx.settings_manager
+			.put_rj_user_settings(&settings)
+			.unwrap();
+
+		assert_eq!(ctx.settings_manager.get_rj_hosts().unwrap().len(), 2);
+		assert_eq!(
+			ctx.settings_manager.get_rj_host("night").unwrap(),
+			person("night")
+		);
+		assert!(matches!(
+			ctx.settings_manager.get_rj_host("evening"),
+			Err(Error::RjHostNotFound(_))
+		));
+
+		assert_eq!(
+			ctx.settings_manager.get_rj_default_host().unwrap(),
+			Some(person("morning"))
+		);
+		ctx.settings_manager.set_rj_default_host("night").unwrap();
+		assert_eq!(
+			ctx.settings_manager.get_rj_default_host().unwrap(),
+			Some(person("night"))
+		);
+		assert!(ctx.settings_manager.set_rj_default_host("evening").is_err());
+	}
+
+	#[test]
+	fn rj_genre_languages_are_stored() {
+		let ctx = test::ContextBuilder::new(test_name!()).build();
+		let mut settings = ctx.settings_manager.get_rj_admin_settings().unwrap();
+		settings
+			.genre_languages
+			.insert("Bollywood".to_owned(), "hi-IN".to_owned());
+		ctx.settings_manager
+			.put_rj_admin_settings(&settings)
+			.unwrap();
+		assert_eq!(
+			ctx.settings_manager
+				.get_rj_admin_settings()
+				.unwrap()
+				.genre_languages,
+			settings.genre_languages
+		);
+	}
+
+	#[test]
+	fn rj_pronunciations_are_stored() {
+		let ctx = test::ContextBuilder::new(test_name!()).build();
+		let mut settings = ctx.settings_manager.get_rj_admin_settings().unwrap();
+		settings.pronunciations.insert(
+			"deadmau5".to_owned(),
+			r#"<sub alias="dead mouse">deadmau5</sub>"#.to_owned(),
+		);
+		ctx.settings_manager
+			.put_rj_admin_settings(&settings)
+			.unwrap();
+		assert_eq!(
+			ctx.settings_manager
+				.get_rj_admin_settings()
+				.unwrap()
+				.pronunciations,
+			settings.pronunciations
+		);
+	}
+
+	#[test]
+	fn rj_user_enabled_is_stored_per_user() {
+		let ctx = test::ContextBuilder::new(test_name!())
+			.user("listener", "password", false)
+			.user("other", "password", false)
+			.build();
+		let settings = &ctx.settings_manager;
+		assert_eq!(settings.get_rj_user_enabled("listener").unwrap(), None);
+
+		settings
+			.put_rj_user_enabled("listener", Some(false))
+			.unwrap();
+		assert_eq!(
+			settings.get_rj_user_enabled("listener").unwrap(),
+			Some(false)
+		);
+		assert_eq!(settings.get_rj_user_enabled("other").unwrap(), None);
+
+		settings.put_rj_user_enabled("listener", None).unwrap();
+		assert_eq!(settings.get_rj_user_enabled("listener").unwrap(), None);
+	}
+
+	#[test]
+	fn rj_natural_pause_is_stored() {
+		let ctx = test::ContextBuilder::new(test_name!()).build();
+		let mut settings = ctx.settings_manager.get_rj_admin_settings().unwrap();
+		assert_eq!(settings.natural_pause, None);
+		settings.natural_pause = Some("750ms".to_owned());
+		ctx.settings_manager
+			.put_rj_admin_settings(&settings)
+			.unwrap();
+		assert_eq!(
+			ctx.settings_manager
+				.get_rj_admin_settings()
+				.unwrap()
+				.natural_pause,
+			settings.natural_pause
+		);
+	}
+
+	#[test]
+	fn rj_tts_content_type_is_stored() {
+		let ctx = test::ContextBuilder::new(test_name!()).build();
+		let mut settings = ctx.settings_manager.get_rj_admin_settings().unwrap();
+		assert_eq!(settings.tts_content_type, None);
+		settings.tts_content_type = Some("audio/mpeg".to_owned());
+		ctx.settings_manager
+			.put_rj_admin_settings(&settings)
+			.unwrap();
+		assert_eq!(
+			ctx.settings_manager
+				.get_rj_admin_settings()
+				.unwrap()
+				.tts_content_type,
+			settings.tts_content_type
+		);
+	}
+
+	#[test]
+	fn rj_tts_backend_is_stored() {
+		let ctx = test::ContextBuilder::new(test_name!()).build();
+		let mut settings = ctx.settings_manager.get_rj_admin_settings().unwrap();
+		assert_eq!(settings.tts_backend, rj::TtsBackend::Http);
+		settings.tts_backend = rj::TtsBackend::Command;
+		ctx.settings_manager
+			.put_rj_admin_settings(&settings)
+			.unwrap();
+		assert_eq!(
+			ctx.settings_manager.get_rj_admin_settings().unwrap(),
+			settings
+		);
+	}
+
+	#[test]
+	fn rj_user_settings_update_is_atomic() {
+		let ctx = test::ContextBuilder::new(test_name!()).build();
+		let original = ctx.settings_manager.get_rj_user_settings().unwrap();
+
+		// Make the update fail after the scripts column has been written.
+		let mut connection = ctx.db.connect().unwrap();
+		diesel::sql_query(
+			"CREATE TRIGGER fail_tts_people BEFORE UPDATE OF tts_people ON rj_user_settings \
+			BEGIN SELECT RAISE(ABORT, 'simulated failure'); END;",
+		)
+		.execute(&mut connection)
+		.unwrap();
+
+		let new_settings = UserSettings {
+			scripts: Some(UserAnnouncementOptions::en_default_script_toml()),
+			..original.clone()
+		};
+		assert!(ctx
+			.settings_manager
+			.put_rj_user_settings(&new_settings)
+			.is_err());
+		let settings = ctx.settings_manager.get_rj_user_settings().unwrap();
+		assert_eq!(settings.scripts, original.scripts);
+	}
+
+	#[test]
+	fn rj_admin_settings_update_is_atomic() {
+		let ctx = test::ContextBuilder::new(test_name!()).build();
+		let original = ctx.settings_manager.get_rj_admin_settings().unwrap();
+
+		// Make the update fail after the url column has been written.
+		let mut connection = ctx.db.connect().unwrap();
+		diesel::sql_query(
+			"CREATE TRIGGER fail_tts_headers BEFORE UPDATE OF tts_headers ON rj_admin_settings \
+			BEGIN SELECT RAISE(ABORT, 'simulated failure'); END;",
+		)
+		.execute(&mut connection)
+		.unwrap();
+
+		let new_settings = AdminSettings {
+			tts_url: Some("http://tts.lan/api/tts".to_owned()),
+			..original.clone()
+		};
+		assert!(ctx
+			.settings_manager
+			.put_rj_admin_settings(&new_settings)
+			.is_err());
+		let settings = ctx.settings_manager.get_rj_admin_settings().unwrap();
+		assert_eq!(settings.tts_url, original.tts_url);
+	}
+
+	#[test]
+	fn rj_user_settings_can_be_reset() {
+		let ctx = test::ContextBuilder::new(test_name!()).build();
+		let person = Person {
+			name: "morning".to_owned(),
+			voice_model: "voice".to_owned(),
+			language: "en-US".to_owned(),
+			..Default::default()
+		};
+		let custom = UserSettings {
+			scripts: Some(
+				UserAnnouncementOptions::en_default_script_toml()
+					.replace("Next song is", "Up next is"),
+			),
+			enable_by_default: Some(true),
+			tts_people: vec![person],
+			default_host: Some("morning".to_owned()),
+			..Default::default()
+		};
+		ctx.settings_manager.put_rj_user_settings(&custom).unwrap();
+
+		let previous = ctx.settings_manager.reset_rj_user_settings().unwrap();
+		assert_eq!(previous, custom);
+
+		let settings = ctx.settings_manager.get_rj_user_settings().unwrap();
+		assert_eq!(
+			settings.scripts,
+			Some(UserAnnouncementOptions::en_default_script_toml())
+		);
+		assert_eq!(settings.enable_by_default, Some(true));
+		assert!(settings.tts_people.is_empty());
+		assert!(settings.voice_rules.is_empty());
+		assert_eq!(settings.default_host, None);
+
+		ctx.settings_manager
+			.put_rj_user_settings(&previous)
+			.unwrap();
+		assert_eq!(ctx.settings_manager.get_rj_user_settings().unwrap(), custom);
+	}
+
+	#[test]
+	fn rj_admin_settings_can_be_reset() {
+		let ctx = test::ContextBuilder::new(test_name!()).build();
+		let custom = AdminSettings {
+			tts_url: Some("http://tts.lan/api/tts".to_owned()),
+			tts_key: Some("text".to_owned()),
+			enable_ssml: true,
+			..ctx.settings_manager.get_rj_admin_settings().unwrap()
+		};
+		ctx.settings_manager.put_rj_admin_settings(&custom).unwrap();
+
+		let previous = ctx.settings_manager.reset_rj_admin_settings().unwrap();
+		assert_eq!(previous.tts_url, custom.tts_url);
+		assert_eq!(previous.tts_key, custom.tts_key);
+		assert!(previous.enable_ssml);
+
+		let settings = ctx.settings_manager.get_rj_admin_settings().unwrap();
+		assert_eq!(settings.tts_url, None);
+		assert_eq!(settings.tts_key, None);
+		assert!(!settings.enable_ssml);
+		assert_eq!(settings.tts_headers, custom.tts_headers);
+	}
+
+	#[test]
+	fn rj_user_settings_reject_invalid_scripts() {
+		let ctx = test::ContextBuilder::new(test_name!()).build();
+		let original = ctx.settings_manager.get_rj_user_settings().unwrap();
+
+		let new_settings = UserSettings {
+			scripts: Some("[options".to_owned()),
+			..original.clone()
+		};
+		assert!(matches!(
+			ctx.settings_manager.put_rj_user_settings(&new_settings),
+			Err(Error::RjScriptInvalid(_))
+		));
+		let settings = ctx.settings_manager.get_rj_user_settings().unwrap();
+		assert_eq!(settings.scripts, original.scripts);
+	}
+
+	#[test]
+	fn index_insert_buffer_size_is_stored() {
+		let ctx = test::ContextBuilder::new(test_name!()).build();
+		assert_eq!(
+			ctx.settings_manager.get_index_insert_buffer_size().unwrap(),
+			None
+		);
+
+		let new_settings = NewSettings {
+			index_insert_buffer_size: Some(-1),
+			..Default::default()
+		};
+		assert!(matches!(
+			ctx.settings_manager.amend(&new_settings),
+			Err(Error::IndexInsertBufferSizeInvalid)
+		));
+
+		let new_settings = NewSettings {
+			index_insert_buffer_size: Some(250),
+			..Default::default()
+		};
+		ctx.settings_manager.amend(&new_settings).unwrap();
+		assert_eq!(
+			ctx.settings_manager.get_index_insert_buffer_size().unwrap(),
+			Some(250)
+		);
+
+		ctx.settings_manager.amend(&NewSettings::default()).unwrap();
+		assert_eq!(
+			ctx.settings_manager.get_index_insert_buffer_size().unwrap(),
+			Some(250)
+		);
+
+		let new_settings = NewSettings {
+			index_insert_buffer_size: Some(0),
+			..Default::default()
+		};
+		ctx.settings_manager.amend(&new_settings).unwrap();
+		assert_eq!(
+			ctx.settings_manager.get_index_insert_buffer_size().unwrap(),
+			None
+		);
+	}
+
+	#[test]
+	fn amend_returns_previous_settings() {
+		let ctx = test::ContextBuilder::new(test_name!()).build();
+		let original = ctx.settings_manager.read().unwrap();
+
+		let new_settings = NewSettings {
+			reindex_every_n_seconds: Some(original.index_sleep_duration_seconds + 1),
+			..Default::default()
+		};
+		let previous = ctx.settings_manager.amend(&new_settings).unwrap();
+		assert_eq!(
+			previous.index_sleep_duration_seconds,
+			original.index_sleep_duration_seconds
+		);
+		assert_eq!(
+			previous.index_album_art_pattern,
+			original.index_album_art_pattern
+		);
+
+		let previous = ctx.settings_manager.amend(&new_settings).unwrap();
+		assert_eq!(
+			previous.index_sleep_duration_seconds,
+			original.index_sleep_duration_seconds + 1
+		);
 	}
 }
@@ -1,14 +1,27 @@
-use image::{DynamicImage, GenericImage, GenericImageView, ImageBuffer, ImageOutputFormat};
+use image::{
+	DynamicImage, GenericImage, GenericImageView, ImageBuffer, ImageFormat, ImageOutputFormat,
+};
+use lewton::inside_ogg::OggStreamReader;
+use log::{error, info};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use std::cmp;
 use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
 use std::fs::{self, File};
 use std::hash::{Hash, Hasher};
+use std::io::Cursor;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::SystemTime;
 
-use crate::utils::{get_audio_format, AudioFormat};
+use crate::utils::{self, get_audio_format, AudioFormat};
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
+	#[error("Could not read thumbnail from APE tag in `{0}`:\n\n{1}")]
+	Ape(PathBuf, ape::Error),
 	#[error("No embedded artwork was found in `{0}`")]
 	EmbeddedArtworkNotFound(PathBuf),
 	#[error("Could not read thumbnail from ID3 tag in `{0}`:\n\n{1}")]
@@ -21,15 +34,91 @@ pub enum Error {
 	Metaflac(PathBuf, metaflac::Error),
 	#[error("Could not read thumbnail from mp4 file in `{0}`:\n\n{1}")]
 	Mp4aMeta(PathBuf, mp4ameta::Error),
+	#[error("Could not read thumbnail from opus file in `{0}`:\n\n{1}")]
+	Opus(PathBuf, opus_headers::ParseError),
+	#[error("Could not read picture block from Vorbis comment in `{0}`")]
+	PictureBlock(PathBuf),
+	#[error("Could not read thumbnail from vorbis file in `{0}`:\n\n{1}")]
+	Vorbis(PathBuf, lewton::VorbisError),
 	#[error("This file format is not supported: {0}")]
 	UnsupportedFormat(&'static str),
 }
 
+/// Image format thumbnails are written in.
+#[derive(Clone, Copy, Debug, Deserialize, Hash, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+	Jpeg,
+	Png,
+	#[serde(rename = "webp")]
+	WebP,
+}
+
+impl Default for Format {
+	fn default() -> Self {
+		Self::Jpeg
+	}
+}
+
+impl Format {
+	fn extension(self) -> &'static str {
+		match self {
+			Self::Jpeg => "jpg",
+			Self::Png => "png",
+			Self::WebP => "webp",
+		}
+	}
+
+	fn supports_transparency(self) -> bool {
+		self != Self::Jpeg
+	}
+}
+
+/// What identifies a source image in the thumbnail cache.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheKey {
+	/// Fast, but moving or renaming a file regenerates its thumbnails.
+	Path,
+	/// Survives moves and renames, but reads the whole source file on every request.
+	Content,
+}
+
+impl Default for CacheKey {
+	fn default() -> Self {
+		Self::Path
+	}
+}
+
 #[derive(Debug, Hash)]
 pub struct Options {
 	pub max_dimension: Option<u32>,
 	pub resize_if_almost_square: bool,
 	pub pad_to_square: bool,
+	pub format: Format,
+	/// JPEG quality, clamped to 1-100 and ignored by other formats. Higher values give sharper
+	/// thumbnails but larger files.
+	pub quality: u8,
+	/// RGBA color filling the padding. A transparent color makes JPEG thumbnails PNG instead.
+	pub background: [u8; 4],
+}
+
+impl Options {
+	fn output_format(&self) -> Format {
+		if self.format == Format::Jpeg && self.background[3] < 255 {
+			Format::Png
+		} else {
+			self.format
+		}
+	}
+
+	// Options that give the same thumbnail are made equal so that they share a cache entry.
+	fn normalized(&self) -> Options {
+		let quality = match self.output_format() {
+			Format::Jpeg => self.quality.clamp(1, 100),
+			Format::Png | Format::WebP => 0,
+		};
+		Options { quality, ..*self }
+	}
 }
 
 impl Default for Options {
@@ -38,19 +127,209 @@ impl Default for Options {
 			max_dimension: Some(400),
 			resize_if_almost_square: true,
 			pad_to_square: true,
+			format: Format::default(),
+			quality: 80,
+			background: [255, 255, 255, 255],
 		}
 	}
 }
 
+#[derive(Default)]
+struct Generations {
+	active: usize,
+	in_flight: HashSet<PathBuf>,
+}
+
+// Bounds how many thumbnails are generated at once and makes sure a given
+// thumbnail is only generated by one request at a time.
+struct GenerationLimiter {
+	max_active: usize,
+	generations: Mutex<Generations>,
+	condvar: Condvar,
+}
+
+// Releases a generation slot when dropped, even if generation fails or panics.
+struct GenerationSlot<'a> {
+	limiter: &'a GenerationLimiter,
+	thumbnail_path: PathBuf,
+}
+
+impl GenerationLimiter {
+	fn new(max_active: usize) -> Self {
+		Self {
+			max_active: cmp::max(max_active, 1),
+			generations: Mutex::new(Generations::default()),
+			condvar: Condvar::new(),
+		}
+	}
+
+	// Blocks until a slot is available. Returns None if the thumbnail was created by
+	// another request while waiting.
+	fn acquire(&self, thumbnail_path: &Path) -> Option<GenerationSlot> {
+		let mut generations = self.generations.lock().unwrap();
+		loop {
+			if !generations.in_flight.contains(thumbnail_path) {
+				if thumbnail_path.exists() {
+					return None;
+				}
+				if generations.active < self.max_active {
+					break;
+				}
+			}
+			generations = self.condvar.wait(generations).unwrap();
+		}
+		generations.active += 1;
+		generations.in_flight.insert(thumbnail_path.to_owned());
+		Some(GenerationSlot {
+			limiter: self,
+			thumbnail_path: thumbnail_path.to_owned(),
+		})
+	}
+}
+
+impl Drop for GenerationSlot<'_> {
+	fn drop(&mut self) {
+		let mut generations = self.limiter.generations.lock().unwrap();
+		generations.active -= 1;
+		generations.in_flight.remove(&self.thumbnail_path);
+		self.limiter.condvar.notify_all();
+	}
+}
+
 #[derive(Clone)]
 pub struct Manager {
 	thumbnails_dir_path: PathBuf,
+	limiter: Arc<GenerationLimiter>,
+	cache_key: CacheKey,
+	max_cache_size: Option<u64>,
+	// Size of the cache as of the last pruning, plus the thumbnails written since.
+	// Unknown until the cache is first pruned.
+	cache_size: Arc<Mutex<Option<u64>>>,
 }
 
 impl Manager {
 	pub fn new(thumbnails_dir_path: PathBuf) -> Self {
+		let key = "POLARIS_MAX_THUMBNAIL_GENERATIONS";
+		let max_generations = std::env::var_os(key)
+			.map(|v| v.to_string_lossy().to_string())
+			.and_then(|v| usize::from_str(&v).ok())
+			.unwrap_or_else(|| cmp::min(num_cpus::get(), 4));
+		info!(
+			"Generating at most {} thumbnails concurrently",
+			max_generations
+		);
+		let cache_key = match std::env::var_os("POLARIS_THUMBNAIL_CACHE_KEY") {
+			Some(v) if v.eq_ignore_ascii_case("content") => CacheKey::Content,
+			_ => CacheKey::Path,
+		};
+		let max_cache_size = std::env::var_os("POLARIS_THUMBNAIL_CACHE_MAX_SIZE")
+			.map(|v| v.to_string_lossy().to_string())
+			.and_then(|v| u64::from_str(&v).ok());
+		if let Some(size) = max_cache_size {
+			info!("Thumbnails cache is limited to {} bytes", size);
+		}
+		Self::with_max_generations(thumbnails_dir_path, max_generations)
+			.with_cache_key(cache_key)
+			.with_max_cache_size(max_cache_size)
+	}
+
+	pub fn with_max_generations(thumbnails_dir_path: PathBuf, max_generations: usize) -> Self {
 		Self {
 			thumbnails_dir_path,
+			limiter: Arc::new(GenerationLimiter::new(max_generations)),
+			cache_key: CacheKey::default(),
+			max_cache_size: None,
+			cache_size: Arc::new(Mutex::new(None)),
+		}
+	}
+
+	pub fn with_cache_key(mut self, cache_key: CacheKey) -> Self {
+		self.cache_key = cache_key;
+		self
+	}
+
+	pub fn with_max_cache_size(mut self, max_cache_size: Option<u64>) -> Self {
+		self.max_cache_size = max_cache_size;
+		self
+	}
+
+	// Deletes the least recently used thumbnails when the cache is over its maximum size,
+	// until it is a tenth under it so that the next thumbnails do not prune it again.
+	// Thumbnails being generated are left alone.
+	pub fn prune_cache(&self) -> Result<(), Error> {
+		let max_cache_size = match self.max_cache_size {
+			Some(size) => size,
+			None => return Ok(()),
+		};
+		let target_size = max_cache_size - max_cache_size / 10;
+		// Held throughout so that concurrent generations do not prune at the same time.
+		let mut tracked_size = self.cache_size.lock().unwrap();
+		let dir = &self.thumbnails_dir_path;
+		let entries = match fs::read_dir(dir) {
+			Ok(entries) => entries,
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+				*tracked_size = Some(0);
+				return Ok(());
+			}
+			Err(e) => return Err(Error::Io(dir.clone(), e)),
+		};
+
+		let mut cache_size = 0;
+		let mut thumbnails = Vec::new();
+		for entry in entries {
+			let entry = entry.map_err(|e| Error::Io(dir.clone(), e))?;
+			let metadata = match entry.metadata() {
+				Ok(m) if m.is_file() => m,
+				_ => continue,
+			};
+			// Cache hits update modification times, access times are often not kept.
+			let last_used = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+			cache_size += metadata.len();
+			thumbnails.push((last_used, metadata.len(), entry.path()));
+		}
+
+		if cache_size > max_cache_size {
+			thumbnails.sort_by_key(|(last_used, _, _)| *last_used);
+			for (_, size, path) in thumbnails {
+				if cache_size <= target_size {
+					break;
+				}
+				let generations = self.limiter.generations.lock().unwrap();
+				if generations.in_flight.contains(&path) {
+					continue;
+				}
+				match fs::remove_file(&path) {
+					Ok(()) => cache_size -= size,
+					Err(e) if e.kind() == std::io::ErrorKind::NotFound => cache_size -= size,
+					Err(e) => {
+						*tracked_size = Some(cache_size);
+						return Err(Error::Io(path, e));
+					}
+				}
+			}
+		}
+		*tracked_size = Some(cache_size);
+		Ok(())
+	}
+
+	// Adds a new thumbnail to the tracked cache size, pruning the cache when it is over
+	// its maximum size. The cache directory is only listed when pruning.
+	fn grow_cache(&self, size: u64) {
+		let max_cache_size = match self.max_cache_size {
+			Some(size) => size,
+			None => return,
+		};
+		let needs_pruning = match self.cache_size.lock().unwrap().as_mut() {
+			Some(cache_size) => {
+				*cache_size += size;
+				*cache_size > max_cache_size
+			}
+			None => true,
+		};
+		if needs_pruning {
+			if let Err(e) = self.prune_cache() {
+				error!("Could not prune thumbnails cache: {}", e);
+			}
 		}
 	}
 
@@ -59,23 +338,78 @@ impl Manager {
 		image_path: &Path,
 		thumbnailoptions: &Options,
 	) -> Result<PathBuf, Error> {
-		match self.retrieve_thumbnail(image_path, thumbnailoptions) {
+		// Content hashing reads the source file, so the path is only computed once
+		let path = self.get_thumbnail_path(image_path, thumbnailoptions)?;
+		self.find_or_create_thumbnail(image_path, path, thumbnailoptions)
+	}
+
+	/// Opens the thumbnail of an image, generating it if needed. A thumbnail pruned
+	/// between being found and being opened is generated again.
+	pub fn open_thumbnail(
+		&self,
+		image_path: &Path,
+		thumbnailoptions: &Options,
+	) -> Result<(PathBuf, File), Error> {
+		let path = self.get_thumbnail_path(image_path, thumbnailoptions)?;
+		let mut retried = false;
+		loop {
+			let path = self.find_or_create_thumbnail(image_path, path.clone(), thumbnailoptions)?;
+			match File::open(&path) {
+				Ok(file) => return Ok((path, file)),
+				Err(e) if e.kind() == std::io::ErrorKind::NotFound && !retried => retried = true,
+				Err(e) => return Err(Error::Io(path, e)),
+			}
+		}
+	}
+
+	fn find_or_create_thumbnail(
+		&self,
+		image_path: &Path,
+		path: PathBuf,
+		thumbnailoptions: &Options,
+	) -> Result<PathBuf, Error> {
+		match Self::retrieve_thumbnail(&path) {
 			Some(path) => Ok(path),
-			None => self.create_thumbnail(image_path, thumbnailoptions),
+			None => self.create_thumbnail(image_path, path, thumbnailoptions),
 		}
 	}
 
-	fn get_thumbnail_path(&self, image_path: &Path, thumbnailoptions: &Options) -> PathBuf {
-		let hash = Manager::hash(image_path, thumbnailoptions);
+	// Generates the thumbnails missing from the cache, several at a time.
+	// Results are in the same order as the image paths.
+	pub fn generate_thumbnails(
+		&self,
+		image_paths: &[PathBuf],
+		thumbnailoptions: &Options,
+	) -> Vec<Result<PathBuf, Error>> {
+		image_paths
+			.par_iter()
+			.map(|image_path| self.get_thumbnail(image_path, thumbnailoptions))
+			.collect()
+	}
+
+	fn get_thumbnail_path(
+		&self,
+		image_path: &Path,
+		thumbnailoptions: &Options,
+	) -> Result<PathBuf, Error> {
+		let hash = self.hash(image_path, thumbnailoptions)?;
 		let mut thumbnail_path = self.thumbnails_dir_path.clone();
-		thumbnail_path.push(format!("{}.jpg", hash));
-		thumbnail_path
+		thumbnail_path.push(format!(
+			"{}.{}",
+			hash,
+			thumbnailoptions.output_format().extension()
+		));
+		Ok(thumbnail_path)
 	}
 
-	fn retrieve_thumbnail(&self, image_path: &Path, thumbnailoptions: &Options) -> Option<PathBuf> {
-		let path = self.get_thumbnail_path(image_path, thumbnailoptions);
+	// Hits are marked as recently used, for pruning.
+	fn retrieve_thumbnail(path: &Path) -> Option<PathBuf> {
 		if path.exists() {
-			Some(path)
+			let _ = File::options()
+				.write(true)
+				.open(path)
+				.and_then(|file| file.set_modified(SystemTime::now()));
+			Some(path.to_owned())
 		} else {
 			None
 		}
@@ -84,32 +418,66 @@ impl Manager {
 	fn create_thumbnail(
 		&self,
 		image_path: &Path,
+		path: PathBuf,
 		thumbnailoptions: &Options,
 	) -> Result<PathBuf, Error> {
-		let thumbnail = generate_thumbnail(image_path, thumbnailoptions)?;
-		let quality = 80;
+		let _slot = match self.limiter.acquire(&path) {
+			Some(slot) => slot,
+			None => return Ok(path),
+		};
+
+		let (bytes, _) = generate_thumbnail_bytes(image_path, thumbnailoptions)?;
 
 		fs::create_dir_all(&self.thumbnails_dir_path)
 			.map_err(|e| Error::Io(self.thumbnails_dir_path.clone(), e))?;
-		let path = self.get_thumbnail_path(image_path, thumbnailoptions);
-		let mut out_file =
-			File::create(&path).map_err(|e| Error::Io(self.thumbnails_dir_path.clone(), e))?;
-		thumbnail
-			.write_to(&mut out_file, ImageOutputFormat::Jpeg(quality))
-			.map_err(|e| Error::Image(image_path.to_owned(), e))?;
+		fs::write(&path, &bytes).map_err(|e| Error::Io(self.thumbnails_dir_path.clone(), e))?;
+		self.grow_cache(bytes.len() as u64);
 		Ok(path)
 	}
 
-	fn hash(path: &Path, thumbnailoptions: &Options) -> u64 {
+	fn hash(&self, path: &Path, thumbnailoptions: &Options) -> Result<u64, Error> {
 		let mut hasher = DefaultHasher::new();
-		path.hash(&mut hasher);
-		thumbnailoptions.hash(&mut hasher);
-		hasher.finish()
+		match self.cache_key {
+			CacheKey::Path => path.hash(&mut hasher),
+			CacheKey::Content => fs::read(path)
+				.map_err(|e| Error::Io(path.to_owned(), e))?
+				.hash(&mut hasher),
+		}
+		thumbnailoptions.normalized().hash(&mut hasher);
+		Ok(hasher.finish())
 	}
 }
 
+// Encodes a thumbnail in memory, without going through the thumbnails cache.
+pub fn generate_thumbnail_bytes(
+	image_path: &Path,
+	options: &Options,
+) -> Result<(Vec<u8>, ImageFormat), Error> {
+	let thumbnail = generate_thumbnail(image_path, options)?;
+	let (output_format, image_format) = match options.output_format() {
+		Format::Jpeg => (
+			ImageOutputFormat::Jpeg(options.normalized().quality),
+			ImageFormat::Jpeg,
+		),
+		Format::Png => (ImageOutputFormat::Png, ImageFormat::Png),
+		Format::WebP => (ImageOutputFormat::WebP, ImageFormat::WebP),
+	};
+
+	let mut bytes = Vec::new();
+	thumbnail
+		.write_to(&mut Cursor::new(&mut bytes), output_format)
+		.map_err(|e| Error::Image(image_path.to_owned(), e))?;
+	Ok((bytes, image_format))
+}
+
 fn generate_thumbnail(image_path: &Path, options: &Options) -> Result<DynamicImage, Error> {
-	let source_image = DynamicImage::ImageRgb8(read(image_path)?.into_rgb8());
+	// Transparency is kept for the formats that can store it
+	let transparent = options.output_format().supports_transparency();
+	let source_image = if transparent {
+		DynamicImage::ImageRgba8(read(image_path)?.into_rgba8())
+	} else {
+		DynamicImage::ImageRgb8(read(image_path)?.into_rgb8())
+	};
 	let (source_width, source_height) = source_image.dimensions();
 	let largest_dimension = cmp::max(source_width, source_height);
 	let out_dimension = cmp::min(
@@ -126,12 +494,22 @@ fn generate_thumbnail(image_path: &Path, options: &Options) -> Result<DynamicIma
 	} else if options.pad_to_square {
 		let scaled_image = source_image.thumbnail(out_dimension, out_dimension);
 		let (scaled_width, scaled_height) = scaled_image.dimensions();
-		let background = image::Rgb([255, 255_u8, 255_u8]);
-		final_image = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(
-			out_dimension,
-			out_dimension,
-			background,
-		));
+		let [red, green, blue, alpha] = options.background;
+		final_image = if transparent {
+			let background = image::Rgba([red, green, blue, alpha]);
+			DynamicImage::ImageRgba8(ImageBuffer::from_pixel(
+				out_dimension,
+				out_dimension,
+				background,
+			))
+		} else {
+			let background = image::Rgb([red, green, blue]);
+			DynamicImage::ImageRgb8(ImageBuffer::from_pixel(
+				out_dimension,
+				out_dimension,
+				background,
+			))
+		};
 		final_image
 			.copy_from(
 				&scaled_image,
@@ -157,19 +535,114 @@ fn read(image_path: &Path) -> Result<DynamicImage, Error> {
 		Some(AudioFormat::OGG) => read_vorbis(image_path),
 		Some(AudioFormat::OPUS) => read_opus(image_path),
 		Some(AudioFormat::WAVE) => read_wave(image_path),
-		None => image::open(image_path).map_err(|e| Error::Image(image_path.to_owned(), e)),
+		None => read_image_file(image_path),
 	}
 }
 
-fn read_ape(_: &Path) -> Result<DynamicImage, Error> {
-	Err(Error::UnsupportedFormat("ape"))
+fn read_image_file(path: &Path) -> Result<DynamicImage, Error> {
+	let data = fs::read(path).map_err(|e| Error::Io(path.to_owned(), e))?;
+	let format = image::ImageFormat::from_path(path)
+		.or_else(|_| image::guess_format(&data))
+		.map_err(|e| Error::Image(path.to_owned(), e))?;
+	let image = image::load_from_memory_with_format(&data, format)
+		.map_err(|e| Error::Image(path.to_owned(), e))?;
+	Ok(apply_exif_orientation(image, &data))
+}
+
+fn decode_embedded(path: &Path, data: &[u8]) -> Result<DynamicImage, Error> {
+	let image = image::load_from_memory(data).map_err(|e| Error::Image(path.to_owned(), e))?;
+	Ok(apply_exif_orientation(image, data))
+}
+
+// Rotates and flips the image the way its EXIF orientation tag says it should be displayed.
+fn apply_exif_orientation(image: DynamicImage, data: &[u8]) -> DynamicImage {
+	match read_jpeg_exif_orientation(data) {
+		Some(2) => image.fliph(),
+		Some(3) => image.rotate180(),
+		Some(4) => image.flipv(),
+		Some(5) => image.rotate90().fliph(),
+		Some(6) => image.rotate90(),
+		Some(7) => image.rotate270().fliph(),
+		Some(8) => image.rotate270(),
+		_ => image,
+	}
+}
+
+// Looks for the orientation tag in the first IFD of a JPEG's EXIF segment.
+fn read_jpeg_exif_orientation(data: &[u8]) -> Option<u16> {
+	if !data.starts_with(&[0xFF, 0xD8]) {
+		return None;
+	}
+	let mut offset = 2;
+	loop {
+		let marker = data.get(offset..offset + 2)?;
+		// Image data starts at the SOS marker, there is no EXIF past it
+		if marker[0] != 0xFF || marker[1] == 0xDA || marker[1] == 0xD9 {
+			return None;
+		}
+		let length =
+			u16::from_be_bytes(data.get(offset + 2..offset + 4)?.try_into().ok()?) as usize;
+		let segment = data.get(offset + 4..offset + 2 + length)?;
+		if marker[1] == 0xE1 && segment.starts_with(b"Exif\0\0") {
+			return read_tiff_orientation(&segment[6..]);
+		}
+		offset += 2 + length;
+	}
+}
+
+fn read_tiff_orientation(tiff: &[u8]) -> Option<u16> {
+	let big_endian = match tiff.get(0..2)? {
+		b"MM" => true,
+		b"II" => false,
+		_ => return None,
+	};
+	let read_u16 = |offset: usize| -> Option<u16> {
+		let bytes = tiff.get(offset..offset + 2)?.try_into().ok()?;
+		Some(if big_endian {
+			u16::from_be_bytes(bytes)
+		} else {
+			u16::from_le_bytes(bytes)
+		})
+	};
+	let read_u32 = |offset: usize| -> Option<u32> {
+		let bytes = tiff.get(offset..offset + 4)?.try_into().ok()?;
+		Some(if big_endian {
+			u32::from_be_bytes(bytes)
+		} else {
+			u32::from_le_bytes(bytes)
+		})
+	};
+	let ifd = read_u32(4)? as usize;
+	let entry_count = read_u16(ifd)? as usize;
+	(0..entry_count)
+		.map(|i| ifd + 2 + i * 12)
+		.find(|entry| read_u16(*entry) == Some(0x0112))
+		.and_then(|entry| read_u16(entry + 8))
+}
+
+fn read_ape(path: &Path) -> Result<DynamicImage, Error> {
+	let tag = ape::read_from_path(path).map_err(|e| Error::Ape(path.to_owned(), e))?;
+	match tag.item("Cover Art (Front)").map(|item| &item.value) {
+		// Binary cover items start with a null terminated file name
+		Some(ape::ItemValue::Binary(value)) => {
+			let data = match value.iter().position(|b| *b == 0) {
+				Some(index) => &value[index + 1..],
+				None => &value[..],
+			};
+			decode_embedded(path, data)
+		}
+		_ => Err(Error::UnsupportedFormat("ape")),
+	}
 }
 
 fn read_flac(path: &Path) -> Result<DynamicImage, Error> {
 	let tag =
 		metaflac::Tag::read_from_path(path).map_err(|e| Error::Metaflac(path.to_owned(), e))?;
-	if let Some(p) = tag.pictures().next() {
-		return image::load_from_memory(&p.data).map_err(|e| Error::Image(path.to_owned(), e));
+	let front_cover = tag
+		.pictures()
+		.find(|p| p.picture_type == metaflac::block::PictureType::CoverFront);
+	if let Some(p) = front_cover.or_else(|| tag.pictures().next()) {
+		return decode_embedded(path, &p.data);
 	}
 	Err(Error::EmbeddedArtworkNotFound(path.to_owned()))
 }
@@ -191,11 +664,10 @@ fn read_wave(path: &Path) -> Result<DynamicImage, Error> {
 
 fn read_id3(path: &Path, tag: &id3::Tag) -> Result<DynamicImage, Error> {
 	tag.pictures()
-		.next()
+		.find(|p| p.picture_type == id3::frame::PictureType::CoverFront)
+		.or_else(|| tag.pictures().next())
 		.ok_or_else(|| Error::EmbeddedArtworkNotFound(path.to_owned()))
-		.and_then(|d| {
-			image::load_from_memory(&d.data).map_err(|e| Error::Image(path.to_owned(), e))
-		})
+		.and_then(|d| decode_embedded(path, &d.data))
 }
 
 fn read_mp4(path: &Path) -> Result<DynamicImage, Error> {
@@ -203,21 +675,91 @@ fn read_mp4(path: &Path) -> Result<DynamicImage, Error> {
 		mp4ameta::Tag::read_from_path(path).map_err(|e| Error::Mp4aMeta(path.to_owned(), e))?;
 	tag.artwork()
 		.ok_or_else(|| Error::EmbeddedArtworkNotFound(path.to_owned()))
-		.and_then(|d| image::load_from_memory(d.data).map_err(|e| Error::Image(path.to_owned(), e)))
+		.and_then(|d| decode_embedded(path, d.data))
 }
 
-fn read_vorbis(_: &Path) -> Result<DynamicImage, Error> {
-	Err(Error::UnsupportedFormat("vorbis"))
+fn read_vorbis(path: &Path) -> Result<DynamicImage, Error> {
+	let file = File::open(path).map_err(|e| Error::Io(path.to_owned(), e))?;
+	let source = OggStreamReader::new(file).map_err(|e| Error::Vorbis(path.to_owned(), e))?;
+	match read_vorbis_comment_picture(
+		path,
+		source.comment_hdr.comment_list.iter().map(|(k, v)| (k, v)),
+	)? {
+		Some(image) => Ok(image),
+		None => Err(Error::UnsupportedFormat("vorbis")),
+	}
+}
+
+fn read_opus(path: &Path) -> Result<DynamicImage, Error> {
+	let headers =
+		opus_headers::parse_from_path(path).map_err(|e| Error::Opus(path.to_owned(), e))?;
+	match read_vorbis_comment_picture(
+		path,
+		headers.comments.user_comments.iter().map(|(k, v)| (k, v)),
+	)? {
+		Some(image) => Ok(image),
+		None => Err(Error::UnsupportedFormat("opus")),
+	}
+}
+
+// Ogg files store their artwork as a base64 encoded FLAC picture block.
+fn read_vorbis_comment_picture<'a, C>(
+	path: &Path,
+	comments: C,
+) -> Result<Option<DynamicImage>, Error>
+where
+	C: IntoIterator<Item = (&'a String, &'a String)>,
+{
+	let blocks = comments
+		.into_iter()
+		.filter_map(|(key, value)| {
+			utils::match_ignore_case! {
+				match key {
+					"METADATA_BLOCK_PICTURE" => Some(value),
+					_ => None,
+				}
+			}
+		})
+		.map(|value| base64::decode(value).map_err(|_| Error::PictureBlock(path.to_owned())))
+		.collect::<Result<Vec<_>, _>>()?;
+	let pictures = blocks
+		.iter()
+		.map(|block| read_picture_block(block).ok_or_else(|| Error::PictureBlock(path.to_owned())))
+		.collect::<Result<Vec<_>, _>>()?;
+
+	// Same picture types as ID3, 3 is the front cover
+	let front_cover = pictures.iter().find(|(picture_type, _)| *picture_type == 3);
+	match front_cover.or_else(|| pictures.first()) {
+		Some((_, data)) => decode_embedded(path, data).map(Some),
+		None => Ok(None),
+	}
 }
 
-fn read_opus(_: &Path) -> Result<DynamicImage, Error> {
-	Err(Error::UnsupportedFormat("opus"))
+// Layout of a picture block, with big endian lengths:
+// type, mime length, mime, description length, description,
+// width, height, depth, colors, data length, data
+fn read_picture_block(block: &[u8]) -> Option<(usize, &[u8])> {
+	fn read_u32(block: &[u8], offset: usize) -> Option<usize> {
+		let bytes = block.get(offset..offset + 4)?;
+		Some(u32::from_be_bytes(bytes.try_into().ok()?) as usize)
+	}
+	let picture_type = read_u32(block, 0)?;
+	let mut offset = 4;
+	let mime_length = read_u32(block, offset)?;
+	offset += 4 + mime_length;
+	let description_length = read_u32(block, offset)?;
+	offset += 4 + description_length + 16;
+	let data_length = read_u32(block, offset)?;
+	offset += 4;
+	Some((picture_type, block.get(offset..offset + data_length)?))
 }
 
 #[cfg(test)]
 mod test {
 
 	use super::*;
+	use crate::test::prepare_test_directory;
+	use crate::test_name;
 
 	#[test]
 	fn can_read_artwork_data() {
@@ -239,9 +781,9 @@ mod test {
 		assert_eq!(aiff_img, embedded_img);
 
 		let ape_img = read(Path::new("test-data/artwork/sample.ape"))
-			.map(|d| d.to_rgb8())
-			.ok();
-		assert_eq!(ape_img, None);
+			.unwrap()
+			.to_rgb8();
+		assert_eq!(ape_img, embedded_img);
 
 		let flac_img = read(Path::new("test-data/artwork/sample.flac"))
 			.unwrap()
@@ -259,18 +801,351 @@ mod test {
 		assert_eq!(m4a_img, embedded_img);
 
 		let ogg_img = read(Path::new("test-data/artwork/sample.ogg"))
-			.map(|d| d.to_rgb8())
-			.ok();
-		assert_eq!(ogg_img, None);
+			.unwrap()
+			.to_rgb8();
+		assert_eq!(ogg_img, embedded_img);
 
 		let opus_img = read(Path::new("test-data/artwork/sample.opus"))
-			.map(|d| d.to_rgb8())
-			.ok();
-		assert_eq!(opus_img, None);
+			.unwrap()
+			.to_rgb8();
+		assert_eq!(opus_img, embedded_img);
+
+		let ogg_without_picture = read(Path::new("test-data/formats/sample.ogg"));
+		assert!(matches!(
+			ogg_without_picture,
+			Err(Error::UnsupportedFormat("vorbis"))
+		));
+
+		let opus_without_picture = read(Path::new("test-data/formats/sample.opus"));
+		assert!(matches!(
+			opus_without_picture,
+			Err(Error::UnsupportedFormat("opus"))
+		));
 
 		let wave_img = read(Path::new("test-data/artwork/sample.wav"))
 			.unwrap()
 			.to_rgb8();
 		assert_eq!(wave_img, embedded_img);
 	}
+
+	#[test]
+	fn thumbnail_format_sets_encoder_and_extension() {
+		let output_dir = prepare_test_directory(test_name!());
+		let manager = Manager::new(output_dir);
+		let image_path = Path::new("test-data/artwork/Folder.png");
+
+		let mut paths = HashSet::new();
+		for (format, extension, image_format) in [
+			(Format::Jpeg, "jpg", image::ImageFormat::Jpeg),
+			(Format::Png, "png", image::ImageFormat::Png),
+			(Format::WebP, "webp", image::ImageFormat::WebP),
+		] {
+			let options = Options {
+				format,
+				..Default::default()
+			};
+			let path = manager.get_thumbnail(image_path, &options).unwrap();
+			assert_eq!(path.extension().unwrap(), extension);
+			assert_eq!(image::ImageFormat::from_path(&path).unwrap(), image_format);
+			let content = fs::read(&path).unwrap();
+			assert_eq!(image::guess_format(&content).unwrap(), image_format);
+			paths.insert(path);
+		}
+		assert_eq!(paths.len(), 3);
+	}
+
+	#[test]
+	fn padding_uses_background_color() {
+		let output_dir = prepare_test_directory(test_name!());
+		let image_path = output_dir.join("wide.png");
+		image::RgbImage::from_pixel(8, 4, image::Rgb([255, 0, 0]))
+			.save(&image_path)
+			.unwrap();
+		let manager = Manager::new(output_dir.join("thumbnails"));
+
+		let corner_pixel = |options: &Options| {
+			let path = manager.get_thumbnail(&image_path, options).unwrap();
+			let thumbnail = image::open(&path).unwrap().to_rgba8();
+			(path, *thumbnail.get_pixel(0, 0))
+		};
+
+		let options = Options {
+			resize_if_almost_square: false,
+			format: Format::Png,
+			background: [0, 0, 0, 255],
+			..Default::default()
+		};
+		let (_, pixel) = corner_pixel(&options);
+		assert_eq!(pixel, image::Rgba([0, 0, 0, 255]));
+
+		// JPEG cannot store transparency
+		let options = Options {
+			resize_if_almost_square: false,
+			background: [0, 0, 0, 0],
+			..Default::default()
+		};
+		let (path, pixel) = corner_pixel(&options);
+		assert_eq!(path.extension().unwrap(), "png");
+		assert_eq!(pixel[3], 0);
+	}
+
+	#[test]
+	fn jpeg_quality_changes_thumbnail() {
+		let output_dir = prepare_test_directory(test_name!());
+		let manager = Manager::new(output_dir);
+		let image_path = Path::new("test-data/artwork/Folder.png");
+
+		let thumbnail = |format, quality| {
+			let options = Options {
+				format,
+				quality,
+				..Default::default()
+			};
+			manager.get_thumbnail(image_path, &options).unwrap()
+		};
+		let file_size = |quality| {
+			fs::metadata(thumbnail(Format::Jpeg, quality))
+				.unwrap()
+				.len()
+		};
+		assert!(file_size(10) < file_size(95));
+
+		// Out of range values are clamped and share the cache entry of the clamped value
+		assert_eq!(thumbnail(Format::Jpeg, 0), thumbnail(Format::Jpeg, 1));
+		assert_eq!(thumbnail(Format::Jpeg, 200), thumbnail(Format::Jpeg, 100));
+
+		// Quality only matters to JPEG thumbnails
+		assert_eq!(thumbnail(Format::Png, 10), thumbnail(Format::Png, 95));
+	}
+
+	#[test]
+	fn content_cache_key_survives_moves() {
+		let output_dir = prepare_test_directory(test_name!());
+		let original_path = output_dir.join("original.png");
+		let moved_path = output_dir.join("moved.png");
+		fs::copy("test-data/artwork/Folder.png", &original_path).unwrap();
+		fs::copy("test-data/artwork/Folder.png", &moved_path).unwrap();
+		let options = Options::default();
+
+		let manager = Manager::with_max_generations(output_dir.join("by_path"), 1);
+		let original = manager.get_thumbnail(&original_path, &options).unwrap();
+		let moved = manager.get_thumbnail(&moved_path, &options).unwrap();
+		assert_ne!(original, moved);
+
+		let manager = Manager::with_max_generations(output_dir.join("by_content"), 1)
+			.with_cache_key(CacheKey::Content);
+		let original = manager.get_thumbnail(&original_path, &options).unwrap();
+		let moved = manager.get_thumbnail(&moved_path, &options).unwrap();
+		assert_eq!(original, moved);
+	}
+
+	// Encodes a wide JPEG with an EXIF segment holding the given orientation
+	fn jpeg_with_orientation(orientation: u16) -> Vec<u8> {
+		let mut jpeg = Vec::new();
+		DynamicImage::ImageRgb8(image::RgbImage::from_pixel(8, 4, image::Rgb([255, 0, 0])))
+			.write_to(&mut Cursor::new(&mut jpeg), ImageOutputFormat::Jpeg(80))
+			.unwrap();
+
+		let mut tiff = b"II*\0".to_vec();
+		tiff.extend(8_u32.to_le_bytes());
+		tiff.extend(1_u16.to_le_bytes());
+		tiff.extend(0x0112_u16.to_le_bytes());
+		tiff.extend(3_u16.to_le_bytes());
+		tiff.extend(1_u32.to_le_bytes());
+		tiff.extend(orientation.to_le_bytes());
+		tiff.extend([0, 0]);
+		tiff.extend(0_u32.to_le_bytes());
+
+		let mut segment = vec![0xFF, 0xE1];
+		segment.extend(((tiff.len() + 8) as u16).to_be_bytes());
+		segment.extend(b"Exif\0\0");
+		segment.extend(tiff);
+
+		let mut data = jpeg[..2].to_vec();
+		data.extend(segment);
+		data.extend(&jpeg[2..]);
+		data
+	}
+
+	#[test]
+	fn applies_exif_orientation() {
+		let output_dir = prepare_test_directory(test_name!());
+		let path = output_dir.join("rotated.jpg");
+
+		fs::write(&path, jpeg_with_orientation(6)).unwrap();
+		assert_eq!(read(&path).unwrap().dimensions(), (4, 8));
+
+		fs::write(&path, jpeg_with_orientation(1)).unwrap();
+		assert_eq!(read(&path).unwrap().dimensions(), (8, 4));
+
+		let embedded = decode_embedded(&path, &jpeg_with_orientation(8)).unwrap();
+		assert_eq!(embedded.dimensions(), (4, 8));
+
+		// Images without EXIF are left untouched
+		let png = read(Path::new("test-data/artwork/Folder.png")).unwrap();
+		assert_eq!(png.dimensions(), (4, 4));
+	}
+
+	#[test]
+	fn generates_thumbnail_bytes_without_cache() {
+		let (bytes, format) = generate_thumbnail_bytes(
+			Path::new("test-data/artwork/Folder.png"),
+			&Options {
+				format: Format::Png,
+				..Default::default()
+			},
+		)
+		.unwrap();
+		assert_eq!(format, ImageFormat::Png);
+		assert_eq!(image::guess_format(&bytes).unwrap(), ImageFormat::Png);
+		assert_eq!(
+			image::load_from_memory(&bytes).unwrap().dimensions(),
+			(4, 4)
+		);
+	}
+
+	#[test]
+	fn prunes_least_recently_used_thumbnails() {
+		let output_dir = prepare_test_directory(test_name!());
+		let manager = Manager::with_max_generations(output_dir, 1);
+		let image_path = Path::new("test-data/artwork/Folder.png");
+		let options = |quality| Options {
+			quality,
+			..Default::default()
+		};
+
+		let paths: Vec<PathBuf> = [40, 60, 80]
+			.into_iter()
+			.map(|quality| {
+				std::thread::sleep(std::time::Duration::from_millis(20));
+				manager
+					.get_thumbnail(image_path, &options(quality))
+					.unwrap()
+			})
+			.collect();
+
+		// Hits count as uses
+		std::thread::sleep(std::time::Duration::from_millis(20));
+		manager.get_thumbnail(image_path, &options(40)).unwrap();
+
+		// Below the maximum size, nothing is pruned
+		let total_size: u64 = paths.iter().map(|p| fs::metadata(p).unwrap().len()).sum();
+		let manager = manager.with_max_cache_size(Some(total_size));
+		manager.prune_cache().unwrap();
+		assert!(paths.iter().all(|p| p.exists()));
+
+		// Above it, the cache is pruned a tenth under it
+		let hit_size = fs::metadata(&paths[0]).unwrap().len();
+		let manager = manager.with_max_cache_size(Some(hit_size + hit_size / 5));
+		manager.prune_cache().unwrap();
+		assert!(paths[0].exists());
+		assert!(!paths[1].exists());
+		assert!(!paths[2].exists());
+		assert_eq!(*manager.cache_size.lock().unwrap(), Some(hit_size));
+	}
+
+	#[test]
+	fn pruned_thumbnails_are_generated_again() {
+		let output_dir = prepare_test_directory(test_name!());
+		let manager = Manager::with_max_generations(output_dir, 1);
+		let image_path = Path::new("test-data/artwork/Folder.png");
+		let options = Options::default();
+
+		let path = manager.get_thumbnail(image_path, &options).unwrap();
+		fs::remove_file(&path).unwrap();
+		let (opened_path, file) = manager.open_thumbnail(image_path, &options).unwrap();
+		assert_eq!(opened_path, path);
+		assert!(file.metadata().unwrap().len() > 0);
+	}
+
+	#[test]
+	fn generates_thumbnails_in_batch() {
+		let output_dir = prepare_test_directory(test_name!());
+		let manager = Manager::with_max_generations(output_dir, 2);
+		let image_paths = vec![
+			PathBuf::from("test-data/artwork/Folder.png"),
+			PathBuf::from("test-data/artwork/missing.png"),
+			PathBuf::from("test-data/artwork/Embedded.png"),
+		];
+
+		let results = manager.generate_thumbnails(&image_paths, &Options::default());
+		assert_eq!(results.len(), 3);
+		assert!(results[0].as_ref().unwrap().exists());
+		assert!(results[1].is_err());
+		assert!(results[2].as_ref().unwrap().exists());
+
+		// Cached thumbnails are reused
+		let again = manager.generate_thumbnails(&image_paths[..1], &Options::default());
+		assert_eq!(again[0].as_ref().unwrap(), results[0].as_ref().unwrap());
+	}
+
+	#[test]
+	fn prefers_front_cover() {
+		let output_dir = prepare_test_directory(test_name!());
+		let front_cover = fs::read("test-data/artwork/Embedded.png").unwrap();
+		let back_cover = fs::read("test-data/artwork/Folder.png").unwrap();
+		let expected = image::load_from_memory(&front_cover).unwrap().to_rgb8();
+
+		let flac_path = output_dir.join("covers.flac");
+		fs::copy("test-data/artwork/sample.flac", &flac_path).unwrap();
+		let mut tag = metaflac::Tag::read_from_path(&flac_path).unwrap();
+		tag.remove_blocks(metaflac::BlockType::Picture);
+		tag.add_picture(
+			"image/png",
+			metaflac::block::PictureType::CoverBack,
+			back_cover.clone(),
+		);
+		tag.add_picture(
+			"image/png",
+			metaflac::block::PictureType::CoverFront,
+			front_cover.clone(),
+		);
+		tag.save().unwrap();
+		assert_eq!(read(&flac_path).unwrap().to_rgb8(), expected);
+
+		let mp3_path = output_dir.join("covers.mp3");
+		fs::copy("test-data/artwork/sample.mp3", &mp3_path).unwrap();
+		let mut tag = id3::Tag::read_from_path(&mp3_path).unwrap();
+		tag.remove_all_pictures();
+		for (picture_type, data) in [
+			(id3::frame::PictureType::CoverBack, back_cover),
+			(id3::frame::PictureType::CoverFront, front_cover),
+		] {
+			tag.add_frame(id3::frame::Picture {
+				mime_type: "image/png".to_owned(),
+				picture_type,
+				description: format!("{:?}", picture_type),
+				data,
+			});
+		}
+		tag.write_to_path(&mp3_path, id3::Version::Id3v24).unwrap();
+		assert_eq!(read(&mp3_path).unwrap().to_rgb8(), expected);
+	}
+
+	#[test]
+	fn concurrent_requests_share_one_thumbnail() {
+		let output_dir = prepare_test_directory(test_name!());
+		let manager = Manager::with_max_generations(output_dir, 1);
+
+		let threads: Vec<_> = (0..4)
+			.map(|_| {
+				let manager = manager.clone();
+				std::thread::spawn(move || {
+					manager
+						.get_thumbnail(
+							Path::new("test-data/artwork/Folder.png"),
+							&Options::default(),
+						)
+						.unwrap()
+				})
+			})
+			.collect();
+		let paths: HashSet<PathBuf> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+		assert_eq!(paths.len(), 1);
+		assert!(paths.iter().all(|p| p.exists()));
+
+		let generations = manager.limiter.generations.lock().unwrap();
+		assert_eq!(generations.active, 0);
+		assert!(generations.in_flight.is_empty());
+	}
 }
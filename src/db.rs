@@ -1,4 +1,5 @@
 use diesel::r2d2::{self, ConnectionManager, PooledConnection};
+use diesel::sql_types::{Double, Nullable, Text};
 use diesel::sqlite::SqliteConnection;
 use diesel::RunQueryDsl;
 use diesel_migrations::EmbeddedMigrations;
@@ -11,6 +12,11 @@ pub use self::schema::*;
 
 const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
 
+// Registered on every connection, see crate::utils::fold_accents
+sql_function!(fn fold_accents(text: Nullable<Text>) -> Nullable<Text>);
+// Registered on every connection, see crate::utils::fuzzy_score
+sql_function!(fn fuzzy_score(text: Nullable<Text>, query: Text) -> Double);
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
 	#[error("Could not initialize database connection pool")]
@@ -45,6 +51,14 @@ impl diesel::r2d2::CustomizeConnection<SqliteConnection, diesel::r2d2::Error>
 		query
 			.execute(connection)
 			.map_err(diesel::r2d2::Error::QueryError)?;
+		fold_accents::register_impl(connection, |text: Option<String>| {
+			text.map(|t| crate::utils::fold_accents(&t))
+		})
+		.map_err(diesel::r2d2::Error::QueryError)?;
+		fuzzy_score::register_impl(connection, |text: Option<String>, query: String| {
+			text.map_or(0.0, |t| crate::utils::fuzzy_score(&t, &query))
+		})
+		.map_err(diesel::r2d2::Error::QueryError)?;
 		Ok(())
 	}
 }
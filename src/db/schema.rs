@@ -26,6 +26,7 @@ table! {
 		auth_secret -> Binary,
 		index_sleep_duration_seconds -> Integer,
 		index_album_art_pattern -> Text,
+		index_insert_buffer_size -> Nullable<Integer>,
 	}
 }
 
@@ -85,6 +86,7 @@ table! {
 		lastfm_session_key -> Nullable<Text>,
 		web_theme_base -> Nullable<Text>,
 		web_theme_accent -> Nullable<Text>,
+		rj_enabled -> Nullable<Integer>,
 	}
 }
 
@@ -94,6 +96,15 @@ table! {
 		tts_service_url -> Nullable<Text>,
 		tts_text_param_key -> Nullable<Text>,
 		tts_enable_ssml -> Integer,
+		tts_headers -> Text,
+		tts_fallback_path -> Nullable<Text>,
+		allowed_voice_models -> Text,
+		allowed_languages -> Text,
+		pronunciations -> Text,
+		natural_pause -> Nullable<Text>,
+		tts_content_type -> Nullable<Text>,
+		tts_backend -> Text,
+		genre_languages -> Text,
 	}
 }
 
@@ -103,6 +114,8 @@ table! {
 		scripts -> Nullable<Text>,
 		enable_by_default -> Nullable<Integer>,
 		tts_people -> Text,
+		voice_rules -> Text,
+		default_host -> Nullable<Text>,
 	}
 }
 
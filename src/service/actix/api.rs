@@ -1,7 +1,7 @@
 use actix_files::NamedFile;
 use actix_web::body::BoxBody;
 use actix_web::http::header::{
-	ContentDisposition, ContentEncoding, DispositionParam, DispositionType,
+	ContentDisposition, ContentEncoding, DispositionParam, DispositionType, HeaderValue,
 };
 use actix_web::{
 	delete,
@@ -10,14 +10,16 @@ use actix_web::{
 	get,
 	http::StatusCode,
 	post, put,
-	web::{self, Data, Json, JsonConfig, ServiceConfig},
-	FromRequest, HttpRequest, HttpResponse, Responder, ResponseError,
+	web::{self, Bytes, Data, Json, JsonConfig, ServiceConfig},
+	FromRequest, HttpRequest, HttpResponse, HttpResponseBuilder, Responder, ResponseError,
 };
 use actix_web_httpauth::extractors::bearer::BearerAuth;
 use futures_util::future::err;
+use futures_util::stream::{self, Stream};
 use percent_encoding::percent_decode_str;
 use std::future::Future;
-use std::path::{Path, PathBuf};
+use std::io::Read;
+use std::path::Path;
 use std::pin::Pin;
 use std::str;
 
@@ -49,6 +51,8 @@ pub fn make_config() -> impl FnOnce(&mut ServiceConfig) + Clone {
 			.service(get_preferences)
 			.service(put_preferences)
 			.service(trigger_index)
+			.service(cancel_index)
+			.service(get_index_progress)
 			.service(login)
 			.service(browse_root)
 			.service(browse)
@@ -56,15 +60,27 @@ pub fn make_config() -> impl FnOnce(&mut ServiceConfig) + Clone {
 			.service(flatten)
 			.service(random)
 			.service(recent)
+			.service(random_songs)
+			.service(recent_songs)
 			.service(search_root)
 			.service(search)
+			.service(search_with_matches)
+			.service(search_count)
 			.service(get_audio)
 			.service(get_thumbnail)
+			.service(generate_thumbnails)
 			.service(list_playlists)
+			.service(search_playlists)
+			.service(playlists_containing)
 			.service(save_playlist)
 			.service(read_playlist)
+			.service(append_to_playlist)
+			.service(remove_from_playlist)
+			.service(rename_playlist)
+			.service(get_playlist_stats)
 			.service(delete_playlist)
 			.service(export_playlist_m3u)
+			.service(export_songs)
 			.service(import_playlist_m3u)
 			.service(lastfm_now_playing)
 			.service(lastfm_scrobble)
@@ -72,10 +88,22 @@ pub fn make_config() -> impl FnOnce(&mut ServiceConfig) + Clone {
 			.service(lastfm_link)
 			.service(lastfm_unlink)
 			.service(get_announcement)
+			.service(get_announcement_packet)
+			.service(get_announcement_with_text)
+			.service(get_song_announcement)
+			.service(preview_rj_announcement)
 			.service(get_rj_admin_settings)
 			.service(put_rj_admin_settings)
+			.service(reset_rj_admin_settings)
+			.service(get_rj_diagnostics)
+			.service(test_rj_synthesis)
 			.service(get_rj_user_settings)
-			.service(put_rj_user_settings);
+			.service(put_rj_user_settings)
+			.service(reset_rj_user_settings)
+			.service(put_rj_user_enabled)
+			.service(get_rj_host)
+			.service(get_rj_default_host)
+			.service(put_rj_default_host);
 	}
 }
 
@@ -102,7 +130,9 @@ impl ResponseError for APIError {
 			APIError::LastFMLinkContentBase64DecodeError => StatusCode::BAD_REQUEST,
 			APIError::LastFMLinkContentEncodingError => StatusCode::BAD_REQUEST,
 			APIError::PlaylistNotFound(_) => StatusCode::NOT_FOUND,
+			APIError::PlaylistAlreadyExists(_) => StatusCode::CONFLICT,
 			APIError::ParseFailed(_) => StatusCode::INTERNAL_SERVER_ERROR,
+			APIError::InvalidPlaylist(_) => StatusCode::BAD_REQUEST,
 			APIError::LastFMNowPlaying(_) => StatusCode::FAILED_DEPENDENCY,
 			APIError::LastFMScrobble(_) => StatusCode::FAILED_DEPENDENCY,
 			APIError::LastFMScrobblerAuthentication(_) => StatusCode::FAILED_DEPENDENCY,
@@ -110,15 +140,20 @@ impl ResponseError for APIError {
 			APIError::PasswordHashing => StatusCode::INTERNAL_SERVER_ERROR,
 			APIError::Settings(_) => StatusCode::INTERNAL_SERVER_ERROR,
 			APIError::SongMetadataNotFound => StatusCode::NOT_FOUND,
+			APIError::ThumbnailApeDecoding(_, _) => StatusCode::INTERNAL_SERVER_ERROR,
 			APIError::ThumbnailFlacDecoding(_, _) => StatusCode::INTERNAL_SERVER_ERROR,
 			APIError::ThumbnailFileIOError => StatusCode::NOT_FOUND,
 			APIError::ThumbnailId3Decoding(_, _) => StatusCode::INTERNAL_SERVER_ERROR,
 			APIError::ThumbnailImageDecoding(_, _) => StatusCode::INTERNAL_SERVER_ERROR,
 			APIError::ThumbnailMp4Decoding(_, _) => StatusCode::INTERNAL_SERVER_ERROR,
+			APIError::ThumbnailOpusDecoding(_, _) => StatusCode::INTERNAL_SERVER_ERROR,
+			APIError::ThumbnailPictureBlockDecoding(_) => StatusCode::INTERNAL_SERVER_ERROR,
+			APIError::ThumbnailVorbisDecoding(_, _) => StatusCode::INTERNAL_SERVER_ERROR,
 			APIError::TomlDeserialization(_) => StatusCode::INTERNAL_SERVER_ERROR,
 			APIError::UnsupportedThumbnailFormat(_) => StatusCode::INTERNAL_SERVER_ERROR,
 			APIError::UserNotFound => StatusCode::NOT_FOUND,
 			APIError::VFSPathNotFound => StatusCode::NOT_FOUND,
+			APIError::RjHostNotFound(_) => StatusCode::NOT_FOUND,
 		}
 	}
 
@@ -297,9 +332,9 @@ async fn put_settings(
 	_admin_rights: AdminRights,
 	settings_manager: Data<settings::Manager>,
 	new_settings: Json<dto::NewSettings>,
-) -> Result<HttpResponse, APIError> {
-	block(move || settings_manager.amend(&new_settings.to_owned().into())).await?;
-	Ok(HttpResponse::new(StatusCode::OK))
+) -> Result<Json<dto::Settings>, APIError> {
+	let previous = block(move || settings_manager.amend(&new_settings.to_owned().into())).await?;
+	Ok(Json(previous.into()))
 }
 
 #[get("/mount_dirs")]
@@ -432,6 +467,24 @@ async fn trigger_index(
 	Ok(HttpResponse::new(StatusCode::OK))
 }
 
+#[post("/cancel_index")]
+async fn cancel_index(
+	index: Data<Index>,
+	_admin_rights: AdminRights,
+	options: web::Query<dto::CancelIndexOptions>,
+) -> Result<HttpResponse, APIError> {
+	index.cancel_update(options.discard_pending.unwrap_or_default());
+	Ok(HttpResponse::new(StatusCode::OK))
+}
+
+#[get("/index_progress")]
+async fn get_index_progress(
+	index: Data<Index>,
+	_admin_rights: AdminRights,
+) -> Json<index::IndexProgress> {
+	Json(index.get_update_progress())
+}
+
 #[post("/auth")]
 async fn login(
 	user_manager: Data<user::Manager>,
@@ -458,8 +511,18 @@ async fn login(
 async fn browse_root(
 	index: Data<Index>,
 	_auth: Auth,
+	page: web::Query<dto::Pagination>,
+	browse_options: web::Query<dto::BrowseOptions>,
 ) -> Result<Json<Vec<index::CollectionFile>>, APIError> {
-	let result = block(move || index.browse(Path::new(""))).await?;
+	let result = block(move || {
+		index.browse_page(
+			Path::new(""),
+			page.offset.unwrap_or(0),
+			page.limit,
+			browse_options.order.unwrap_or_default(),
+		)
+	})
+	.await?;
 	Ok(Json(result))
 }
 
@@ -468,19 +531,49 @@ async fn browse(
 	index: Data<Index>,
 	_auth: Auth,
 	path: web::Path<String>,
+	page: web::Query<dto::Pagination>,
+	browse_options: web::Query<dto::BrowseOptions>,
 ) -> Result<Json<Vec<index::CollectionFile>>, APIError> {
 	let result = block(move || {
 		let path = percent_decode_str(&path).decode_utf8_lossy();
-		index.browse(Path::new(path.as_ref()))
+		index.browse_page(
+			Path::new(path.as_ref()),
+			page.offset.unwrap_or(0),
+			page.limit,
+			browse_options.order.unwrap_or_default(),
+		)
 	})
 	.await?;
 	Ok(Json(result))
 }
 
+fn songs_response(songs: Vec<index::Song>, options: &dto::SongOptions) -> HttpResponse {
+	if options.include_id.unwrap_or_default() {
+		let songs: Vec<dto::SongWithId> = songs.into_iter().map(|s| s.into()).collect();
+		HttpResponse::Ok().json(songs)
+	} else {
+		HttpResponse::Ok().json(songs)
+	}
+}
+
 #[get("/flatten")]
-async fn flatten_root(index: Data<Index>, _auth: Auth) -> Result<Json<Vec<index::Song>>, APIError> {
-	let songs = block(move || index.flatten(Path::new(""))).await?;
-	Ok(Json(songs))
+async fn flatten_root(
+	index: Data<Index>,
+	_auth: Auth,
+	page: web::Query<dto::Pagination>,
+	flatten_options: web::Query<dto::FlattenOptions>,
+	options: web::Query<dto::SongOptions>,
+) -> Result<HttpResponse, APIError> {
+	let songs = block(move || {
+		index.flatten_page(
+			Path::new(""),
+			page.offset.unwrap_or(0),
+			page.limit,
+			flatten_options.order.unwrap_or_default(),
+		)
+	})
+	.await?;
+	Ok(songs_response(songs, &options))
 }
 
 #[get("/flatten/{path:.*}")]
@@ -488,13 +581,21 @@ async fn flatten(
 	index: Data<Index>,
 	_auth: Auth,
 	path: web::Path<String>,
-) -> Result<Json<Vec<index::Song>>, APIError> {
+	page: web::Query<dto::Pagination>,
+	flatten_options: web::Query<dto::FlattenOptions>,
+	options: web::Query<dto::SongOptions>,
+) -> Result<HttpResponse, APIError> {
 	let songs = block(move || {
 		let path = percent_decode_str(&path).decode_utf8_lossy();
-		index.flatten(Path::new(path.as_ref()))
+		index.flatten_page(
+			Path::new(path.as_ref()),
+			page.offset.unwrap_or(0),
+			page.limit,
+			flatten_options.order.unwrap_or_default(),
+		)
 	})
 	.await?;
-	Ok(Json(songs))
+	Ok(songs_response(songs, &options))
 }
 
 #[get("/random")]
@@ -509,12 +610,34 @@ async fn recent(index: Data<Index>, _auth: Auth) -> Result<Json<Vec<index::Direc
 	Ok(Json(result))
 }
 
+#[get("/random/songs")]
+async fn random_songs(
+	index: Data<Index>,
+	_auth: Auth,
+	options: web::Query<dto::SongOptions>,
+) -> Result<HttpResponse, APIError> {
+	let result = block(move || index.get_random_songs(20)).await?;
+	Ok(songs_response(result, &options))
+}
+
+#[get("/recent/songs")]
+async fn recent_songs(
+	index: Data<Index>,
+	_auth: Auth,
+	options: web::Query<dto::SongOptions>,
+) -> Result<HttpResponse, APIError> {
+	let result = block(move || index.get_recent_songs(20)).await?;
+	Ok(songs_response(result, &options))
+}
+
 #[get("/search")]
 async fn search_root(
 	index: Data<Index>,
 	_auth: Auth,
+	options: web::Query<dto::SearchOptions>,
 ) -> Result<Json<Vec<index::CollectionFile>>, APIError> {
-	let result = block(move || index.search("")).await?;
+	let options: index::SearchOptions = options.into_inner().into();
+	let result = block(move || index.search("", &options)).await?;
 	Ok(Json(result))
 }
 
@@ -523,11 +646,37 @@ async fn search(
 	index: Data<Index>,
 	_auth: Auth,
 	query: web::Path<String>,
+	options: web::Query<dto::SearchOptions>,
 ) -> Result<Json<Vec<index::CollectionFile>>, APIError> {
-	let result = block(move || index.search(&query)).await?;
+	let options: index::SearchOptions = options.into_inner().into();
+	let result = block(move || index.search(&query, &options)).await?;
+	Ok(Json(result))
+}
+
+#[get("/search_with_matches/{query:.*}")]
+async fn search_with_matches(
+	index: Data<Index>,
+	_auth: Auth,
+	query: web::Path<String>,
+	options: web::Query<dto::SearchOptions>,
+) -> Result<Json<Vec<index::SearchResult>>, APIError> {
+	let options: index::SearchOptions = options.into_inner().into();
+	let result = block(move || index.search_with_matches(&query, &options)).await?;
 	Ok(Json(result))
 }
 
+#[get("/search_count/{query:.*}")]
+async fn search_count(
+	index: Data<Index>,
+	_auth: Auth,
+	query: web::Path<String>,
+	options: web::Query<dto::SearchOptions>,
+) -> Result<Json<dto::SearchCount>, APIError> {
+	let options: index::SearchOptions = options.into_inner().into();
+	let count = block(move || index.search_count(&query, &options)).await?;
+	Ok(Json(dto::SearchCount { count }))
+}
+
 #[get("/audio/{path:.*}")]
 async fn get_audio(
 	vfs_manager: Data<vfs::Manager>,
@@ -555,21 +704,63 @@ async fn get_thumbnail(
 ) -> Result<MediaFile, APIError> {
 	let options = thumbnail::Options::from(options_input.0);
 
-	let thumbnail_path = block(move || -> Result<PathBuf, APIError> {
+	let (thumbnail_path, thumbnail_file) = block(move || -> Result<_, APIError> {
 		let vfs = vfs_manager.get_vfs()?;
 		let path = percent_decode_str(&path).decode_utf8_lossy();
 		let image_path = vfs.virtual_to_real(Path::new(path.as_ref()))?;
-		thumbnails_manager
-			.get_thumbnail(&image_path, &options)
-			.map_err(|e| e.into())
+		Ok(thumbnails_manager.open_thumbnail(&image_path, &options)?)
 	})
 	.await?;
 
-	let named_file = NamedFile::open(thumbnail_path).map_err(|_| APIError::ThumbnailFileIOError)?;
+	// The file is already open, so pruning the cache meanwhile cannot fail the response.
+	let named_file = NamedFile::from_file(thumbnail_file, thumbnail_path)
+		.map_err(|_| APIError::ThumbnailFileIOError)?;
 
 	Ok(MediaFile::new(named_file))
 }
 
+#[post("/thumbnails")]
+async fn generate_thumbnails(
+	vfs_manager: Data<vfs::Manager>,
+	thumbnails_manager: Data<thumbnail::Manager>,
+	_admin_rights: AdminRights,
+	options_input: web::Query<dto::ThumbnailOptions>,
+	input: Json<dto::GenerateThumbnailsInput>,
+) -> Result<Json<Vec<dto::GenerateThumbnailsResult>>, APIError> {
+	let options = thumbnail::Options::from(options_input.0);
+	let paths = input.into_inner().paths;
+
+	let results = block(
+		move || -> Result<Vec<dto::GenerateThumbnailsResult>, APIError> {
+			let vfs = vfs_manager.get_vfs()?;
+			let mut errors: Vec<Option<String>> = vec![None; paths.len()];
+			let mut image_paths = Vec::new();
+			let mut indices = Vec::new();
+			for (index, path) in paths.iter().enumerate() {
+				match vfs.virtual_to_real(Path::new(path)) {
+					Ok(image_path) => {
+						image_paths.push(image_path);
+						indices.push(index);
+					}
+					Err(e) => errors[index] = Some(e.to_string()),
+				}
+			}
+			let generated = thumbnails_manager.generate_thumbnails(&image_paths, &options);
+			for (index, result) in indices.into_iter().zip(generated) {
+				errors[index] = result.err().map(|e| e.to_string());
+			}
+			Ok(paths
+				.into_iter()
+				.zip(errors)
+				.map(|(path, error)| dto::GenerateThumbnailsResult { path, error })
+				.collect())
+		},
+	)
+	.await?;
+
+	Ok(Json(results))
+}
+
 #[get("/playlists")]
 async fn list_playlists(
 	playlist_manager: Data<playlist::Manager>,
@@ -584,17 +775,106 @@ async fn list_playlists(
 	Ok(Json(playlists))
 }
 
+#[get("/playlists/search/{query:.*}")]
+async fn search_playlists(
+	playlist_manager: Data<playlist::Manager>,
+	auth: Auth,
+	query: web::Path<String>,
+) -> Result<Json<Vec<dto::ListPlaylistsEntry>>, APIError> {
+	let playlist_names =
+		block(move || playlist_manager.search_playlists(&auth.username, &query)).await?;
+	let playlists: Vec<dto::ListPlaylistsEntry> = playlist_names
+		.into_iter()
+		.map(|p| dto::ListPlaylistsEntry { name: p })
+		.collect();
+
+	Ok(Json(playlists))
+}
+
+#[get("/playlists/containing/{path:.*}")]
+async fn playlists_containing(
+	playlist_manager: Data<playlist::Manager>,
+	auth: Auth,
+	path: web::Path<String>,
+) -> Result<Json<Vec<dto::ListPlaylistsEntry>>, APIError> {
+	let playlist_names = block(move || {
+		let path = percent_decode_str(&path).decode_utf8_lossy();
+		playlist_manager.playlists_containing(&auth.username, Path::new(path.as_ref()))
+	})
+	.await?;
+	let playlists: Vec<dto::ListPlaylistsEntry> = playlist_names
+		.into_iter()
+		.map(|p| dto::ListPlaylistsEntry { name: p })
+		.collect();
+
+	Ok(Json(playlists))
+}
+
 #[put("/playlist/{name}")]
 async fn save_playlist(
+	playlist_manager: Data<playlist::Manager>,
+	auth: Auth,
+	name: web::Path<String>,
+	options: web::Query<dto::SavePlaylistOptions>,
+	playlist: Json<dto::SavePlaylistInput>,
+) -> Result<HttpResponse, APIError> {
+	block(move || {
+		playlist_manager.save_playlist_with_dedupe(
+			&name,
+			&auth.username,
+			&playlist.tracks,
+			options.dedupe,
+		)
+	})
+	.await?;
+	Ok(HttpResponse::new(StatusCode::OK))
+}
+
+#[post("/playlist/{name}/songs")]
+async fn append_to_playlist(
 	playlist_manager: Data<playlist::Manager>,
 	auth: Auth,
 	name: web::Path<String>,
 	playlist: Json<dto::SavePlaylistInput>,
 ) -> Result<HttpResponse, APIError> {
-	block(move || playlist_manager.save_playlist(&name, &auth.username, &playlist.tracks)).await?;
+	block(move || playlist_manager.append_to_playlist(&name, &auth.username, &playlist.tracks))
+		.await?;
+	Ok(HttpResponse::new(StatusCode::OK))
+}
+
+#[delete("/playlist/{name}/songs")]
+async fn remove_from_playlist(
+	playlist_manager: Data<playlist::Manager>,
+	auth: Auth,
+	name: web::Path<String>,
+	removal: Json<dto::RemoveFromPlaylistInput>,
+) -> Result<HttpResponse, APIError> {
+	block(move || playlist_manager.remove_from_playlist(&name, &auth.username, &removal.indices))
+		.await?;
 	Ok(HttpResponse::new(StatusCode::OK))
 }
 
+#[post("/playlist/{name}/rename")]
+async fn rename_playlist(
+	playlist_manager: Data<playlist::Manager>,
+	auth: Auth,
+	name: web::Path<String>,
+	rename: Json<dto::RenamePlaylistInput>,
+) -> Result<HttpResponse, APIError> {
+	block(move || playlist_manager.rename_playlist(&name, &rename.name, &auth.username)).await?;
+	Ok(HttpResponse::new(StatusCode::OK))
+}
+
+#[get("/playlist/{name}/stats")]
+async fn get_playlist_stats(
+	playlist_manager: Data<playlist::Manager>,
+	auth: Auth,
+	name: web::Path<String>,
+) -> Result<Json<playlist::PlaylistStats>, APIError> {
+	let stats = block(move || playlist_manager.playlist_stats(&name, &auth.username)).await?;
+	Ok(Json(stats))
+}
+
 #[get("/playlist/{name}")]
 async fn read_playlist(
 	playlist_manager: Data<playlist::Manager>,
@@ -611,7 +891,11 @@ async fn export_playlist_m3u(
 	auth: Auth,
 	exchange: web::Query<playlist::PlaylistExport>,
 ) -> Result<HttpResponse, APIError> {
-	let download_file_name = format!("{:?}.m3u", exchange.name);
+	let download_file_name = format!(
+		"{:?}.{}",
+		exchange.name,
+		exchange.kind.unwrap_or_default().extension()
+	);
 	let buffer: String =
 		block(move || playlist_manager.export_playlist(&auth.username, exchange.into_inner()))
 			.await?;
@@ -624,21 +908,36 @@ async fn export_playlist_m3u(
 		.body(buffer))
 }
 
-#[put("/exchange/playlist")]
-async fn import_playlist_m3u(
-	_playlist_manager: Data<playlist::Manager>,
+#[post("/exchange/songs")]
+async fn export_songs(
 	_auth: Auth,
-	exchange: web::Query<playlist::PlaylistImport>,
-	// playlist: Json<dto::SavePlaylistInput>,
-	_playlist: String,
+	options: web::Query<dto::ExportSongsOptions>,
+	songs: Json<Vec<index::Song>>,
 ) -> Result<HttpResponse, APIError> {
+	let kind = options.into_inner().kind.unwrap_or_default();
+	let download_file_name = format!("queue.{}", kind.extension());
+	let buffer = playlist::serialize_playlist(&songs, kind)?;
 	Ok(HttpResponse::Ok()
 		.content_type("application/force-download")
 		.insert_header(ContentDisposition {
 			disposition: DispositionType::Attachment,
-			parameters: vec![DispositionParam::Filename(exchange.name.clone())],
+			parameters: vec![DispositionParam::Filename(download_file_name)],
 		})
-		.body("hello world"))
+		.body(buffer))
+}
+
+#[put("/exchange/playlist")]
+async fn import_playlist_m3u(
+	playlist_manager: Data<playlist::Manager>,
+	auth: Auth,
+	exchange: web::Query<playlist::PlaylistImport>,
+	playlist: String,
+) -> Result<HttpResponse, APIError> {
+	block(move || {
+		playlist_manager.import_playlist(&auth.username, exchange.into_inner(), &playlist)
+	})
+	.await?;
+	Ok(HttpResponse::new(StatusCode::OK))
 }
 
 #[delete("/playlist/{name}")]
@@ -758,31 +1057,223 @@ fn make_error_response(err: String) -> HttpResponse {
 		.body(err);
 }
 
+// Names the RJ voicing an announcement, for responses that only carry the announcement itself.
+const RJ_HOST_HEADER: &str = "X-Polaris-RJ-Host";
+
+fn with_rj_host(mut builder: HttpResponseBuilder, host: Option<String>) -> HttpResponseBuilder {
+	if let Some(host) = host.and_then(|h| HeaderValue::from_str(&h).ok()) {
+		builder.insert_header((RJ_HOST_HEADER, host));
+	}
+	builder
+}
+
+// Unless the request says otherwise, announcements follow the user's preference and,
+// without one, the enable_by_default setting.
+fn with_user_preference(
+	index: &Index,
+	settings_manager: &settings::Manager,
+	username: &str,
+	mut request: index::RjRequest,
+) -> index::RjRequest {
+	if request.announce.is_none() {
+		let preference = settings_manager
+			.get_rj_user_enabled(username)
+			.unwrap_or_else(|e| {
+				log::error!("Could not read RJ preference of {}: {}", username, e);
+				None
+			});
+		request.announce = Some(
+			preference.unwrap_or_else(|| index.rj_manager.read().unwrap().is_enabled_by_default()),
+		);
+	}
+	request
+}
+
 #[get("/rj/songs")]
 async fn get_announcement(
+	index: Data<Index>,
+	settings_manager: Data<settings::Manager>,
+	auth: Auth,
+	announce: web::Query<index::RjRequest>,
+) -> HttpResponse {
+	let rj_manager = index.rj_manager.clone();
+	let res = my_block(move || {
+		let request = with_user_preference(
+			&index,
+			&settings_manager,
+			&auth.username,
+			announce.into_inner(),
+		);
+		rj::get_announcement_packet(index.as_ref(), request)
+	})
+	.await;
+	let script = match res {
+		Ok((_, script)) if script.is_silent() => return HttpResponse::new(StatusCode::NO_CONTENT),
+		Ok((_, script)) => script,
+		Err(e) => return make_error_response(e.to_string()),
+	};
+	let packet = script.packet;
+	// The lock is released before synthesis so that settings can be updated meanwhile.
+	let synthesizer = rj_manager.read().unwrap().synthesizer();
+	// Audio is forwarded to the client while it is being synthesized.
+	let speech = web::block(move || synthesizer.get_speech_stream(&packet)).await;
+	match speech {
+		Ok(Ok((content_type, reader))) => {
+			with_rj_host(HttpResponse::build(StatusCode::OK), script.host)
+				.content_type(content_type)
+				.streaming(speech_chunks(reader))
+		}
+		Ok(Err(e)) => make_error_response(e.to_string()),
+		Err(_) => APIError::Internal.error_response(),
+	}
+}
+
+#[post("/rj/song")]
+async fn get_song_announcement(
 	index: Data<Index>,
 	_auth: Auth,
+	input: Json<dto::RjSongAnnouncement>,
+) -> HttpResponse {
+	let input = input.into_inner();
+	// The lock is released before synthesis so that settings can be updated meanwhile.
+	let res = {
+		let rj_manager = index.rj_manager.read().unwrap();
+		rj::get_song_announcement_script(&rj_manager, &input.song, input.present.unwrap_or(true))
+			.map(|script| (rj_manager.synthesizer(), script))
+	};
+	let (synthesizer, script) = match res {
+		Ok(res) => res,
+		Err(e) => return make_error_response(e.to_string()),
+	};
+	let packet = script.packet;
+	let speech = web::block(move || synthesizer.get_speech_stream(&packet)).await;
+	match speech {
+		Ok(Ok((content_type, reader))) => {
+			with_rj_host(HttpResponse::build(StatusCode::OK), script.host)
+				.content_type(content_type)
+				.streaming(speech_chunks(reader))
+		}
+		Ok(Err(e)) => make_error_response(e.to_string()),
+		Err(_) => APIError::Internal.error_response(),
+	}
+}
+
+const SPEECH_CHUNK_SIZE: usize = 16 * 1024;
+
+fn speech_chunks(
+	reader: Box<dyn Read + Send>,
+) -> impl Stream<Item = Result<Bytes, actix_web::Error>> {
+	stream::unfold(Some(reader), |reader| async move {
+		let mut reader = reader?;
+		let chunk = web::block(move || {
+			let mut buffer = vec![0; SPEECH_CHUNK_SIZE];
+			let size = reader.read(&mut buffer)?;
+			buffer.truncate(size);
+			Ok::<_, std::io::Error>((reader, buffer))
+		})
+		.await;
+		match chunk {
+			Ok(Ok((_, buffer))) if buffer.is_empty() => None,
+			Ok(Ok((reader, buffer))) => Some((Ok(Bytes::from(buffer)), Some(reader))),
+			Ok(Err(e)) => Some((Err(ErrorInternalServerError(e)), None)),
+			Err(e) => Some((Err(ErrorInternalServerError(e)), None)),
+		}
+	})
+}
+
+#[get("/rj/packet")]
+async fn get_announcement_packet(
+	index: Data<Index>,
+	settings_manager: Data<settings::Manager>,
+	auth: Auth,
 	announce: web::Query<index::RjRequest>,
 ) -> HttpResponse {
-	let res = my_block(move || rj::get_announcement(index.as_ref(), announce.into_inner())).await;
-	if res.is_err() {
-		return make_error_response(res.expect_err("Memory corruption").to_string());
+	let res = my_block(move || {
+		let request = with_user_preference(
+			&index,
+			&settings_manager,
+			&auth.username,
+			announce.into_inner(),
+		);
+		rj::get_announcement_packet(index.as_ref(), request)
+	})
+	.await;
+	match res {
+		Ok((_, script)) if script.is_silent() => HttpResponse::new(StatusCode::NO_CONTENT),
+		Ok((content_type, script)) => {
+			with_rj_host(HttpResponse::build(StatusCode::OK), script.host)
+				.content_type(content_type)
+				.body(script.packet)
+		}
+		Err(e) => make_error_response(e.to_string()),
 	}
-	let (content_type, buffer) = res.unwrap();
-	HttpResponse::build(StatusCode::OK)
-		.content_type(content_type)
-		.body(buffer)
 }
 
+#[get("/rj/announcement")]
+async fn get_announcement_with_text(
+	index: Data<Index>,
+	settings_manager: Data<settings::Manager>,
+	auth: Auth,
+	announce: web::Query<index::RjRequest>,
+) -> HttpResponse {
+	let rj_manager = index.rj_manager.clone();
+	let res = my_block(move || {
+		let request = with_user_preference(
+			&index,
+			&settings_manager,
+			&auth.username,
+			announce.into_inner(),
+		);
+		rj::get_announcement_script(index.as_ref(), request)
+	})
+	.await;
+	let script = match res {
+		Ok(script) if script.is_silent() => return HttpResponse::new(StatusCode::NO_CONTENT),
+		Ok(script) => script,
+		Err(e) => return make_error_response(e.to_string()),
+	};
+	let speech = rj_manager.read().unwrap().get_speech_async(script.packet);
+	match speech.await {
+		Ok((content_type, buffer)) => {
+			HttpResponse::build(StatusCode::OK).json(dto::RjAnnouncement {
+				text: script.text,
+				content_type,
+				audio: base64::encode(buffer),
+				host: script.host,
+			})
+		}
+		Err(e) => make_error_response(e.to_string()),
+	}
+}
+
+// enable_by_default is reported as the requesting user's own preference when they have one.
 #[get("/rj/user_settings")]
 async fn get_rj_user_settings(
 	settings_manager: Data<settings::Manager>,
-	_auth: Auth,
+	auth: Auth,
 ) -> Result<Json<rj::UserSettings>, APIError> {
-	let settings = block(move || settings_manager.get_rj_user_settings()).await?;
+	let settings = block(move || {
+		let mut settings = settings_manager.get_rj_user_settings()?;
+		if let Some(enabled) = settings_manager.get_rj_user_enabled(&auth.username)? {
+			settings.enable_by_default = Some(enabled);
+		}
+		Ok::<_, settings::Error>(settings)
+	})
+	.await?;
 	Ok(Json(settings))
 }
 
+#[put("/rj/user_enabled")]
+async fn put_rj_user_enabled(
+	settings_manager: Data<settings::Manager>,
+	auth: Auth,
+	user_enabled: Json<dto::RjUserEnabled>,
+) -> Result<HttpResponse, APIError> {
+	block(move || settings_manager.put_rj_user_enabled(&auth.username, user_enabled.enabled))
+		.await?;
+	Ok(HttpResponse::new(StatusCode::OK))
+}
+
 fn update_user_settings(
 	index: Data<Index>,
 	settings_manager: Data<settings::Manager>,
@@ -815,6 +1306,79 @@ async fn put_rj_user_settings(
 		.body("dancing with wolves error")
 }
 
+// Rebuilds the RJ manager from the stored settings, as on startup. The caller holds the lock
+// so that the settings cannot change in between.
+fn reload_rj_manager(
+	rj_manager: &mut rj::Manager,
+	settings_manager: &settings::Manager,
+) -> Result<(), APIError> {
+	*rj_manager = rj::Manager::create(
+		settings_manager.get_rj_admin_settings()?,
+		settings_manager.get_rj_user_settings()?,
+	)?;
+	Ok(())
+}
+
+// Returns the settings that were replaced so that they can be put back.
+#[delete("/rj/user_settings")]
+async fn reset_rj_user_settings(
+	_admin_rights: AdminRights,
+	index: Data<Index>,
+	settings_manager: Data<settings::Manager>,
+) -> Result<Json<rj::UserSettings>, APIError> {
+	let previous = block(move || -> Result<rj::UserSettings, APIError> {
+		let mut rj_manager = index.rj_manager.write().unwrap();
+		let previous = settings_manager.reset_rj_user_settings()?;
+		reload_rj_manager(&mut rj_manager, &settings_manager).map_err(|e| {
+			if let Err(e) = settings_manager.put_rj_user_settings(&previous) {
+				log::error!("Could not restore RJ user settings: {}", e);
+			}
+			e
+		})?;
+		Ok(previous)
+	})
+	.await?;
+	Ok(Json(previous))
+}
+
+#[get("/rj/hosts/{name}")]
+async fn get_rj_host(
+	settings_manager: Data<settings::Manager>,
+	_auth: Auth,
+	name: web::Path<String>,
+) -> Result<Json<rj::Person>, APIError> {
+	let host = block(move || settings_manager.get_rj_host(&name)).await?;
+	Ok(Json(host))
+}
+
+#[get("/rj/default_host")]
+async fn get_rj_default_host(
+	settings_manager: Data<settings::Manager>,
+	_auth: Auth,
+) -> Result<Json<Option<rj::Person>>, APIError> {
+	let host = block(move || settings_manager.get_rj_default_host()).await?;
+	Ok(Json(host))
+}
+
+#[put("/rj/default_host/{name}")]
+async fn put_rj_default_host(
+	_admin_rights: AdminRights,
+	index: Data<Index>,
+	settings_manager: Data<settings::Manager>,
+	name: web::Path<String>,
+) -> Result<HttpResponse, APIError> {
+	block(move || -> Result<(), APIError> {
+		let mut rj_manager = index.rj_manager.write().unwrap();
+		let previous = rj_manager.set_default_host(Some(name.to_string()))?;
+		settings_manager.set_rj_default_host(&name).map_err(|op| {
+			rj_manager.set_default_host(previous).unwrap();
+			op.into()
+		})
+	})
+	.await?;
+	Ok(HttpResponse::new(StatusCode::OK))
+}
+
 #[get("/rj/admin_settings")]
 async fn get_rj_admin_settings(
 	settings_manager: Data<settings::Manager>,
@@ -824,6 +1388,46 @@ async fn get_rj_admin_settings(
 	Ok(Json(settings))
 }
 
+#[get("/rj/diagnostics")]
+async fn get_rj_diagnostics(
+	index: Data<Index>,
+	_admin_rights: AdminRights,
+) -> Result<Json<rj::RjDiagnostics>, APIError> {
+	let diagnostics = index.rj_manager.read().unwrap().diagnostics();
+	Ok(Json(diagnostics))
+}
+
+#[post("/rj/test")]
+async fn test_rj_synthesis(index: Data<Index>, _admin_rights: AdminRights) -> HttpResponse {
+	let (synthesizer, packet) = {
+		let rj_manager = index.rj_manager.read().unwrap();
+		(rj_manager.synthesizer(), rj_manager.test_packet())
+	};
+	let speech = web::block(move || synthesizer.test_synthesis(&packet)).await;
+	match speech {
+		Ok(Ok((content_type, buffer))) => HttpResponse::build(StatusCode::OK)
+			.content_type(content_type)
+			.body(buffer),
+		Ok(Err(e)) => make_error_response(e.to_string()),
+		Err(_) => APIError::Internal.error_response(),
+	}
+}
+
+#[post("/rj/preview")]
+async fn preview_rj_announcement(
+	index: Data<Index>,
+	_admin_rights: AdminRights,
+	input: Json<dto::RjSongAnnouncement>,
+) -> Json<dto::RjAnnouncementPreview> {
+	let rj_manager = index.rj_manager.read().unwrap();
+	let text = rj_manager.preview_announcement(&input.song, input.present.unwrap_or(true));
+	let missing_fields = rj_manager.missing_required_fields(&input.song);
+	Json(dto::RjAnnouncementPreview {
+		text,
+		missing_fields,
+	})
+}
+
 fn update_admin_settings(
 	index: Data<Index>,
 	settings_manager: Data<settings::Manager>,
@@ -839,6 +1443,28 @@ fn update_admin_settings(
 		})
 }
 
+// Returns the settings that were replaced so that they can be put back.
+#[delete("/rj/admin_settings")]
+async fn reset_rj_admin_settings(
+	_admin_rights: AdminRights,
+	index: Data<Index>,
+	settings_manager: Data<settings::Manager>,
+) -> Result<Json<rj::AdminSettings>, APIError> {
+	let previous = block(move || -> Result<rj::AdminSettings, APIError> {
+		let mut rj_manager = index.rj_manager.write().unwrap();
+		let previous = settings_manager.reset_rj_admin_settings()?;
+		reload_rj_manager(&mut rj_manager, &settings_manager).map_err(|e| {
+			if let Err(e) = settings_manager.put_rj_admin_settings(&previous) {
+				log::error!("Could not restore RJ admin settings: {}", e);
+			}
+			e
+		})?;
+		Ok(previous)
+	})
+	.await?;
+	Ok(Json(previous))
+}
+
 #[put("/rj/admin_settings")]
 async fn put_rj_admin_settings(
 	_admin_rights: AdminRights,
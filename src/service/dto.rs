@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-use crate::app::{config, ddns, settings, thumbnail, user, vfs};
+use crate::app::{config, ddns, index, playlist, settings, thumbnail, user, vfs};
 use std::convert::From;
 
 pub const API_MAJOR_VERSION: i32 = 7;
@@ -39,6 +39,9 @@ pub struct AuthQueryParameters {
 pub struct ThumbnailOptions {
 	pub size: Option<ThumbnailSize>,
 	pub pad: Option<bool>,
+	pub format: Option<thumbnail::Format>,
+	pub quality: Option<u8>,
+	pub background: Option<ThumbnailBackground>,
 }
 
 impl From<ThumbnailOptions> for thumbnail::Options {
@@ -46,10 +49,86 @@ impl From<ThumbnailOptions> for thumbnail::Options {
 		let mut options = thumbnail::Options::default();
 		options.max_dimension = dto.size.map_or(options.max_dimension, Into::into);
 		options.pad_to_square = dto.pad.unwrap_or(options.pad_to_square);
+		options.format = dto.format.unwrap_or(options.format);
+		options.quality = dto.quality.unwrap_or(options.quality);
+		options.background = dto.background.map_or(options.background, Into::into);
 		options
 	}
 }
 
+#[derive(Default, Serialize, Deserialize)]
+pub struct SearchOptions {
+	pub kind: Option<index::SearchKind>,
+	pub max_results: Option<i64>,
+	pub fold_accents: Option<bool>,
+	pub fuzzy: Option<bool>,
+}
+
+impl From<SearchOptions> for index::SearchOptions {
+	fn from(dto: SearchOptions) -> Self {
+		Self {
+			kind: dto.kind.unwrap_or_default(),
+			max_results: dto.max_results.or(Some(index::DEFAULT_SEARCH_MAX_RESULTS)),
+			fold_accents: dto.fold_accents.unwrap_or_default(),
+			fuzzy: dto.fuzzy.unwrap_or_default(),
+		}
+	}
+}
+
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct SearchCount {
+	pub count: i64,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct BrowseOptions {
+	pub order: Option<index::BrowseOrder>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct FlattenOptions {
+	pub order: Option<index::SongOrder>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SongOptions {
+	pub include_id: Option<bool>,
+}
+
+/// Song along with its database id. Ids are derived from song paths, so a song file
+/// gets a new id when it is renamed or moved.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SongWithId {
+	pub id: i32,
+	#[serde(flatten)]
+	pub song: index::Song,
+}
+
+impl From<index::Song> for SongWithId {
+	fn from(song: index::Song) -> Self {
+		Self {
+			id: song.id(),
+			song,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CancelIndexOptions {
+	pub discard_pending: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenerateThumbnailsInput {
+	pub paths: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GenerateThumbnailsResult {
+	pub path: String,
+	pub error: Option<String>,
+}
+
 #[derive(Clone, Copy, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum ThumbnailSize {
@@ -69,16 +148,61 @@ impl Into<Option<u32>> for ThumbnailSize {
 	}
 }
 
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThumbnailBackground {
+	White,
+	Black,
+	Transparent,
+}
+
+#[allow(clippy::from_over_into)]
+impl Into<[u8; 4]> for ThumbnailBackground {
+	fn into(self) -> [u8; 4] {
+		match self {
+			Self::White => [255, 255, 255, 255],
+			Self::Black => [0, 0, 0, 255],
+			Self::Transparent => [0, 0, 0, 0],
+		}
+	}
+}
+
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ListPlaylistsEntry {
 	pub name: String,
 }
 
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Pagination {
+	pub offset: Option<u32>,
+	pub limit: Option<u32>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SavePlaylistInput {
 	pub tracks: Vec<String>,
 }
 
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SavePlaylistOptions {
+	pub dedupe: Option<playlist::Dedupe>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ExportSongsOptions {
+	pub kind: Option<playlist::PlaylistType>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RenamePlaylistInput {
+	pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoveFromPlaylistInput {
+	pub indices: Vec<usize>,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct LastFMLink {
 	pub auth_token: String, // user::AuthToken emitted by Polaris, valid for LastFMLink scope
@@ -205,6 +329,7 @@ impl From<Config> for config::Config {
 pub struct NewSettings {
 	pub album_art_pattern: Option<String>,
 	pub reindex_every_n_seconds: Option<i32>,
+	pub index_insert_buffer_size: Option<i32>,
 }
 
 impl From<NewSettings> for settings::NewSettings {
@@ -212,6 +337,7 @@ impl From<NewSettings> for settings::NewSettings {
 		Self {
 			album_art_pattern: s.album_art_pattern,
 			reindex_every_n_seconds: s.reindex_every_n_seconds,
+			index_insert_buffer_size: s.index_insert_buffer_size,
 		}
 	}
 }
@@ -220,6 +346,7 @@ impl From<NewSettings> for settings::NewSettings {
 pub struct Settings {
 	pub album_art_pattern: String,
 	pub reindex_every_n_seconds: i32,
+	pub index_insert_buffer_size: Option<i32>,
 }
 
 impl From<settings::Settings> for Settings {
@@ -227,9 +354,40 @@ impl From<settings::Settings> for Settings {
 		Self {
 			album_art_pattern: s.index_album_art_pattern,
 			reindex_every_n_seconds: s.index_sleep_duration_seconds,
+			index_insert_buffer_size: s.index_insert_buffer_size,
 		}
 	}
 }
 
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RjUserEnabled {
+	/// None follows the enable_by_default setting.
+	pub enabled: Option<bool>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RjSongAnnouncement {
+	pub song: index::Song,
+	/// Announces the song as coming up rather than as just played. Defaults to true.
+	pub present: Option<bool>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RjAnnouncementPreview {
+	pub text: String,
+	/// Fields the script requires that the song lacks, so it cannot be announced properly.
+	pub missing_fields: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RjAnnouncement {
+	pub text: String,
+	pub content_type: String,
+	/// Base64 encoded audio
+	pub audio: String,
+	/// Name of the RJ voicing the announcement
+	pub host: Option<String>,
+}
+
 // TODO: Preferences, CollectionFile, Song and Directory should have dto types
 // TODO Song dto type should skip `None` values when serializing, to lower payload sizes by a lot
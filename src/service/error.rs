@@ -39,8 +39,12 @@ pub enum APIError {
 	LastFMLinkContentEncodingError,
 	#[error("Playlist not found:{0}")]
 	PlaylistNotFound(String),
+	#[error("Playlist already exists:{0}")]
+	PlaylistAlreadyExists(String),
 	#[error("Failed to parse:{0}")]
 	ParseFailed(String),
+	#[error("Invalid playlist:{0}")]
+	InvalidPlaylist(String),
 	#[error("Could send Now Playing update to last.fm:\n\n{0}")]
 	LastFMNowPlaying(rustfm_scrobble::ScrobblerError),
 	#[error("Could emit scrobble with last.fm:\n\n{0}")]
@@ -59,6 +63,8 @@ pub enum APIError {
 	Settings(settings::Error),
 	#[error("Song not found")]
 	SongMetadataNotFound,
+	#[error("Could not decode thumbnail from APE tag in `{0}`:\n\n{1}")]
+	ThumbnailApeDecoding(PathBuf, ape::Error),
 	#[error("Could not decode thumbnail from flac file `{0}`:\n\n{1}")]
 	ThumbnailFlacDecoding(PathBuf, metaflac::Error),
 	#[error("Thumbnail file could not be opened")]
@@ -69,6 +75,12 @@ pub enum APIError {
 	ThumbnailImageDecoding(PathBuf, image::error::ImageError),
 	#[error("Could not decode thumbnail from mp4 file `{0}`:\n\n{1}")]
 	ThumbnailMp4Decoding(PathBuf, mp4ameta::Error),
+	#[error("Could not decode thumbnail from opus file `{0}`:\n\n{1}")]
+	ThumbnailOpusDecoding(PathBuf, opus_headers::ParseError),
+	#[error("Could not decode thumbnail picture block in `{0}`")]
+	ThumbnailPictureBlockDecoding(PathBuf),
+	#[error("Could not decode thumbnail from vorbis file `{0}`:\n\n{1}")]
+	ThumbnailVorbisDecoding(PathBuf, lewton::VorbisError),
 	#[error("Toml deserialization error:\n\n{0}")]
 	TomlDeserialization(toml::de::Error),
 	#[error("Unsupported thumbnail format: `{0}`")]
@@ -77,6 +89,8 @@ pub enum APIError {
 	UserNotFound,
 	#[error("Path not found in virtual filesystem")]
 	VFSPathNotFound,
+	#[error("RJ host not found: {0}")]
+	RjHostNotFound(String),
 }
 
 impl From<config::Error> for APIError {
@@ -96,10 +110,12 @@ impl From<playlist::Error> for APIError {
 	fn from(error: playlist::Error) -> APIError {
 		match error {
 			playlist::Error::PlaylistNotFound(name) => APIError::PlaylistNotFound(name),
+			playlist::Error::PlaylistAlreadyExists(name) => APIError::PlaylistAlreadyExists(name),
 			playlist::Error::Database(e) => APIError::Database(e),
 			playlist::Error::DatabaseConnection(e) => e.into(),
 			playlist::Error::UserNotFound => APIError::UserNotFound,
 			playlist::Error::Vfs(e) => e.into(),
+			playlist::Error::InvalidPlaylist(message) => APIError::InvalidPlaylist(message),
 		}
 	}
 }
@@ -130,7 +146,10 @@ impl From<settings::Error> for APIError {
 			settings::Error::AuthenticationSecretInvalid => APIError::Settings(error),
 			settings::Error::MiscSettingsNotFound => APIError::Settings(error),
 			settings::Error::IndexAlbumArtPatternInvalid => APIError::Settings(error),
+			settings::Error::IndexInsertBufferSizeInvalid => APIError::Settings(error),
 			settings::Error::Database(e) => APIError::Database(e),
+			settings::Error::RjHostNotFound(name) => APIError::RjHostNotFound(name),
+			settings::Error::RjScriptInvalid(message) => APIError::ParseFailed(message),
 		}
 	}
 }
@@ -201,12 +220,16 @@ impl From<lastfm::Error> for APIError {
 impl From<thumbnail::Error> for APIError {
 	fn from(error: thumbnail::Error) -> APIError {
 		match error {
+			thumbnail::Error::Ape(p, e) => APIError::ThumbnailApeDecoding(p, e),
 			thumbnail::Error::EmbeddedArtworkNotFound(_) => APIError::EmbeddedArtworkNotFound,
 			thumbnail::Error::Id3(p, e) => APIError::ThumbnailId3Decoding(p, e),
 			thumbnail::Error::Image(p, e) => APIError::ThumbnailImageDecoding(p, e),
 			thumbnail::Error::Io(p, e) => APIError::Io(p, e),
 			thumbnail::Error::Metaflac(p, e) => APIError::ThumbnailFlacDecoding(p, e),
 			thumbnail::Error::Mp4aMeta(p, e) => APIError::ThumbnailMp4Decoding(p, e),
+			thumbnail::Error::Opus(p, e) => APIError::ThumbnailOpusDecoding(p, e),
+			thumbnail::Error::PictureBlock(p) => APIError::ThumbnailPictureBlockDecoding(p),
+			thumbnail::Error::Vorbis(p, e) => APIError::ThumbnailVorbisDecoding(p, e),
 			thumbnail::Error::UnsupportedFormat(f) => APIError::UnsupportedThumbnailFormat(f),
 		}
 	}
@@ -1,7 +1,9 @@
 use http::StatusCode;
+use std::collections::HashSet;
 use std::path::{Path, PathBuf};
 
 use crate::app::index;
+use crate::service::dto;
 use crate::service::test::{add_trailing_slash, constants::*, protocol, ServiceType, TestService};
 use crate::test_name;
 
@@ -58,6 +60,38 @@ fn browse_directory() {
 	assert_eq!(entries.len(), 5);
 }
 
+#[test]
+fn browse_directory_page() {
+	let mut service = ServiceType::new(&test_name!());
+	service.complete_initial_setup();
+	service.login_admin();
+	service.index();
+	service.login();
+
+	let path: PathBuf = [TEST_MOUNT_NAME, "Khemmis", "Hunted"].iter().collect();
+	let request = protocol::browse_page(&path, 1, 2);
+	let response = service.fetch_json::<_, Vec<index::CollectionFile>>(&request);
+	assert_eq!(response.status(), StatusCode::OK);
+	let entries = response.body();
+	assert_eq!(entries.len(), 2);
+}
+
+#[test]
+fn browse_rejects_negative_page() {
+	let mut service = ServiceType::new(&test_name!());
+	service.complete_initial_setup();
+	service.login_admin();
+	service.index();
+	service.login();
+
+	let path: PathBuf = [TEST_MOUNT_NAME, "Khemmis", "Hunted"].iter().collect();
+	for (offset, limit) in [(0, -1), (-1, 2)] {
+		let request = protocol::browse_page(&path, offset, limit);
+		let response = service.fetch(&request);
+		assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+	}
+}
+
 #[test]
 fn browse_bad_directory() {
 	let mut service = ServiceType::new(&test_name!());
@@ -108,6 +142,23 @@ fn flatten_directory() {
 	assert_eq!(entries.len(), TEST_ALL_SONGS_COUNT);
 }
 
+#[test]
+fn flatten_with_ids() {
+	let mut service = ServiceType::new(&test_name!());
+	service.complete_initial_setup();
+	service.login_admin();
+	service.index();
+	service.login();
+
+	let request = protocol::flatten_with_ids(Path::new(TEST_MOUNT_NAME));
+	let response = service.fetch_json::<_, Vec<dto::SongWithId>>(&request);
+	assert_eq!(response.status(), StatusCode::OK);
+	let entries = response.body();
+	assert_eq!(entries.len(), TEST_ALL_SONGS_COUNT);
+	let ids: HashSet<i32> = entries.iter().map(|s| s.id).collect();
+	assert_eq!(ids.len(), TEST_ALL_SONGS_COUNT);
+}
+
 #[test]
 fn flatten_bad_directory() {
 	let mut service = ServiceType::new(&test_name!());
@@ -232,6 +283,46 @@ fn search_with_query() {
 	assert!(result_has_title(results, "Beyond The Door"));
 }
 
+#[test]
+fn search_restricted_to_kind() {
+	let mut service = ServiceType::new(&test_name!());
+	service.complete_initial_setup();
+	service.login_admin();
+	service.index();
+	service.login();
+
+	let request = protocol::search_kind("door", index::SearchKind::Songs);
+	let response = service.fetch_json::<_, Vec<index::CollectionFile>>(&request);
+	let results = response.body();
+	assert_eq!(results.len(), 1);
+	assert!(result_has_title(results, "Beyond The Door"));
+
+	let request = protocol::search_kind("door", index::SearchKind::Directories);
+	let response = service.fetch_json::<_, Vec<index::CollectionFile>>(&request);
+	assert!(response.body().is_empty());
+
+	let request = protocol::search_kind("Tobokegao", index::SearchKind::Songs);
+	let response = service.fetch_json::<_, Vec<index::CollectionFile>>(&request);
+	assert!(response
+		.body()
+		.iter()
+		.all(|f| matches!(f, index::CollectionFile::Song(_))));
+}
+
+#[test]
+fn search_count_golden_path() {
+	let mut service = ServiceType::new(&test_name!());
+	service.complete_initial_setup();
+	service.login_admin();
+	service.index();
+	service.login();
+
+	let request = protocol::search_count("door");
+	let response = service.fetch_json::<_, dto::SearchCount>(&request);
+	assert_eq!(response.status(), StatusCode::OK);
+	assert_eq!(response.body().count, 1);
+}
+
 #[test]
 fn search_extended_tags() {
 	let mut service = ServiceType::new(&test_name!());
@@ -258,7 +349,7 @@ fn search_with_query_one_field() {
 	let request = protocol::search("album:Remixe");
 	let response = service.fetch_json::<_, Vec<index::CollectionFile>>(&request);
 	let results = response.body();
-	assert_eq!(results.len(), 1);
+	assert_eq!(results.len(), 2);
 	assert!(result_has_title(results, "ピクニック (Picnic) (Remix)"));
 }
 
@@ -291,7 +382,7 @@ fn search_with_query_one_year() {
 	let request = protocol::search("year:2016");
 	let response = service.fetch_json::<_, Vec<index::CollectionFile>>(&request);
 	let results = response.body();
-	assert_eq!(results.len(), 13);
+	assert_eq!(results.len(), 16);
 	assert!(result_has_title(results, "ピクニック (Picnic) (Remix)"));
 }
 
@@ -306,6 +397,6 @@ fn search_with_query_year_range() {
 	let request = protocol::search("year:2000-2017");
 	let response = service.fetch_json::<_, Vec<index::CollectionFile>>(&request);
 	let results = response.body();
-	assert_eq!(results.len(), 13);
+	assert_eq!(results.len(), 16);
 	assert!(result_has_title(results, "ピクニック (Picnic) (Remix)"));
 }
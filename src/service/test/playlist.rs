@@ -1,4 +1,5 @@
 use http::StatusCode;
+use std::path::PathBuf;
 
 use crate::app::index;
 use crate::service::dto;
@@ -23,6 +24,78 @@ fn list_playlists_golden_path() {
 	assert_eq!(response.status(), StatusCode::OK);
 }
 
+#[test]
+fn search_playlists_requires_auth() {
+	let mut service = ServiceType::new(&test_name!());
+	let request = protocol::search_playlists("play");
+	let response = service.fetch(&request);
+	assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[test]
+fn search_playlists_golden_path() {
+	let mut service = ServiceType::new(&test_name!());
+	service.complete_initial_setup();
+	service.login();
+
+	let my_playlist = dto::SavePlaylistInput { tracks: Vec::new() };
+	let request = protocol::save_playlist(TEST_PLAYLIST_NAME, my_playlist);
+	let response = service.fetch(&request);
+	assert_eq!(response.status(), StatusCode::OK);
+
+	let request = protocol::search_playlists("play");
+	let response = service.fetch_json::<_, Vec<dto::ListPlaylistsEntry>>(&request);
+	assert_eq!(response.status(), StatusCode::OK);
+	assert_eq!(
+		response.body(),
+		&vec![dto::ListPlaylistsEntry {
+			name: TEST_PLAYLIST_NAME.to_owned()
+		}]
+	);
+
+	let request = protocol::search_playlists("not a playlist");
+	let response = service.fetch_json::<_, Vec<dto::ListPlaylistsEntry>>(&request);
+	assert_eq!(response.status(), StatusCode::OK);
+	assert!(response.body().is_empty());
+}
+
+#[test]
+fn playlists_containing_golden_path() {
+	let mut service = ServiceType::new(&test_name!());
+	service.complete_initial_setup();
+	service.login_admin();
+	service.index();
+	service.login();
+
+	let song: PathBuf = [TEST_MOUNT_NAME, "Khemmis", "Hunted", "02 - Candlelight.mp3"]
+		.iter()
+		.collect();
+	let my_playlist = dto::SavePlaylistInput {
+		tracks: vec![song.to_string_lossy().into_owned()],
+	};
+	let request = protocol::save_playlist(TEST_PLAYLIST_NAME, my_playlist);
+	let response = service.fetch(&request);
+	assert_eq!(response.status(), StatusCode::OK);
+
+	let request = protocol::playlists_containing(&song);
+	let response = service.fetch_json::<_, Vec<dto::ListPlaylistsEntry>>(&request);
+	assert_eq!(response.status(), StatusCode::OK);
+	assert_eq!(
+		response.body(),
+		&vec![dto::ListPlaylistsEntry {
+			name: TEST_PLAYLIST_NAME.to_owned()
+		}]
+	);
+
+	let other_song: PathBuf = [TEST_MOUNT_NAME, "Khemmis", "Hunted", "05 - Hunted.mp3"]
+		.iter()
+		.collect();
+	let request = protocol::playlists_containing(&other_song);
+	let response = service.fetch_json::<_, Vec<dto::ListPlaylistsEntry>>(&request);
+	assert_eq!(response.status(), StatusCode::OK);
+	assert!(response.body().is_empty());
+}
+
 #[test]
 fn save_playlist_requires_auth() {
 	let mut service = ServiceType::new(&test_name!());
@@ -3,7 +3,13 @@ use percent_encoding::{percent_encode, NON_ALPHANUMERIC};
 use std::path::Path;
 
 use crate::service::dto;
-use crate::{app::user, service::dto::ThumbnailSize};
+use crate::{
+	app::{
+		index::{self, SearchKind},
+		rj, user,
+	},
+	service::dto::ThumbnailSize,
+};
 
 pub fn web_index() -> Request<()> {
 	Request::builder()
@@ -155,6 +161,21 @@ pub fn browse(path: &Path) -> Request<()> {
 		.unwrap()
 }
 
+pub fn browse_page(path: &Path, offset: i64, limit: i64) -> Request<()> {
+	let path = path.to_string_lossy();
+	let endpoint = format!(
+		"/api/browse/{}?offset={}&limit={}",
+		url_encode(path.as_ref()),
+		offset,
+		limit
+	);
+	Request::builder()
+		.method(Method::GET)
+		.uri(&endpoint)
+		.body(())
+		.unwrap()
+}
+
 pub fn flatten(path: &Path) -> Request<()> {
 	let path = path.to_string_lossy();
 	let endpoint = format!("/api/flatten/{}", url_encode(path.as_ref()));
@@ -165,6 +186,16 @@ pub fn flatten(path: &Path) -> Request<()> {
 		.unwrap()
 }
 
+pub fn flatten_with_ids(path: &Path) -> Request<()> {
+	let path = path.to_string_lossy();
+	let endpoint = format!("/api/flatten/{}?include_id=true", url_encode(path.as_ref()));
+	Request::builder()
+		.method(Method::GET)
+		.uri(&endpoint)
+		.body(())
+		.unwrap()
+}
+
 pub fn random() -> Request<()> {
 	Request::builder()
 		.method(Method::GET)
@@ -190,6 +221,29 @@ pub fn search(query: &str) -> Request<()> {
 		.unwrap()
 }
 
+pub fn search_kind(query: &str, kind: SearchKind) -> Request<()> {
+	let kind = match kind {
+		SearchKind::All => "all",
+		SearchKind::Songs => "songs",
+		SearchKind::Directories => "directories",
+	};
+	let endpoint = format!("/api/search/{}?kind={}", url_encode(query), kind);
+	Request::builder()
+		.method(Method::GET)
+		.uri(&endpoint)
+		.body(())
+		.unwrap()
+}
+
+pub fn search_count(query: &str) -> Request<()> {
+	let endpoint = format!("/api/search_count/{}", url_encode(query));
+	Request::builder()
+		.method(Method::GET)
+		.uri(&endpoint)
+		.body(())
+		.unwrap()
+}
+
 pub fn audio(path: &Path) -> Request<()> {
 	let path = path.to_string_lossy();
 	let endpoint = format!("/api/audio/{}", url_encode(path.as_ref()));
@@ -241,6 +295,25 @@ pub fn playlists() -> Request<()> {
 		.unwrap()
 }
 
+pub fn search_playlists(query: &str) -> Request<()> {
+	let endpoint = format!("/api/playlists/search/{}", url_encode(query));
+	Request::builder()
+		.method(Method::GET)
+		.uri(&endpoint)
+		.body(())
+		.unwrap()
+}
+
+pub fn playlists_containing(path: &Path) -> Request<()> {
+	let path = path.to_string_lossy();
+	let endpoint = format!("/api/playlists/containing/{}", url_encode(path.as_ref()));
+	Request::builder()
+		.method(Method::GET)
+		.uri(&endpoint)
+		.body(())
+		.unwrap()
+}
+
 pub fn save_playlist(
 	name: &str,
 	playlist: dto::SavePlaylistInput,
@@ -312,6 +385,92 @@ pub fn lastfm_scrobble(path: &Path) -> Request<()> {
 		.unwrap()
 }
 
+pub fn rj_packet() -> Request<()> {
+	Request::builder()
+		.method(Method::GET)
+		.uri("/api/rj/packet")
+		.body(())
+		.unwrap()
+}
+
+pub fn get_rj_user_settings() -> Request<()> {
+	Request::builder()
+		.method(Method::GET)
+		.uri("/api/rj/user_settings")
+		.body(())
+		.unwrap()
+}
+
+pub fn put_rj_user_settings(settings: rj::UserSettings) -> Request<rj::UserSettings> {
+	Request::builder()
+		.method(Method::PUT)
+		.uri("/api/rj/user_settings")
+		.body(settings)
+		.unwrap()
+}
+
+pub fn reset_rj_user_settings() -> Request<()> {
+	Request::builder()
+		.method(Method::DELETE)
+		.uri("/api/rj/user_settings")
+		.body(())
+		.unwrap()
+}
+
+pub fn get_rj_admin_settings() -> Request<()> {
+	Request::builder()
+		.method(Method::GET)
+		.uri("/api/rj/admin_settings")
+		.body(())
+		.unwrap()
+}
+
+pub fn put_rj_admin_settings(settings: rj::AdminSettings) -> Request<rj::AdminSettings> {
+	Request::builder()
+		.method(Method::PUT)
+		.uri("/api/rj/admin_settings")
+		.body(settings)
+		.unwrap()
+}
+
+pub fn reset_rj_admin_settings() -> Request<()> {
+	Request::builder()
+		.method(Method::DELETE)
+		.uri("/api/rj/admin_settings")
+		.body(())
+		.unwrap()
+}
+
+pub fn put_rj_user_enabled(enabled: Option<bool>) -> Request<dto::RjUserEnabled> {
+	Request::builder()
+		.method(Method::PUT)
+		.uri("/api/rj/user_enabled")
+		.body(dto::RjUserEnabled { enabled })
+		.unwrap()
+}
+
+pub fn rj_song_announcement(song: index::Song) -> Request<dto::RjSongAnnouncement> {
+	Request::builder()
+		.method(Method::POST)
+		.uri("/api/rj/song")
+		.body(dto::RjSongAnnouncement {
+			song,
+			present: None,
+		})
+		.unwrap()
+}
+
+pub fn rj_preview(song: index::Song) -> Request<dto::RjSongAnnouncement> {
+	Request::builder()
+		.method(Method::POST)
+		.uri("/api/rj/preview")
+		.body(dto::RjSongAnnouncement {
+			song,
+			present: None,
+		})
+		.unwrap()
+}
+
 fn url_encode(input: &str) -> String {
 	percent_encode(input.as_bytes(), NON_ALPHANUMERIC).to_string()
 }
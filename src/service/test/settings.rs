@@ -1,5 +1,6 @@
 use http::StatusCode;
 
+use crate::app::{index::Song, rj};
 use crate::service::dto::{self, Settings};
 use crate::service::test::{protocol, ServiceType, TestService};
 use crate::test_name;
@@ -61,12 +62,18 @@ fn put_settings_golden_path() {
 	service.complete_initial_setup();
 	service.login_admin();
 
+	let request = protocol::get_settings();
+	let response = service.fetch_json::<_, dto::Settings>(&request);
+	let original = response.body().clone();
+
 	let request = protocol::put_settings(dto::NewSettings {
 		album_art_pattern: Some("test_pattern".to_owned()),
 		reindex_every_n_seconds: Some(31),
+		index_insert_buffer_size: Some(500),
 	});
-	let response = service.fetch(&request);
+	let response = service.fetch_json::<_, dto::Settings>(&request);
 	assert_eq!(response.status(), StatusCode::OK);
+	assert_eq!(response.body(), &original);
 
 	let request = protocol::get_settings();
 	let response = service.fetch_json::<_, dto::Settings>(&request);
@@ -76,6 +83,169 @@ fn put_settings_golden_path() {
 		&Settings {
 			album_art_pattern: "test_pattern".to_owned(),
 			reindex_every_n_seconds: 31,
+			index_insert_buffer_size: Some(500),
 		},
 	);
 }
+
+#[test]
+fn put_rj_user_enabled_requires_auth() {
+	let mut service = ServiceType::new(&test_name!());
+	service.complete_initial_setup();
+	let request = protocol::put_rj_user_enabled(Some(false));
+	let response = service.fetch(&request);
+	assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[test]
+fn rj_user_enabled_is_respected() {
+	let mut service = ServiceType::new(&test_name!());
+	service.complete_initial_setup();
+	service.login();
+
+	let request = protocol::rj_packet();
+	let response = service.fetch(&request);
+	assert_ne!(response.status(), StatusCode::NO_CONTENT);
+
+	let request = protocol::put_rj_user_enabled(Some(false));
+	let response = service.fetch(&request);
+	assert_eq!(response.status(), StatusCode::OK);
+
+	let request = protocol::rj_packet();
+	let response = service.fetch(&request);
+	assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+	let request = protocol::get_rj_user_settings();
+	let response = service.fetch_json::<_, rj::UserSettings>(&request);
+	assert_eq!(response.status(), StatusCode::OK);
+	assert_eq!(response.body().enable_by_default, Some(false));
+}
+
+#[test]
+fn rj_song_announcement_requires_auth() {
+	let mut service = ServiceType::new(&test_name!());
+	service.complete_initial_setup();
+	let request = protocol::rj_song_announcement(Song::test_only_from_path("root/song.mp3"));
+	let response = service.fetch(&request);
+	assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[test]
+fn rj_song_announcement_requires_rj() {
+	let mut service = ServiceType::new(&test_name!());
+	service.complete_initial_setup();
+	service.login();
+
+	// The song does not have to be in the collection.
+	let request = protocol::rj_song_announcement(Song::test_only_from_path("root/song.mp3"));
+	let response = service.fetch(&request);
+	assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn rj_preview_requires_admin() {
+	let mut service = ServiceType::new(&test_name!());
+	service.complete_initial_setup();
+	service.login();
+	let request = protocol::rj_preview(Song::test_only_from_path("root/song.mp3"));
+	let response = service.fetch(&request);
+	assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[test]
+fn rj_preview_golden_path() {
+	let mut service = ServiceType::new(&test_name!());
+	service.complete_initial_setup();
+	service.login_admin();
+
+	// Previews work before the tts server is configured.
+	let mut song = Song::test_only_from_path("root/song.mp3");
+	song.title = Some("Preview Title".to_owned());
+	let request = protocol::rj_preview(song);
+	let response = service.fetch_json::<_, dto::RjAnnouncementPreview>(&request);
+	assert_eq!(response.status(), StatusCode::OK);
+	assert!(response.body().text.contains("Preview Title"));
+	assert!(response
+		.body()
+		.missing_fields
+		.contains(&"artist".to_owned()));
+}
+
+#[test]
+fn reset_rj_settings_requires_admin() {
+	let mut service = ServiceType::new(&test_name!());
+	service.complete_initial_setup();
+	service.login();
+
+	let request = protocol::reset_rj_user_settings();
+	let response = service.fetch(&request);
+	assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+	let request = protocol::reset_rj_admin_settings();
+	let response = service.fetch(&request);
+	assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[test]
+fn reset_rj_user_settings_golden_path() {
+	let mut service = ServiceType::new(&test_name!());
+	service.complete_initial_setup();
+	service.login_admin();
+
+	let request = protocol::get_rj_user_settings();
+	let defaults = service
+		.fetch_json::<_, rj::UserSettings>(&request)
+		.into_body();
+	let settings = rj::UserSettings {
+		enable_by_default: Some(true),
+		tts_people: vec![rj::Person {
+			name: "host".to_owned(),
+			voice_model: "host-voice".to_owned(),
+			language: "en-US".to_owned(),
+			..Default::default()
+		}],
+		..defaults.clone()
+	};
+	let request = protocol::put_rj_user_settings(settings.clone());
+	let response = service.fetch(&request);
+	assert_eq!(response.status(), StatusCode::OK);
+
+	let request = protocol::reset_rj_user_settings();
+	let response = service.fetch_json::<_, rj::UserSettings>(&request);
+	assert_eq!(response.status(), StatusCode::OK);
+	assert_eq!(response.body().tts_people, settings.tts_people);
+
+	let request = protocol::get_rj_user_settings();
+	let response = service.fetch_json::<_, rj::UserSettings>(&request);
+	assert!(response.body().tts_people.is_empty());
+	assert_eq!(response.body().scripts, defaults.scripts);
+}
+
+#[test]
+fn reset_rj_admin_settings_golden_path() {
+	let mut service = ServiceType::new(&test_name!());
+	service.complete_initial_setup();
+	service.login_admin();
+
+	let settings = rj::AdminSettings {
+		tts_url: Some("http://tts.lan/api/tts".to_owned()),
+		tts_key: Some("text".to_owned()),
+		..Default::default()
+	};
+	let request = protocol::put_rj_admin_settings(settings);
+	let response = service.fetch(&request);
+	assert_eq!(response.status(), StatusCode::OK);
+
+	let request = protocol::reset_rj_admin_settings();
+	let response = service.fetch_json::<_, rj::AdminSettings>(&request);
+	assert_eq!(response.status(), StatusCode::OK);
+	assert_eq!(
+		response.body().tts_url.as_deref(),
+		Some("http://tts.lan/api/tts")
+	);
+
+	let request = protocol::get_rj_admin_settings();
+	let response = service.fetch_json::<_, rj::AdminSettings>(&request);
+	assert_eq!(response.body().tts_url, None);
+	assert_eq!(response.body().tts_key, None);
+}
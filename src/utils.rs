@@ -13,7 +13,7 @@ macro_rules! match_ignore_case {
 pub use crate::match_ignore_case;
 
 #[allow(clippy::upper_case_acronyms)]
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum AudioFormat {
 	AIFF,
 	APE,
@@ -26,6 +26,29 @@ pub enum AudioFormat {
 	WAVE,
 }
 
+impl AudioFormat {
+	pub fn mime_type(&self) -> &'static str {
+		match self {
+			AudioFormat::AIFF => "audio/aiff",
+			AudioFormat::APE => "audio/ape",
+			AudioFormat::FLAC => "audio/flac",
+			AudioFormat::MP3 => "audio/mpeg",
+			AudioFormat::MP4 => "audio/mp4",
+			AudioFormat::MPC => "audio/musepack",
+			AudioFormat::OGG => "audio/ogg",
+			AudioFormat::OPUS => "audio/opus",
+			AudioFormat::WAVE => "audio/wav",
+		}
+	}
+}
+
+/// Returns true if `path` looks like a song Polaris can index and stream.
+/// Only the file extension is inspected, the file itself is not opened.
+#[allow(dead_code)]
+pub fn is_supported_audio(path: &Path) -> bool {
+	get_audio_format(path).is_some()
+}
+
 pub fn get_audio_format(path: &Path) -> Option<AudioFormat> {
 	let extension = match path.extension() {
 		Some(e) => e,
@@ -50,6 +73,120 @@ pub fn get_audio_format(path: &Path) -> Option<AudioFormat> {
 	}
 }
 
+// Strips diacritics from latin letters, ex. "Björk" becomes "Bjork".
+// ASCII letters keep their case, folded letters are lowercased and others are left untouched.
+pub fn fold_accents(text: &str) -> String {
+	let mut folded = String::with_capacity(text.len());
+	for c in text.chars() {
+		if c.is_ascii() {
+			folded.push(c);
+			continue;
+		}
+		for c in c.to_lowercase() {
+			match c {
+				'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => folded.push('a'),
+				'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => folded.push('c'),
+				'ď' | 'đ' => folded.push('d'),
+				'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => folded.push('e'),
+				'ĝ' | 'ğ' | 'ġ' | 'ģ' => folded.push('g'),
+				'ĥ' | 'ħ' => folded.push('h'),
+				'ì' | 'í' | 'î' | 'ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' => folded.push('i'),
+				'ĵ' => folded.push('j'),
+				'ķ' => folded.push('k'),
+				'ĺ' | 'ļ' | 'ľ' | 'ŀ' | 'ł' => folded.push('l'),
+				'ñ' | 'ń' | 'ņ' | 'ň' => folded.push('n'),
+				'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => folded.push('o'),
+				'ŕ' | 'ŗ' | 'ř' => folded.push('r'),
+				'ś' | 'ŝ' | 'ş' | 'š' => folded.push('s'),
+				'ţ' | 'ť' | 'ŧ' => folded.push('t'),
+				'ù' | 'ú' | 'û' | 'ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => {
+					folded.push('u')
+				}
+				'ŵ' => folded.push('w'),
+				'ý' | 'ÿ' | 'ŷ' => folded.push('y'),
+				'ź' | 'ż' | 'ž' => folded.push('z'),
+				'ß' => folded.push_str("ss"),
+				'æ' => folded.push_str("ae"),
+				'œ' => folded.push_str("oe"),
+				c => folded.push(c),
+			}
+		}
+	}
+	folded
+}
+
+// Number of single character edits turning `a` into `b`.
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+	let mut previous: Vec<usize> = (0..=b.len()).collect();
+	let mut current = vec![0; b.len() + 1];
+	for (i, ca) in a.iter().enumerate() {
+		current[0] = i + 1;
+		for (j, cb) in b.iter().enumerate() {
+			let substitution = previous[j] + usize::from(ca != cb);
+			current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+		}
+		std::mem::swap(&mut previous, &mut current);
+	}
+	previous[b.len()]
+}
+
+// Scores how closely `text` matches `query`, from 0 for no match to 1 when `text` contains it.
+// Otherwise each word of the query is compared to the closest word of `text`, so that
+// "guitars" or "metalica" still score high against "Guitar" or "Metallica".
+pub fn fuzzy_score(text: &str, query: &str) -> f64 {
+	let text = text.to_lowercase();
+	let query = query.to_lowercase();
+	if text.contains(&query) {
+		return 1.0;
+	}
+	let words: Vec<Vec<char>> = text
+		.split(|c: char| !c.is_alphanumeric())
+		.filter(|w| !w.is_empty())
+		.map(|w| w.chars().collect())
+		.collect();
+	let query_words: Vec<Vec<char>> = query
+		.split(|c: char| !c.is_alphanumeric())
+		.filter(|w| !w.is_empty())
+		.map(|w| w.chars().collect())
+		.collect();
+	if words.is_empty() || query_words.is_empty() {
+		return 0.0;
+	}
+	let total: f64 = query_words
+		.iter()
+		.map(|query_word| {
+			words
+				.iter()
+				.map(|word| {
+					let distance = edit_distance(query_word, word) as f64;
+					1.0 - distance / query_word.len().max(word.len()) as f64
+				})
+				.fold(0.0, f64::max)
+		})
+		.sum();
+	total / query_words.len() as f64
+}
+
+#[test]
+fn can_fold_accents() {
+	assert_eq!(fold_accents("Björk"), "Bjork");
+	assert_eq!(fold_accents("MÖTLEY CRÜE"), "MoTLEY CRuE");
+	assert_eq!(
+		fold_accents("Sigur Rós - Ágætis byrjun"),
+		"Sigur Ros - agaetis byrjun"
+	);
+	assert_eq!(fold_accents("ピクニック (Picnic)"), "ピクニック (Picnic)");
+}
+
+#[test]
+fn can_score_fuzzy_matches() {
+	assert_eq!(fuzzy_score("Nothing Else Matters", "else"), 1.0);
+	assert!(fuzzy_score("Guitar Heroes", "guitars") > 0.8);
+	assert!(fuzzy_score("Metallica", "metalica") > 0.8);
+	assert!(fuzzy_score("Metallica", "khemmis") < 0.5);
+	assert_eq!(fuzzy_score("", "khemmis"), 0.0);
+}
+
 #[test]
 fn can_guess_audio_format() {
 	assert_eq!(get_audio_format(Path::new("animals/🐷/my🐖file.jpg")), None);
@@ -70,3 +207,27 @@ fn can_guess_audio_format() {
 		Some(AudioFormat::WAVE)
 	);
 }
+
+#[test]
+fn can_detect_supported_audio() {
+	let samples = [
+		("sample.aif", AudioFormat::AIFF),
+		("sample.ape", AudioFormat::APE),
+		("sample.flac", AudioFormat::FLAC),
+		("sample.m4a", AudioFormat::MP4),
+		("sample.mp3", AudioFormat::MP3),
+		("sample.ogg", AudioFormat::OGG),
+		("sample.opus", AudioFormat::OPUS),
+		("sample.wav", AudioFormat::WAVE),
+	];
+	for (file_name, format) in samples {
+		let path = Path::new("test-data/formats").join(file_name);
+		assert!(path.exists());
+		assert!(is_supported_audio(&path));
+		assert_eq!(get_audio_format(&path), Some(format));
+	}
+	assert!(!is_supported_audio(Path::new("test-data/config.toml")));
+	assert!(!is_supported_audio(Path::new(
+		"test-data/artwork/Folder.png"
+	)));
+}